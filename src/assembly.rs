@@ -0,0 +1,236 @@
+use bevy::prelude::*;
+
+use crate::interaction::Selectable;
+
+/// Component that is present on sub-assembly entities, which parent a
+/// named group of parts so they can be handled as one unit.
+#[derive(Debug, Clone)]
+pub struct SubAssembly {
+    pub name: String,
+    /// Set while the members of the sub-assembly are being edited, in
+    /// which case clicking a member selects it instead of the group.
+    pub expanded: bool,
+    /// The .mpa file the members were loaded from, if the sub-assembly is
+    /// linked to it. Linked sub-assemblies are reloaded from their source
+    /// whenever the assembly containing them is opened.
+    pub source: Option<String>,
+}
+
+impl Default for SubAssembly {
+    fn default() -> Self {
+        Self {
+            name: "Sub-assembly".to_string(),
+            expanded: false,
+            source: None,
+        }
+    }
+}
+
+/// Bundle to make it easy to construct sub-assembly entities.
+#[derive(Bundle, Debug, Default)]
+pub struct SubAssemblyBundle {
+    sub_assembly: SubAssembly,
+    selectable: Selectable,
+    pub transform: Transform,
+    pub global_transform: GlobalTransform,
+}
+
+/// Spawns an empty sub-assembly, returning its entity.
+pub(crate) fn spawn_sub_assembly(
+    commands: &mut Commands,
+    mut meshes: &mut ResMut<Assets<Mesh>>,
+    mut materials: &mut ResMut<Assets<StandardMaterial>>,
+    name: String,
+    source: Option<String>,
+    transform: Transform,
+) -> Entity {
+    let entity = commands
+        .spawn(SubAssemblyBundle {
+            sub_assembly: SubAssembly {
+                name,
+                source,
+                expanded: false,
+            },
+            transform,
+            ..SubAssemblyBundle::default()
+        })
+        .current_entity()
+        .unwrap();
+    commands.with_children(|parent| {
+        crate::gizmo::spawn_translate(parent, &mut meshes, &mut materials);
+        crate::gizmo::spawn_rotate(parent, &mut meshes, &mut materials);
+    });
+    entity
+}
+
+/// Gathers members into a new sub-assembly with the given transform. The
+/// transforms of the members and the sub-assembly are all relative to
+/// parent, which the sub-assembly is added to.
+pub(crate) fn assemble(
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<StandardMaterial>>,
+    name: String,
+    source: Option<String>,
+    transform: Transform,
+    parent: Option<Entity>,
+    members: &[(Entity, Transform)],
+) -> Entity {
+    let group = spawn_sub_assembly(commands, meshes, materials, name, source, transform);
+    if let Some(parent) = parent {
+        commands.push_children(parent, &[group]);
+    }
+
+    for (entity, t) in members {
+        commands.insert_one(*entity, to_local(&transform, t));
+    }
+    let entities: Vec<Entity> = members.iter().map(|(e, _)| *e).collect();
+    commands.push_children(group, &entities);
+    group
+}
+
+/// Dissolves a sub-assembly, moving its members to parent without
+/// changing where they are in the world. gizmos are the gizmo entities
+/// of the sub-assembly, which are despawned along with it.
+pub(crate) fn dissolve(
+    commands: &mut Commands,
+    group: Entity,
+    transform: Transform,
+    parent: Option<Entity>,
+    members: &[(Entity, Transform)],
+    gizmos: impl Iterator<Item = Entity>,
+) {
+    for (entity, t) in members {
+        commands.insert_one(*entity, to_world(&transform, t));
+        match parent {
+            Some(parent) => {
+                commands.push_children(parent, &[*entity]);
+            }
+            None => {
+                commands.remove_one::<Parent>(*entity);
+            }
+        }
+    }
+
+    // The members are still listed as children of the sub-assembly at
+    // this point, so it cannot be despawned recursively.
+    for gizmo in gizmos {
+        commands.despawn_recursive(gizmo);
+    }
+    commands.despawn(group);
+}
+
+/// Returns the entity which should be selected when the given part is
+/// clicked: the outermost sub-assembly it is a member of which is not
+/// being edited, or the part itself.
+pub fn selection_root(
+    entity: Entity,
+    parents: &Query<&Parent>,
+    groups: &Query<&SubAssembly>,
+) -> Entity {
+    let mut root = entity;
+    let mut current = entity;
+    while let Ok(parent) = parents.get(current) {
+        match groups.get(parent.0) {
+            Ok(group) => {
+                if !group.expanded {
+                    root = parent.0;
+                }
+                current = parent.0;
+            }
+            Err(_) => break,
+        }
+    }
+    root
+}
+
+/// Returns the world-space transform of the sub-assembly an entity is a
+/// member of, or the identity if it is not a member of one.
+pub fn parent_frame(
+    entity: Entity,
+    parents: &Query<&Parent>,
+    globals: &Query<&GlobalTransform>,
+) -> Transform {
+    match parents.get(entity).ok().and_then(|p| globals.get(p.0).ok()) {
+        Some(g) => Transform {
+            translation: g.translation,
+            rotation: g.rotation,
+            scale: g.scale,
+        },
+        None => Transform::identity(),
+    }
+}
+
+/// Converts a transform relative to frame into the space frame is in.
+pub fn to_world(frame: &Transform, local: &Transform) -> Transform {
+    Transform {
+        translation: frame.translation + frame.rotation * (frame.scale * local.translation),
+        rotation: (frame.rotation * local.rotation).normalize(),
+        scale: frame.scale * local.scale,
+    }
+}
+
+/// Converts a transform into one relative to frame.
+pub fn to_local(frame: &Transform, world: &Transform) -> Transform {
+    let inverse = frame.rotation.conjugate();
+    Transform {
+        translation: inverse * (world.translation - frame.translation) / frame.scale,
+        rotation: (inverse * world.rotation).normalize(),
+        scale: world.scale / frame.scale,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::transform_changed;
+
+    fn frame() -> Transform {
+        Transform {
+            translation: Vec3::new(10., -4., 2.),
+            rotation: Quat::from_rotation_z(std::f32::consts::FRAC_PI_2),
+            scale: Vec3::one(),
+        }
+    }
+
+    fn part() -> Transform {
+        Transform {
+            translation: Vec3::new(1., 2., 3.),
+            rotation: Quat::from_axis_angle(Vec3::new(1., 1., 0.).normalize(), 0.7),
+            scale: Vec3::one(),
+        }
+    }
+
+    #[test]
+    fn to_world_applies_the_frame() {
+        let local = Transform::from_translation(Vec3::new(1., 0., 0.));
+        let world = to_world(&frame(), &local);
+        assert!((world.translation - Vec3::new(10., -3., 2.)).length() < 1e-5);
+        assert!(!transform_changed(
+            &Transform::from_rotation(world.rotation),
+            &Transform::from_rotation(frame().rotation)
+        ));
+    }
+
+    #[test]
+    fn to_local_undoes_to_world() {
+        let round_trip = to_local(&frame(), &to_world(&frame(), &part()));
+        assert!(!transform_changed(&round_trip, &part()));
+    }
+
+    #[test]
+    fn to_world_undoes_to_local() {
+        let round_trip = to_world(&frame(), &to_local(&frame(), &part()));
+        assert!(!transform_changed(&round_trip, &part()));
+    }
+
+    #[test]
+    fn scaled_frames_round_trip() {
+        let frame = Transform {
+            scale: Vec3::splat(2.),
+            ..frame()
+        };
+        let round_trip = to_local(&frame, &to_world(&frame, &part()));
+        assert!(!transform_changed(&round_trip, &part()));
+    }
+}
@@ -0,0 +1,331 @@
+use serde::Serialize;
+
+use crate::parts::{Screw, Support, SupportKind};
+use crate::storage::ObjectRep;
+
+/// The formats a bill of materials can be written in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BomFormat {
+    Csv,
+    Json,
+    Markdown,
+}
+
+impl BomFormat {
+    /// Picks the format from the extension of a file name, defaulting to
+    /// CSV.
+    pub fn from_path(path: &std::path::Path) -> Self {
+        match path.extension().and_then(std::ffi::OsStr::to_str) {
+            Some("json") => BomFormat::Json,
+            Some("md") => BomFormat::Markdown,
+            _ => BomFormat::Csv,
+        }
+    }
+}
+
+impl std::str::FromStr for BomFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(BomFormat::Csv),
+            "json" => Ok(BomFormat::Json),
+            "md" | "markdown" => Ok(BomFormat::Markdown),
+            _ => Err(format!("unknown format {:?}, expected csv, json or md", s)),
+        }
+    }
+}
+
+/// A line in a bill of materials, counting identical parts.
+#[derive(Serialize, Debug, Clone)]
+pub struct BomLine {
+    pub item: &'static str,
+    pub spec: String,
+    pub quantity: usize,
+
+    /// Identifies identical parts, and orders the lines.
+    #[serde(skip)]
+    key: (u8, String, usize),
+}
+
+/// A bill of materials, listing the parts which make up an assembly.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Bom {
+    pub lines: Vec<BomLine>,
+}
+
+impl Bom {
+    /// Counts the parts which make up the given objects, including the
+    /// members of sub-assemblies.
+    pub(crate) fn from_objects(objects: &[ObjectRep]) -> Self {
+        let mut bom = Bom::default();
+        bom.add_objects(objects);
+        bom.lines.sort_by(|a, b| a.key.cmp(&b.key));
+        bom
+    }
+
+    fn add_objects(&mut self, objects: &[ObjectRep]) {
+        for obj in objects {
+            match obj {
+                // Panels are counted together if they have the same name
+                // and spec, and are made the same way.
+                ObjectRep::Panel {
+                    path,
+                    spec,
+                    material,
+                    thickness,
+                    ..
+                } => {
+                    let name = format!(
+                        "{} ({}, {}mm)",
+                        path.split("/").last().unwrap(),
+                        material.name(),
+                        thickness
+                    );
+                    let key = (0, format!("{}\n{}", name, spec), 0);
+                    self.add("PCB", name, key)
+                }
+                // Screws are counted together if they have the same size,
+                // head and drive.
+                ObjectRep::Screw {
+                    size,
+                    head,
+                    drive,
+                    length,
+                    ..
+                } => {
+                    let screw = Screw {
+                        size: *size,
+                        head: *head,
+                        drive: *drive,
+                    };
+                    self.add(
+                        "Screw",
+                        format!("{}x{} {}", screw, length, screw.style()),
+                        (1, format!("{} {}", screw, screw.style()), *length),
+                    )
+                }
+                ObjectRep::Washer { washer, .. } => self.add(
+                    "Washer",
+                    format!("{}", washer),
+                    (2, format!("{}", washer), 0),
+                ),
+                ObjectRep::Nut { nut, .. } => {
+                    self.add("Nut", format!("{}", nut), (3, format!("{}", nut), 0))
+                }
+                ObjectRep::Standoff {
+                    size,
+                    length,
+                    gender,
+                    material,
+                    ..
+                } => {
+                    let spec = format!("{} {}, {}", size, gender.name(), material.name());
+                    self.add(
+                        "Standoff",
+                        format!("{}x{} {}, {}", size, length, gender.name(), material.name()),
+                        (4, spec, *length),
+                    )
+                }
+                ObjectRep::Insert { insert, .. } => self.add(
+                    "Heat-set insert",
+                    format!("{}", insert),
+                    (5, format!("{}", insert), 0),
+                ),
+                ObjectRep::Support {
+                    size, length, kind, ..
+                } => {
+                    let support = Support {
+                        size: *size,
+                        length: *length,
+                        kind: *kind,
+                    };
+                    let item = match kind {
+                        SupportKind::Rivet => "Rivet",
+                        SupportKind::PcbSupport => "PCB support",
+                    };
+                    self.add(
+                        item,
+                        format!("{}", support),
+                        (6, format!("{} {}", kind.name(), size), *length),
+                    )
+                }
+                ObjectRep::Group { objects, .. } => self.add_objects(objects),
+                ObjectRep::None => {}
+            }
+        }
+    }
+
+    fn add(&mut self, item: &'static str, spec: String, key: (u8, String, usize)) {
+        match self.lines.iter_mut().find(|l| l.key == key) {
+            Some(line) => line.quantity += 1,
+            None => self.lines.push(BomLine {
+                item,
+                spec,
+                quantity: 1,
+                key,
+            }),
+        }
+    }
+
+    pub fn render(&self, format: BomFormat) -> String {
+        match format {
+            BomFormat::Csv => self.to_csv(),
+            BomFormat::Json => serde_json::to_string_pretty(self).unwrap(),
+            BomFormat::Markdown => self.to_markdown(),
+        }
+    }
+
+    fn to_csv(&self) -> String {
+        let escape = |s: &str| {
+            if s.contains(|c| c == ',' || c == '"' || c == '\n') {
+                format!("\"{}\"", s.replace('"', "\"\""))
+            } else {
+                s.to_string()
+            }
+        };
+
+        let mut out = "item,spec,quantity\n".to_string();
+        for line in &self.lines {
+            out.push_str(&format!(
+                "{},{},{}\n",
+                line.item,
+                escape(&line.spec),
+                line.quantity
+            ));
+        }
+        out
+    }
+
+    fn to_markdown(&self) -> String {
+        let mut out = "| Item | Spec | Quantity |\n| --- | --- | ---: |\n".to_string();
+        for line in &self.lines {
+            out.push_str(&format!(
+                "| {} | {} | {} |\n",
+                line.item,
+                line.spec.replace('|', "\\|"),
+                line.quantity
+            ));
+        }
+        out
+    }
+}
+
+/// Why the bill of materials for a file could not be produced.
+#[derive(Debug)]
+pub enum LoadError {
+    /// The file could not be read.
+    Read(std::io::Error),
+    /// The file was read, but is not a valid assembly.
+    Invalid(String),
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LoadError::Read(e) => write!(f, "failed reading file: {}", e),
+            LoadError::Invalid(e) => e.fmt(f),
+        }
+    }
+}
+
+/// Reads the bill of materials for an .mpa file, including any linked
+/// sub-assemblies. Linked sub-assemblies which could not be loaded are
+/// counted as they were saved, and described alongside the result.
+pub fn load(path: &std::path::Path) -> Result<(Bom, Vec<String>), LoadError> {
+    let contents = std::fs::read(path).map_err(LoadError::Read)?;
+    let result = crate::storage::decode_scene(&contents)
+        .map_err(|e| LoadError::Invalid(format!("failed decoding assembly: {}", e)))?;
+    if !result.errors.is_empty() {
+        return Err(LoadError::Invalid(format!(
+            "{} objects could not be loaded, so the bill of materials would be incomplete",
+            result.errors.len()
+        )));
+    }
+
+    let mut objects = result.document.objects;
+    let problems = crate::storage::resolve_links(&mut objects);
+    Ok((Bom::from_objects(&objects), problems))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn objects(objs: serde_json::Value) -> Vec<ObjectRep> {
+        serde_json::from_value(objs).unwrap()
+    }
+
+    fn pos() -> serde_json::Value {
+        json!({"x": 0.0, "y": 0.0, "z": 0.0, "quat": [0.0, 0.0, 0.0, 1.0]})
+    }
+
+    fn line(item: &'static str, spec: &str, quantity: usize) -> BomLine {
+        BomLine {
+            item,
+            spec: spec.to_string(),
+            quantity,
+            key: (0, spec.to_string(), 0),
+        }
+    }
+
+    #[test]
+    fn groups_and_sorts_parts() {
+        let bom = Bom::from_objects(&objects(json!([
+            {"kind": "nut", "pos": pos(), "nut": "M3"},
+            {"kind": "screw", "pos": pos(), "screw": "M3", "head": "hex", "length": 10},
+            {"kind": "washer", "pos": pos(), "washer": "M3"},
+            {"kind": "screw", "pos": pos(), "screw": "M3", "head": "hex", "length": 8},
+            {"kind": "screw", "pos": pos(), "screw": "M3", "head": "hex", "length": 10},
+            {"kind": "group", "pos": pos(), "name": "bracket", "objects": [
+                {"kind": "nut", "pos": pos(), "nut": "M3"},
+                {"kind": "screw", "pos": pos(), "screw": "M3", "head": "hex", "length": 10},
+            ]},
+        ])));
+
+        let lines: Vec<_> = bom
+            .lines
+            .iter()
+            .map(|l| (l.item, l.spec.as_str(), l.quantity))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                ("Screw", "M3x8 hex head", 1),
+                ("Screw", "M3x10 hex head", 3),
+                ("Washer", "M3", 1),
+                ("Nut", "M3", 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn screws_differing_in_head_are_separate_lines() {
+        let bom = Bom::from_objects(&objects(json!([
+            {"kind": "screw", "pos": pos(), "screw": "M3", "head": "hex", "length": 10},
+            {"kind": "screw", "pos": pos(), "screw": "M3", "head": "pan", "length": 10},
+        ])));
+        assert_eq!(bom.lines.len(), 2);
+    }
+
+    #[test]
+    fn csv_quotes_special_characters() {
+        let bom = Bom {
+            lines: vec![
+                line("PCB", "plain", 1),
+                line("PCB", "front, back", 2),
+                line("PCB", "the \"best\" panel", 3),
+                line("PCB", "two\nlines", 4),
+            ],
+        };
+        assert_eq!(
+            bom.to_csv(),
+            "item,spec,quantity\n\
+             PCB,plain,1\n\
+             PCB,\"front, back\",2\n\
+             PCB,\"the \"\"best\"\" panel\",3\n\
+             PCB,\"two\nlines\",4\n"
+        );
+    }
+}
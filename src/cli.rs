@@ -0,0 +1,311 @@
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+use crate::bom::BomFormat;
+use crate::parts::PanelInfo;
+use crate::storage::{self, Document, ObjectRep};
+
+/// Commands which run without opening a window. Each exits with a status
+/// of 0 on success, 1 if a file was invalid, 2 if it could not be read, or
+/// 3 if the output could not be written.
+#[derive(Debug, StructOpt, Clone)]
+pub enum Command {
+    /// Checks that .spec and .mpa files load cleanly.
+    Validate {
+        #[structopt(parse(from_os_str), required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Prints the bill of materials for an assembly.
+    Bom {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+        /// Output format: csv, json or md.
+        #[structopt(long, default_value = "csv")]
+        format: BomFormat,
+    },
+    /// Writes a self-contained copy of an assembly in the current format,
+    /// with linked sub-assemblies embedded. If the output is a .stl, .obj,
+    /// .gltf or .glb file, the geometry of the assembly is written instead.
+    Export {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+        #[structopt(short, long, parse(from_os_str))]
+        output: PathBuf,
+    },
+    /// Describes the contents of .spec and .mpa files.
+    Info {
+        #[structopt(parse(from_os_str), required = true)]
+        files: Vec<PathBuf>,
+    },
+}
+
+const EXIT_INVALID: i32 = 1;
+const EXIT_UNREADABLE: i32 = 2;
+const EXIT_UNWRITABLE: i32 = 3;
+
+/// Runs the command, returning the status to exit with.
+pub fn run(cmd: Command) -> i32 {
+    match cmd {
+        Command::Validate { files } => files.iter().map(|f| validate(f)).max().unwrap_or(0),
+        Command::Info { files } => files.iter().map(|f| info(f)).max().unwrap_or(0),
+        Command::Bom { file, format } => match crate::bom::load(&file) {
+            Ok((bom, problems)) => {
+                for problem in problems {
+                    eprintln!("{}: warning: {}", file.display(), problem);
+                }
+                print!("{}", bom.render(format));
+                0
+            }
+            Err(e) => {
+                eprintln!("{}: {}", file.display(), e);
+                match e {
+                    crate::bom::LoadError::Read(_) => EXIT_UNREADABLE,
+                    crate::bom::LoadError::Invalid(_) => EXIT_INVALID,
+                }
+            }
+        },
+        Command::Export { file, output } => export(&file, &output),
+    }
+}
+
+fn is_spec(path: &Path) -> bool {
+    path.extension() == Some(std::ffi::OsStr::new("spec"))
+}
+
+fn read_spec(path: &Path) -> Result<PanelInfo, i32> {
+    match std::fs::read(path) {
+        Ok(contents) => Ok(PanelInfo::new(
+            path.to_string_lossy().to_string(),
+            String::from_utf8_lossy(&contents).to_string(),
+        )),
+        Err(e) => {
+            eprintln!("{}: failed reading file: {}", path.display(), e);
+            Err(EXIT_UNREADABLE)
+        }
+    }
+}
+
+fn read_assembly(path: &Path) -> Result<storage::LoadResult, i32> {
+    let contents = std::fs::read(path).map_err(|e| {
+        eprintln!("{}: failed reading file: {}", path.display(), e);
+        EXIT_UNREADABLE
+    })?;
+    storage::decode_scene(&contents).map_err(|e| {
+        eprintln!("{}: failed decoding assembly: {}", path.display(), e);
+        EXIT_INVALID
+    })
+}
+
+// Checks a panel spec can be built the same way it would be to spawn it,
+// returning a description of the problem if not.
+fn check_panel(panel: &PanelInfo) -> Result<(), String> {
+    if let Some(err) = panel.error() {
+        return Err(match err.location {
+            Some((line, column)) => format!(
+                "invalid spec at line {}, column {}: {}",
+                line, column, err.message
+            ),
+            None => format!("invalid spec: {}", err.message),
+        });
+    }
+    panel
+        .panel()
+        .tessellate_3d()
+        .map(|_| ())
+        .map_err(|e| format!("failed tessellating panel: {:?}", e))
+}
+
+// Checks the panels and linked sub-assemblies within objects, returning
+// descriptions of any problems.
+fn check_objects(objects: &[ObjectRep], problems: &mut Vec<String>) {
+    for obj in objects {
+        match obj {
+            ObjectRep::Panel { path, spec, .. } => {
+                if let Err(e) = check_panel(&PanelInfo::new(path.clone(), spec.clone())) {
+                    problems.push(format!("panel {}: {}", obj.name(), e));
+                }
+            }
+            ObjectRep::Group {
+                source: Some(source),
+                objects,
+                ..
+            } => {
+                let mut linked = vec![];
+                if let Err(e) = storage::load_linked(source, &mut linked) {
+                    problems.push(format!("sub-assembly linked to {}: {}", source, e));
+                }
+                problems.extend(linked);
+                check_objects(objects, problems);
+            }
+            ObjectRep::Group { objects, .. } => check_objects(objects, problems),
+            _ => {}
+        }
+    }
+}
+
+fn validate(path: &Path) -> i32 {
+    let problems = if is_spec(path) {
+        let panel = match read_spec(path) {
+            Ok(panel) => panel,
+            Err(status) => return status,
+        };
+        check_panel(&panel).err().into_iter().collect()
+    } else {
+        let result = match read_assembly(path) {
+            Ok(result) => result,
+            Err(status) => return status,
+        };
+        let mut problems: Vec<String> = result
+            .errors
+            .iter()
+            .map(|e| match &e.field {
+                Some(field) => format!("{}: field {}: {}", e.path, field, e.reason),
+                None => format!("{}: {}", e.path, e.reason),
+            })
+            .collect();
+        check_objects(&result.document.objects, &mut problems);
+        problems
+    };
+
+    if problems.is_empty() {
+        println!("{}: ok", path.display());
+        0
+    } else {
+        for problem in problems {
+            eprintln!("{}: {}", path.display(), problem);
+        }
+        EXIT_INVALID
+    }
+}
+
+fn info(path: &Path) -> i32 {
+    if is_spec(path) {
+        let panel = match read_spec(path) {
+            Ok(panel) => panel,
+            Err(status) => return status,
+        };
+        println!("{}: panel spec", path.display());
+        match check_panel(&panel) {
+            Ok(()) => println!("  well formed"),
+            Err(e) => println!("  {}", e),
+        }
+        return 0;
+    }
+
+    let result = match read_assembly(path) {
+        Ok(result) => result,
+        Err(status) => return status,
+    };
+    let doc = &result.document;
+    println!("{}: assembly", path.display());
+    println!(
+        "  format version {}{}, units {}, written by {}",
+        doc.header.format_version,
+        if result.upgraded { " (upgraded)" } else { "" },
+        doc.header.units,
+        doc.header.app_version
+    );
+
+    let (mut groups, mut links, mut constraints) = (0, 0, doc.constraints.len());
+    let mut pending: Vec<&ObjectRep> = doc.objects.iter().collect();
+    while let Some(obj) = pending.pop() {
+        if let ObjectRep::Group {
+            source,
+            objects,
+            constraints: c,
+            ..
+        } = obj
+        {
+            groups += 1;
+            if source.is_some() {
+                links += 1;
+            }
+            constraints += c.len();
+            pending.extend(objects.iter());
+        }
+    }
+    println!(
+        "  {} objects, {} sub-assemblies ({} linked), {} constraints",
+        doc.objects.len(),
+        groups,
+        links,
+        constraints
+    );
+    if !result.errors.is_empty() {
+        println!("  {} objects could not be loaded", result.errors.len());
+    }
+
+    let bom = crate::bom::Bom::from_objects(&doc.objects);
+    for line in bom.lines {
+        println!("  {:>4} x {} {}", line.quantity, line.item, line.spec);
+    }
+    0
+}
+
+fn export(path: &Path, output: &Path) -> i32 {
+    let assembly = output.extension() == Some(std::ffi::OsStr::new("mpa"));
+    if !assembly && crate::export::MeshFormat::from_extension(output).is_none() {
+        eprintln!(
+            "{}: unknown output format, expected .mpa, .stl, .obj, .gltf or .glb",
+            output.display()
+        );
+        return EXIT_INVALID;
+    }
+
+    let result = match read_assembly(path) {
+        Ok(result) => result,
+        Err(status) => return status,
+    };
+    if !result.errors.is_empty() {
+        eprintln!(
+            "{}: {} objects could not be loaded, run validate for details",
+            path.display(),
+            result.errors.len()
+        );
+        return EXIT_INVALID;
+    }
+
+    let mut doc: Document = result.document;
+    for problem in storage::resolve_links(&mut doc.objects) {
+        eprintln!("{}: warning: {}", path.display(), problem);
+    }
+    if !assembly {
+        return export_mesh(path, &doc.objects, output);
+    }
+    storage::unlink(&mut doc.objects);
+
+    let f = match std::fs::File::create(output) {
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!("{}: failed creating file: {}", output.display(), e);
+            return EXIT_UNWRITABLE;
+        }
+    };
+    match serde_json::to_writer_pretty(f, &doc) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}: failed writing assembly: {}", output.display(), e);
+            EXIT_UNWRITABLE
+        }
+    }
+}
+
+// Writes the geometry of the objects in the format given by the extension
+// of output.
+fn export_mesh(path: &Path, objects: &[ObjectRep], output: &Path) -> i32 {
+    let mesh = match crate::export::MeshExport::from_objects(objects) {
+        Ok(mesh) => mesh,
+        Err(e) => {
+            eprintln!("{}: {}", path.display(), e);
+            return EXIT_INVALID;
+        }
+    };
+    let written = std::fs::File::create(output).and_then(|mut f| mesh.write(&mut f, output));
+    match written {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}: failed writing mesh: {}", output.display(), e);
+            EXIT_UNWRITABLE
+        }
+    }
+}
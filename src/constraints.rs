@@ -0,0 +1,517 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use crate::assembly::{to_local, to_world};
+use crate::history::{Change, History};
+use crate::interaction::Selectable;
+use crate::parts::PanelHoles;
+
+pub struct Plugin;
+
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_event::<ConstraintEvent>()
+            .add_system(editor.system())
+            .add_system(solver.system());
+    }
+}
+
+/// Maximum number of times constraints are re-solved in a frame, which
+/// bounds how far a move propagates along a chain of constrained parts.
+const MAX_PASSES: usize = 8;
+
+/// Describes how a part is held relative to the part it is constrained to.
+/// Points and directions are in the coordinates of the target part.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ConstraintKind {
+    /// The Z axis of the part lies along the axis through point, such as a
+    /// screw in a drill hole.
+    Coaxial { point: [f32; 3], axis: [f32; 3] },
+    /// The origin of the part lies on the plane through point, with its Z
+    /// axis along the normal, such as a washer resting on a panel.
+    FaceContact { point: [f32; 3], normal: [f32; 3] },
+    /// The part is parallel to the target, offset along its Z axis by the
+    /// given distance in mm.
+    Offset { distance: f32 },
+}
+
+impl ConstraintKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConstraintKind::Coaxial { .. } => "Coaxial",
+            ConstraintKind::FaceContact { .. } => "Face contact",
+            ConstraintKind::Offset { .. } => "Offset",
+        }
+    }
+
+    /// Returns the transform closest to local which satisfies the
+    /// constraint, where both are relative to the target.
+    fn project(&self, local: &Transform) -> Transform {
+        let mut out = *local;
+        match self {
+            ConstraintKind::Coaxial { point, axis } => {
+                let (point, axis) = (Vec3::from(*point), Vec3::from(*axis).normalize());
+                out.rotation = align(local.rotation, axis);
+                out.translation = point + axis * (local.translation - point).dot(axis);
+            }
+            ConstraintKind::FaceContact { point, normal } => {
+                let (point, normal) = (Vec3::from(*point), Vec3::from(*normal).normalize());
+                out.rotation = align(local.rotation, normal);
+                out.translation =
+                    local.translation - normal * (local.translation - point).dot(normal);
+            }
+            ConstraintKind::Offset { distance } => {
+                out.rotation = align(local.rotation, Vec3::unit_z());
+                out.translation.z = *distance;
+            }
+        }
+        out
+    }
+}
+
+/// Rotates the given rotation by the smallest amount which brings its Z
+/// axis along axis, in whichever direction it is nearest to.
+fn align(rotation: Quat, axis: Vec3) -> Quat {
+    let z = rotation * Vec3::unit_z();
+    let axis = if z.dot(axis) < 0. { -axis } else { axis };
+    let cross = z.cross(axis);
+    if cross.length() < 1e-6 {
+        return rotation;
+    }
+    let angle = z.dot(axis).max(-1.).min(1.).acos();
+    (Quat::from_axis_angle(cross.normalize(), angle) * rotation).normalize()
+}
+
+/// A constraint holding a part relative to target.
+#[derive(Debug, Clone)]
+pub struct Constraint {
+    pub target: Entity,
+    pub kind: ConstraintKind,
+    /// Where the part was relative to the target when last solved, so it
+    /// can follow the target as it moves.
+    relative: Option<Transform>,
+}
+
+impl Constraint {
+    pub fn new(target: Entity, kind: ConstraintKind) -> Self {
+        Self {
+            target,
+            kind,
+            relative: None,
+        }
+    }
+}
+
+/// Component present on parts which are constrained to other parts.
+#[derive(Debug, Clone, Default)]
+pub struct Constraints(pub Vec<Constraint>);
+
+/// Describes a constraint in an .mpa file. Parts are identified by their
+/// position in a depth-first walk of the objects the constraint is saved
+/// alongside, where sub-assemblies come before their members.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct ConstraintRep {
+    pub part: usize,
+    pub target: usize,
+    #[serde(flatten)]
+    pub kind: ConstraintKind,
+}
+
+/// Resolves constraints saved alongside objects, given the entities the
+/// objects were spawned as in depth-first order. Fails if a constraint
+/// refers to a part which is not among them.
+pub(crate) fn resolve(
+    reps: &[ConstraintRep],
+    order: &[Entity],
+) -> Result<Vec<(Entity, Constraint)>, String> {
+    reps.iter()
+        .map(|rep| match (order.get(rep.part), order.get(rep.target)) {
+            (Some(part), Some(target)) => Ok((*part, Constraint::new(*target, rep.kind.clone()))),
+            _ => Err(format!(
+                "{} constraint between parts {} and {}, but there are only {} parts",
+                rep.kind.name(),
+                rep.part,
+                rep.target,
+                order.len()
+            )),
+        })
+        .collect()
+}
+
+/// Adds resolved constraints to the parts they apply to, alongside any
+/// constraints the parts already have.
+pub(crate) fn insert(commands: &mut Commands, constraints: Vec<(Entity, Constraint)>) {
+    if !constraints.is_empty() {
+        commands.add_command(InsertConstraints(constraints));
+    }
+}
+
+// Command which appends constraints to parts, since a part spawned earlier
+// in the frame may have been given constraints which are not visible to
+// queries yet.
+struct InsertConstraints(Vec<(Entity, Constraint)>);
+
+impl bevy::ecs::Command for InsertConstraints {
+    fn write(self: Box<Self>, world: &mut World, _resources: &mut Resources) {
+        for (part, constraint) in self.0 {
+            if let Ok(mut constraints) = world.get_mut::<Constraints>(part) {
+                constraints.0.push(constraint);
+                continue;
+            }
+            // Parts which were despawned in the meantime are skipped.
+            let _ = world.insert_one(part, Constraints(vec![constraint]));
+        }
+    }
+}
+
+/// The kinds of constraint which can be created from the inspector.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConstraintType {
+    Coaxial,
+    FaceContact,
+    Offset,
+}
+
+#[derive(Debug)]
+pub enum ConstraintEvent {
+    /// Constrains the first part to the second, based on where they are
+    /// now.
+    Add(Entity, Entity, ConstraintType),
+    Remove(Entity, usize),
+    /// Changes the distance of an offset constraint.
+    SetOffset(Entity, usize, f32),
+}
+
+/// Returns the world transform of a part, using the transforms of the
+/// sub-assemblies it is a member of. Unlike GlobalTransform, this is
+/// current for parts which were moved or spawned earlier in the frame.
+fn world_transform(
+    entity: Entity,
+    transforms: &Query<&Transform, With<Selectable>>,
+    parents: &Query<&Parent>,
+) -> Option<Transform> {
+    let mut out = *transforms.get(entity).ok()?;
+    let mut current = entity;
+    while let Ok(parent) = parents.get(current) {
+        match transforms.get(parent.0) {
+            Ok(frame) => out = to_world(frame, &out),
+            Err(_) => break,
+        }
+        current = parent.0;
+    }
+    Some(out)
+}
+
+/// Returns true if the part or a sub-assembly it is a member of has moved.
+fn was_moved(entity: Entity, moved: &HashSet<Entity>, parents: &Query<&Parent>) -> bool {
+    let mut current = entity;
+    loop {
+        if moved.contains(&current) {
+            return true;
+        }
+        match parents.get(current) {
+            Ok(parent) => current = parent.0,
+            Err(_) => return false,
+        }
+    }
+}
+
+/// Returns true if part is constrained to target, directly or through
+/// other parts.
+fn depends_on(part: Entity, target: Entity, constrained: &mut Query<&mut Constraints>) -> bool {
+    let mut pending = vec![part];
+    let mut seen = HashSet::new();
+    while let Some(entity) = pending.pop() {
+        if entity == target {
+            return true;
+        }
+        if seen.insert(entity) {
+            if let Ok(constraints) = constrained.get_mut(entity) {
+                pending.extend(constraints.0.iter().map(|c| c.target));
+            }
+        }
+    }
+    false
+}
+
+fn editor(
+    ev_constraint: Res<Events<ConstraintEvent>>,
+    mut constraint_reader: Local<EventReader<ConstraintEvent>>,
+    mut history: ResMut<History>,
+    commands: &mut Commands,
+    mut constrained: Query<&mut Constraints>,
+    transforms: Query<&Transform, With<Selectable>>,
+    holes: Query<&PanelHoles>,
+    parents: Query<&Parent>,
+) {
+    for ev in constraint_reader.iter(&ev_constraint) {
+        match ev {
+            ConstraintEvent::Add(part, target, ty) => {
+                if part == target || depends_on(*target, *part, &mut constrained) {
+                    eprintln!("Cannot constrain a part to a part which depends on it");
+                    continue;
+                }
+                let local = match (
+                    world_transform(*part, &transforms, &parents),
+                    world_transform(*target, &transforms, &parents),
+                ) {
+                    (Some(part), Some(target)) => to_local(&target, &part),
+                    _ => continue,
+                };
+                let holes = holes.get(*target).ok();
+
+                let kind = match ty {
+                    // Panels are constrained through their nearest hole,
+                    // other parts through their own axis.
+                    ConstraintType::Coaxial => {
+                        let nearest = holes.and_then(|h| {
+                            h.holes.iter().min_by(|a, b| {
+                                let xy = Vec2::new(local.translation.x, local.translation.y);
+                                (a.0 - xy)
+                                    .length()
+                                    .partial_cmp(&(b.0 - xy).length())
+                                    .unwrap()
+                            })
+                        });
+                        ConstraintKind::Coaxial {
+                            point: match nearest {
+                                Some((center, _)) => [center.x, center.y, 0.],
+                                None => [0., 0., 0.],
+                            },
+                            axis: [0., 0., 1.],
+                        }
+                    }
+                    // Parts rest on whichever surface of a panel they
+                    // are nearest to.
+                    ConstraintType::FaceContact => {
+                        let surface = match holes {
+                            Some(h) if local.translation.z >= (h.top + h.bottom) / 2. => h.top,
+                            Some(h) => h.bottom,
+                            None => 0.,
+                        };
+                        ConstraintKind::FaceContact {
+                            point: [0., 0., surface],
+                            normal: [0., 0., 1.],
+                        }
+                    }
+                    ConstraintType::Offset => ConstraintKind::Offset {
+                        distance: local.translation.z,
+                    },
+                };
+
+                let constraint = Constraint::new(*target, kind);
+                let from = match constrained.get_mut(*part) {
+                    Ok(mut constraints) => {
+                        let from = constraints.0.clone();
+                        constraints.0.push(constraint.clone());
+                        from
+                    }
+                    Err(_) => {
+                        commands.insert_one(*part, Constraints(vec![constraint.clone()]));
+                        vec![]
+                    }
+                };
+                let mut to = from.clone();
+                to.push(constraint);
+                history.record(Change::Constrain {
+                    entity: *part,
+                    from,
+                    to,
+                });
+            }
+            ConstraintEvent::Remove(part, index) => {
+                if let Ok(mut constraints) = constrained.get_mut(*part) {
+                    if *index >= constraints.0.len() {
+                        continue;
+                    }
+                    let from = constraints.0.clone();
+                    constraints.0.remove(*index);
+                    if constraints.0.is_empty() {
+                        commands.remove_one::<Constraints>(*part);
+                    }
+                    history.record(Change::Constrain {
+                        entity: *part,
+                        from,
+                        to: constraints.0.clone(),
+                    });
+                }
+            }
+            ConstraintEvent::SetOffset(part, index, value) => {
+                if let Ok(mut constraints) = constrained.get_mut(*part) {
+                    if let Some(c) = constraints.0.get_mut(*index) {
+                        if let ConstraintKind::Offset { distance } = &mut c.kind {
+                            *distance = *value;
+                            c.relative = None;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// solver re-solves constraints whenever a constrained part or the part it
+// is constrained to has moved. A part which was moved itself is brought
+// back onto its constraints, while a part whose target moved follows it.
+fn solver(
+    history: Res<History>,
+    mut constrained: Query<(Entity, &mut Constraints)>,
+    mut transforms: QuerySet<(
+        Query<&Transform, With<Selectable>>,
+        Query<&mut Transform, With<Selectable>>,
+        Query<Entity, (With<Selectable>, Changed<Transform>)>,
+    )>,
+    parents: Query<&Parent>,
+) {
+    let mut moved: HashSet<Entity> = transforms.q2().iter().collect();
+
+    for _ in 0..MAX_PASSES {
+        let mut settled = true;
+
+        for (entity, mut constraints) in constrained.iter_mut() {
+            // Constraints on parts which have since been deleted are kept
+            // while the deletion can be undone, and dropped once it cannot.
+            let q = transforms.q0();
+            let gone = |c: &Constraint| q.get(c.target).is_err() && !history.mentions(c.target);
+            if constraints.0.iter().any(gone) {
+                constraints.0.retain(|c| !gone(c));
+            }
+
+            for c in constraints.0.iter_mut() {
+                let (target, current) = match (
+                    world_transform(c.target, transforms.q0(), &parents),
+                    world_transform(entity, transforms.q0(), &parents),
+                ) {
+                    (Some(target), Some(current)) => (target, current),
+                    _ => continue,
+                };
+
+                let local = match c.relative {
+                    _ if was_moved(entity, &moved, &parents) => {
+                        c.kind.project(&to_local(&target, &current))
+                    }
+                    Some(relative) if was_moved(c.target, &moved, &parents) => relative,
+                    Some(_) => continue,
+                    None => c.kind.project(&to_local(&target, &current)),
+                };
+                c.relative = Some(local);
+
+                let solved = to_world(&target, &local);
+                if crate::history::transform_changed(&current, &solved) {
+                    let frame = parents
+                        .get(entity)
+                        .ok()
+                        .and_then(|p| world_transform(p.0, transforms.q0(), &parents))
+                        .unwrap_or_else(Transform::identity);
+                    if let Ok(mut transform) = transforms.q1_mut().get_mut(entity) {
+                        *transform = to_local(&frame, &solved);
+                    }
+                    moved.insert(entity);
+                    settled = false;
+                }
+            }
+        }
+
+        if settled {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-5
+    }
+
+    fn tilted() -> Transform {
+        Transform {
+            translation: Vec3::new(3., 4., 5.),
+            rotation: Quat::from_rotation_x(0.3),
+            scale: Vec3::one(),
+        }
+    }
+
+    #[test]
+    fn align_turns_the_z_axis() {
+        let rotation = align(Quat::identity(), Vec3::unit_x());
+        assert!(close(rotation * Vec3::unit_z(), Vec3::unit_x()));
+    }
+
+    #[test]
+    fn align_picks_the_nearest_direction() {
+        // A part already along the axis, but upside down, is left alone.
+        let flipped = Quat::from_rotation_x(std::f32::consts::PI);
+        let rotation = align(flipped, Vec3::unit_z());
+        assert!(close(rotation * Vec3::unit_z(), -Vec3::unit_z()));
+    }
+
+    #[test]
+    fn coaxial_moves_onto_the_axis() {
+        let kind = ConstraintKind::Coaxial {
+            point: [1., 1., 0.],
+            axis: [0., 0., 1.],
+        };
+        let out = kind.project(&tilted());
+        assert!(close(out.translation, Vec3::new(1., 1., 5.)));
+        assert!(close(out.rotation * Vec3::unit_z(), Vec3::unit_z()));
+    }
+
+    #[test]
+    fn face_contact_moves_onto_the_plane() {
+        let kind = ConstraintKind::FaceContact {
+            point: [0., 0., 1.6],
+            normal: [0., 0., 1.],
+        };
+        let out = kind.project(&tilted());
+        assert!(close(out.translation, Vec3::new(3., 4., 1.6)));
+        assert!(close(out.rotation * Vec3::unit_z(), Vec3::unit_z()));
+    }
+
+    #[test]
+    fn offset_keeps_the_distance() {
+        let kind = ConstraintKind::Offset { distance: -2. };
+        let out = kind.project(&tilted());
+        assert!(close(out.translation, Vec3::new(3., 4., -2.)));
+        assert!(close(out.rotation * Vec3::unit_z(), Vec3::unit_z()));
+    }
+
+    #[test]
+    fn projecting_twice_changes_nothing() {
+        let kind = ConstraintKind::Coaxial {
+            point: [1., 1., 0.],
+            axis: [1., 0., 1.],
+        };
+        let once = kind.project(&tilted());
+        let twice = kind.project(&once);
+        assert!(!crate::history::transform_changed(&once, &twice));
+    }
+
+    #[test]
+    fn resolve_matches_parts_by_position() {
+        let order = [Entity::new(7), Entity::new(8), Entity::new(9)];
+        let reps = vec![ConstraintRep {
+            part: 2,
+            target: 0,
+            kind: ConstraintKind::Offset { distance: 1. },
+        }];
+        let resolved = resolve(&reps, &order).unwrap();
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].0, Entity::new(9));
+        assert_eq!(resolved[0].1.target, Entity::new(7));
+    }
+
+    #[test]
+    fn resolve_rejects_missing_parts() {
+        let reps = vec![ConstraintRep {
+            part: 0,
+            target: 3,
+            kind: ConstraintKind::Offset { distance: 1. },
+        }];
+        assert!(resolve(&reps, &[Entity::new(1)]).is_err());
+    }
+}
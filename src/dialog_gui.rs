@@ -1,8 +1,12 @@
 use bevy::prelude::*;
 use bevy_egui::*;
+use bevy_mod_picking::{Group, PickState};
 
-use crate::inspector_gui::{Library, SpawnPartEvent};
+use crate::bom::{Bom, BomFormat};
+use crate::export::MeshExport;
+use crate::inspector_gui::{Library, SpawnPartEvent, SpawnSceneEvent};
 use crate::interaction::HotkeyEvent;
+use crate::storage::ObjectError;
 
 use std::{fs, path};
 
@@ -28,7 +32,24 @@ fn load_assets(_world: &mut World, resources: &mut Resources) {
 pub(crate) enum FileNavIntent {
     SpecSelection,
     SaveScene,
-    OpenScene,
+    /// Writes the bill of materials, in the format given by the extension
+    /// of the file.
+    ExportBom(Bom),
+    /// Writes the geometry of the assembly, in the format given by the
+    /// extension of the file.
+    ExportMesh(MeshExport),
+    OpenScene {
+        /// Insert the assembly as a sub-assembly placed at the selection,
+        /// rather than spawning its objects where they were saved.
+        as_sub_assembly: bool,
+        /// The point on the model which was under the cursor when the
+        /// dialog was opened, where the sub-assembly is placed if nothing
+        /// is selected.
+        cursor: Option<Vec3>,
+        /// Link the sub-assembly to the file, so it is reloaded with any
+        /// changes made to it.
+        linked: bool,
+    },
 }
 
 impl FileNavIntent {
@@ -36,7 +57,9 @@ impl FileNavIntent {
         match self {
             FileNavIntent::SpecSelection => &"Add panel from spec",
             FileNavIntent::SaveScene => &"Save assembly",
-            FileNavIntent::OpenScene => &"Insert assembly",
+            FileNavIntent::ExportBom(_) => &"Export bill of materials",
+            FileNavIntent::ExportMesh(_) => &"Export mesh",
+            FileNavIntent::OpenScene { .. } => &"Insert assembly",
         }
     }
     fn filter(&self, entry: &(fs::DirEntry, fs::Metadata)) -> bool {
@@ -54,7 +77,7 @@ impl FileNavIntent {
                     || entry.0.path().extension() == Some(&std::ffi::OsStr::new("spec"))
             }
 
-            FileNavIntent::SaveScene | FileNavIntent::OpenScene => {
+            FileNavIntent::SaveScene | FileNavIntent::OpenScene { .. } => {
                 (entry.1.is_dir()
                     && !entry
                         .0
@@ -66,6 +89,69 @@ impl FileNavIntent {
                         .starts_with("."))
                     || entry.0.path().extension() == Some(&std::ffi::OsStr::new("mpa"))
             }
+
+            FileNavIntent::ExportBom(_) => {
+                (entry.1.is_dir()
+                    && !entry
+                        .0
+                        .path()
+                        .file_name()
+                        .unwrap()
+                        .to_str()
+                        .unwrap()
+                        .starts_with("."))
+                    || ["csv", "json", "md"]
+                        .iter()
+                        .any(|ext| entry.0.path().extension() == Some(&std::ffi::OsStr::new(ext)))
+            }
+
+            FileNavIntent::ExportMesh(_) => {
+                (entry.1.is_dir()
+                    && !entry
+                        .0
+                        .path()
+                        .file_name()
+                        .unwrap()
+                        .to_str()
+                        .unwrap()
+                        .starts_with("."))
+                    || ["stl", "obj", "gltf", "glb"]
+                        .iter()
+                        .any(|ext| entry.0.path().extension() == Some(&std::ffi::OsStr::new(ext)))
+            }
+        }
+    }
+
+    /// Writes the output of a save intent to the given path, returning
+    /// true if it was written.
+    fn write(&self, path: &path::Path, scene: &serde_json::Value) -> bool {
+        let f = match std::fs::File::create(path) {
+            Ok(f) => f,
+            Err(e) => {
+                eprintln!("Failed to open file: {:?}", e);
+                return false;
+            }
+        };
+        let result: std::io::Result<()> = match self {
+            FileNavIntent::SaveScene => {
+                serde_json::to_writer_pretty(f, scene).map_err(|e| e.into())
+            }
+            FileNavIntent::ExportBom(bom) => {
+                let mut f = f;
+                std::io::Write::write_all(&mut f, bom.render(BomFormat::from_path(path)).as_bytes())
+            }
+            FileNavIntent::ExportMesh(mesh) => {
+                let mut f = std::io::BufWriter::new(f);
+                mesh.write(&mut f, path)
+            }
+            _ => return false,
+        };
+        match result {
+            Ok(_) => true,
+            Err(e) => {
+                eprintln!("Failed writing {:?}: {:?}", path, e);
+                false
+            }
         }
     }
 }
@@ -84,6 +170,17 @@ pub(crate) enum DialogState {
         current: path::PathBuf,
         contents: Vec<(fs::DirEntry, fs::Metadata)>,
     },
+    LoadErrors {
+        path: path::PathBuf,
+        loaded: usize,
+        fatal: Option<String>,
+        errors: Vec<ObjectError>,
+        failed: Vec<String>,
+        /// Problems which didnt stop objects loading, such as linked
+        /// sub-assemblies which were loaded as they were saved.
+        warnings: Vec<String>,
+    },
+    SpawnErrors(Vec<String>),
 }
 
 impl Default for DialogState {
@@ -140,7 +237,12 @@ pub enum DialogHotkeyEvent {
     AddSpec,
     Escape,
     SaveScene(serde_json::Value),
+    ExportBom(Bom),
+    ExportMesh(MeshExport),
     LoadScene,
+    /// Reports parts which could not be built, such as panels with an
+    /// invalid spec.
+    SpawnErrors(Vec<String>),
 }
 
 fn draw_files(
@@ -176,6 +278,152 @@ fn draw_files(
     }
 }
 
+/// Loads the assembly at path into the scene. Any objects which decode
+/// successfully are spawned; if there were problems, the returned state
+/// is a report of them. If placement is provided, the objects are
+/// inserted as a sub-assembly with that transform.
+fn open_scene(
+    path: &path::PathBuf,
+    placement: Option<Transform>,
+    linked: bool,
+    spawner: &mut ResMut<Events<SpawnSceneEvent>>,
+) -> DialogState {
+    let contents = match std::fs::read(path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            return DialogState::LoadErrors {
+                path: path.clone(),
+                loaded: 0,
+                fatal: Some(format!("Failed reading file: {}", e)),
+                errors: vec![],
+                failed: vec![],
+                warnings: vec![],
+            };
+        }
+    };
+    let result = match crate::storage::decode_scene(&contents) {
+        Ok(result) => result,
+        Err(e) => {
+            return DialogState::LoadErrors {
+                path: path.clone(),
+                loaded: 0,
+                fatal: Some(format!("Failed decoding assembly: {}", e)),
+                errors: vec![],
+                failed: vec![],
+                warnings: vec![],
+            };
+        }
+    };
+
+    // Only upgrade the file in-place if everything was understood, otherwise
+    // the objects we couldnt decode would be lost.
+    if result.upgraded && result.errors.is_empty() {
+        match std::fs::File::create(path) {
+            Ok(f) => {
+                if let Err(e) = serde_json::to_writer_pretty(f, &result.document) {
+                    eprintln!("Failed upgrading {:?}: {:?}", path, e);
+                }
+            }
+            Err(e) => eprintln!("Failed to open file: {:?}", e),
+        }
+    }
+
+    let mut objects = result.document.objects;
+    let warnings = crate::storage::resolve_links(&mut objects);
+
+    let loaded = objects.len();
+    let members = objects.into_iter().filter_map(|obj| obj.into_spawn());
+    match placement {
+        Some(transform) => {
+            let name = path
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let source = if linked {
+                Some(path.to_string_lossy().to_string())
+            } else {
+                None
+            };
+            spawner.send(SpawnSceneEvent(
+                vec![SpawnPartEvent::Group(
+                    name,
+                    source,
+                    members.collect(),
+                    result.document.constraints,
+                    Some(transform),
+                )],
+                vec![],
+            ));
+        }
+        None => {
+            spawner.send(SpawnSceneEvent(
+                members.collect(),
+                result.document.constraints,
+            ));
+        }
+    }
+
+    if result.errors.is_empty() && warnings.is_empty() {
+        DialogState::None
+    } else {
+        DialogState::LoadErrors {
+            path: path.clone(),
+            loaded,
+            fatal: None,
+            errors: result.errors,
+            failed: vec![],
+            warnings,
+        }
+    }
+}
+
+fn draw_load_errors(
+    ui: &mut egui::Ui,
+    path: &path::PathBuf,
+    loaded: usize,
+    fatal: &Option<String>,
+    errors: &Vec<ObjectError>,
+    failed: &Vec<String>,
+    warnings: &Vec<String>,
+) {
+    ui.label(format!("{}", path.display()));
+    ui.separator();
+    if let Some(fatal) = fatal {
+        ui.colored_label(egui::Color32::RED, fatal.as_str());
+        return;
+    }
+
+    ui.label(format!(
+        "Loaded {} objects, {} could not be loaded:",
+        loaded.saturating_sub(failed.len()),
+        errors.len() + failed.len()
+    ));
+    ui.allocate_space(egui::Vec2::new(0., 4.));
+    egui::containers::ScrollArea::auto_sized()
+        .id_source("load_errors")
+        .show(ui, |ui| {
+            for err in errors {
+                ui.horizontal(|ui| {
+                    ui.colored_label(egui::Color32::RED, err.path.as_str());
+                    if let Some(field) = &err.field {
+                        ui.label(format!("{}:", field));
+                    }
+                    ui.label(err.reason.as_str());
+                });
+            }
+            draw_spawn_errors(ui, failed);
+            for warning in warnings {
+                ui.colored_label(egui::Color32::from_rgb(255, 200, 0), warning.as_str());
+            }
+        });
+}
+
+fn draw_spawn_errors(ui: &mut egui::Ui, failed: &Vec<String>) {
+    for err in failed {
+        ui.colored_label(egui::Color32::RED, err.as_str());
+    }
+}
+
 fn ui(
     cmd_args: Res<crate::CmdArgs>,
     mut library: ResMut<Library>,
@@ -186,7 +434,10 @@ fn ui(
     mut egui_context: ResMut<EguiContext>,
     mut state: ResMut<DialogState>,
 
-    mut spawner: ResMut<Events<SpawnPartEvent>>,
+    mut spawner: ResMut<Events<SpawnSceneEvent>>,
+    sel: Res<crate::interaction::Selection>,
+    pick_state: Res<PickState>,
+    globals: Query<&GlobalTransform>,
 ) {
     let mut action: Option<UiAction> = None;
 
@@ -223,7 +474,13 @@ fn ui(
                 *state = DialogState::Open {
                     current,
                     contents,
-                    intent: FileNavIntent::OpenScene,
+                    intent: FileNavIntent::OpenScene {
+                        as_sub_assembly: true,
+                        cursor: pick_state
+                            .top(Group::default())
+                            .map(|(_, hit)| *hit.position()),
+                        linked: false,
+                    },
                 };
             }
             DialogHotkeyEvent::SaveScene(scene) => {
@@ -245,9 +502,56 @@ fn ui(
                     intent: FileNavIntent::SaveScene,
                 }
             }
+            DialogHotkeyEvent::ExportBom(bom) => {
+                let current = if cmd_args.0.spec_dirs.len() == 0 {
+                    directories::BaseDirs::new()
+                        .unwrap()
+                        .home_dir()
+                        .to_path_buf()
+                } else {
+                    cmd_args.0.spec_dirs[0].clone().into()
+                };
+                let contents = read_dir(&current);
+
+                *state = DialogState::Save {
+                    current,
+                    contents,
+                    filename: "bom.csv".to_string(),
+                    scene: serde_json::Value::Null,
+                    intent: FileNavIntent::ExportBom(bom.clone()),
+                }
+            }
+            DialogHotkeyEvent::ExportMesh(mesh) => {
+                let current = if cmd_args.0.spec_dirs.len() == 0 {
+                    directories::BaseDirs::new()
+                        .unwrap()
+                        .home_dir()
+                        .to_path_buf()
+                } else {
+                    cmd_args.0.spec_dirs[0].clone().into()
+                };
+                let contents = read_dir(&current);
+
+                *state = DialogState::Save {
+                    current,
+                    contents,
+                    filename: "assembly.stl".to_string(),
+                    scene: serde_json::Value::Null,
+                    intent: FileNavIntent::ExportMesh(mesh.clone()),
+                }
+            }
             DialogHotkeyEvent::Escape => {
                 *state = DialogState::None;
             }
+            DialogHotkeyEvent::SpawnErrors(errors) => match *state {
+                // Scenes are spawned after they are decoded, so their
+                // failures are added to the report of the load.
+                DialogState::LoadErrors { ref mut failed, .. } => {
+                    failed.extend(errors.iter().cloned())
+                }
+                DialogState::SpawnErrors(ref mut failed) => failed.extend(errors.iter().cloned()),
+                _ => *state = DialogState::SpawnErrors(errors.clone()),
+            },
         }
     }
 
@@ -258,10 +562,54 @@ fn ui(
         egui::pos2(screen.right() - 310., screen.bottom() - 85.),
     );
 
+    let mut dismiss = false;
     match *state {
         DialogState::None => (),
+        DialogState::LoadErrors {
+            ref path,
+            loaded,
+            ref fatal,
+            ref errors,
+            ref failed,
+            ref warnings,
+        } => {
+            egui::Window::new("Assembly load errors")
+                .id(egui::Id::new("dialog"))
+                .fixed_rect(rect)
+                .resizable(false)
+                .collapsible(false)
+                .show(ctx, |ui| {
+                    draw_load_errors(ui, path, loaded, fatal, errors, failed, warnings);
+
+                    ui.separator();
+                    ui.with_layout(egui::Layout::top_down(egui::Align::Max), |ui| {
+                        if ui.button("Close").clicked() {
+                            dismiss = true;
+                        }
+                    });
+                });
+        }
+        DialogState::SpawnErrors(ref failed) => {
+            egui::Window::new("Parts could not be built")
+                .id(egui::Id::new("dialog"))
+                .fixed_rect(rect)
+                .resizable(false)
+                .collapsible(false)
+                .show(ctx, |ui| {
+                    egui::containers::ScrollArea::auto_sized()
+                        .id_source("spawn_errors")
+                        .show(ui, |ui| draw_spawn_errors(ui, failed));
+
+                    ui.separator();
+                    ui.with_layout(egui::Layout::top_down(egui::Align::Max), |ui| {
+                        if ui.button("Close").clicked() {
+                            dismiss = true;
+                        }
+                    });
+                });
+        }
         DialogState::Open {
-            ref intent,
+            ref mut intent,
             ref current,
             ref contents,
         } => {
@@ -282,6 +630,19 @@ fn ui(
                             };
                         }
                     });
+                    if let FileNavIntent::OpenScene {
+                        as_sub_assembly,
+                        linked,
+                        ..
+                    } = intent
+                    {
+                        ui.horizontal(|ui| {
+                            ui.checkbox(as_sub_assembly, "As sub-assembly at selection");
+                            if *as_sub_assembly {
+                                ui.checkbox(linked, "Link to file");
+                            }
+                        });
+                    }
                     ui.separator();
                     ui.allocate_space(egui::Vec2::new(0., 4.));
 
@@ -350,6 +711,10 @@ fn ui(
         }
     }
 
+    if dismiss {
+        *state = DialogState::None;
+    }
+
     if let Some(action) = action {
         match (&mut *state, action) {
             (
@@ -395,44 +760,36 @@ fn ui(
                                 eprintln!("Failed reading {:?}: {:?}", path, e);
                             }
                         },
-                        FileNavIntent::OpenScene => match std::fs::read(path) {
-                            Ok(contents) => {
-                                use crate::storage::ObjectRep;
-                                for obj in crate::storage::decode_scene(&contents) {
-                                    match obj {
-                                        ObjectRep::Panel {
-                                            path,
-                                            spec,
-                                            convex_hull,
-                                            pos,
-                                            color,
-                                        } => {
-                                            let panel = crate::parts::PanelInfo::new(path, spec);
-                                            spawner.send(SpawnPartEvent::Panel(
-                                                panel,
-                                                convex_hull,
-                                                color,
-                                                Some(pos.into()),
-                                            ))
-                                        }
-                                        ObjectRep::Screw { pos, screw, length } => spawner.send(
-                                            SpawnPartEvent::Screw(screw, length, Some(pos.into())),
-                                        ),
-                                        ObjectRep::Washer { pos, washer } => spawner
-                                            .send(SpawnPartEvent::Washer(washer, Some(pos.into()))),
-                                        ObjectRep::Nut { pos, nut } => {
-                                            spawner.send(SpawnPartEvent::Nut(nut, Some(pos.into())))
-                                        }
-                                        _ => (),
+                        FileNavIntent::OpenScene {
+                            as_sub_assembly,
+                            cursor,
+                            linked,
+                        } => {
+                            // Sub-assemblies take on the position and
+                            // orientation of the selected object. Otherwise
+                            // they are placed where the cursor was over the
+                            // model, or at the origin.
+                            let placement = if *as_sub_assembly {
+                                let at = match sel.entity().and_then(|e| globals.get(e).ok()) {
+                                    Some(global) => Transform {
+                                        translation: global.translation,
+                                        rotation: global.rotation,
+                                        ..Default::default()
+                                    },
+                                    None => {
+                                        Transform::from_translation(cursor.unwrap_or(Vec3::zero()))
                                     }
-                                }
-                                *state = DialogState::None;
-                            }
-                            Err(e) => {
-                                eprintln!("Failed reading {:?}: {:?}", path, e);
-                            }
-                        },
-                        FileNavIntent::SaveScene => (),
+                                };
+                                Some(at)
+                            } else {
+                                None
+                            };
+                            let linked = *linked;
+                            *state = open_scene(path, placement, linked, &mut spawner);
+                        }
+                        FileNavIntent::SaveScene
+                        | FileNavIntent::ExportBom(_)
+                        | FileNavIntent::ExportMesh(_) => (),
                     }
                 }
             }
@@ -467,16 +824,8 @@ fn ui(
                     *current = path.to_path_buf();
                     *contents = read_dir(&current);
                 } else {
-                    match intent {
-                        FileNavIntent::SaveScene => match std::fs::File::create(path) {
-                            Ok(f) => {
-                                if let Ok(_) = serde_json::to_writer_pretty(f, scene) {
-                                    *state = DialogState::None;
-                                }
-                            }
-                            Err(e) => eprintln!("Failed to open file: {:?}", e),
-                        },
-                        _ => (),
+                    if intent.write(path, scene) {
+                        *state = DialogState::None;
                     }
                 }
             }
@@ -489,21 +838,13 @@ fn ui(
                     ..
                 },
                 UiAction::SavePressed,
-            ) => match intent {
-                FileNavIntent::SaveScene => {
-                    let mut path = current.to_path_buf();
-                    path.push(filename);
-                    match std::fs::File::create(path) {
-                        Ok(f) => {
-                            if let Ok(_) = serde_json::to_writer(f, scene) {
-                                *state = DialogState::None;
-                            }
-                        }
-                        Err(e) => eprintln!("Failed to open file: {:?}", e),
-                    }
+            ) => {
+                let mut path = current.to_path_buf();
+                path.push(filename);
+                if intent.write(&path, scene) {
+                    *state = DialogState::None;
                 }
-                _ => (),
-            },
+            }
             _ => {}
         }
     }
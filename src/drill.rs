@@ -0,0 +1,499 @@
+use std::collections::HashMap;
+
+use crate::parts::PanelHoles;
+
+/// Number of segments the wall of a drilled hole is made of.
+const SEGMENTS: usize = 24;
+
+/// Distance, in mm, under which points are considered to be the same.
+const EPSILON: f64 = 1e-6;
+
+/// Minimum width, in mm, of board which must be left around a hole for it
+/// to be drilled.
+const MIN_WEB: f64 = 0.05;
+
+type Point = [f64; 2];
+type Tessellation = (Vec<[f64; 3]>, Vec<u32>);
+
+/// Cuts the holes out of a tessellated panel, adding a wall around each.
+/// Holes which would cross the edge of the panel or another hole are left
+/// solid, as is the whole panel if its faces cannot be triangulated.
+pub(crate) fn drill(tessellation: Tessellation, holes: &PanelHoles) -> Tessellation {
+    match try_drill(&tessellation, holes) {
+        Some(drilled) => drilled,
+        None => tessellation,
+    }
+}
+
+fn try_drill(tessellation: &Tessellation, holes: &PanelHoles) -> Option<Tessellation> {
+    let (verts, inds) = tessellation;
+
+    // Triangles of the top and bottom faces are rebuilt, while the walls
+    // around the edge of the panel are kept as they are.
+    let (mut top, mut bottom, mut walls) = (vec![], vec![], vec![]);
+    for tri in inds.chunks_exact(3) {
+        let tri = [
+            verts[tri[0] as usize],
+            verts[tri[1] as usize],
+            verts[tri[2] as usize],
+        ];
+        let normal = normal(&tri);
+        let length = (normal[0].powi(2) + normal[1].powi(2) + normal[2].powi(2)).sqrt();
+        if normal[2] > length * 0.5 {
+            top.push(tri);
+        } else if normal[2] < -length * 0.5 {
+            bottom.push(tri);
+        } else {
+            walls.push(tri);
+        }
+    }
+
+    // Loops are wound counter-clockwise around the board, and clockwise
+    // around cutouts in it, on both faces.
+    let top_loops = boundary_loops(&top)?;
+    let bottom_loops: Vec<Vec<Point>> = boundary_loops(&bottom)?
+        .into_iter()
+        .map(|l| l.into_iter().rev().collect())
+        .collect();
+
+    let mut drilled: Vec<(Point, f64)> = vec![];
+    for (center, radius) in &holes.holes {
+        let (center, radius) = ([center.x as f64, center.y as f64], *radius as f64);
+        let inside = top_loops.iter().filter(|l| contains(l, center)).count() % 2 == 1;
+        let clear = top_loops.iter().all(|l| {
+            (0..l.len())
+                .all(|i| segment_distance(center, l[i], l[(i + 1) % l.len()]) > radius + MIN_WEB)
+        });
+        let apart = drilled
+            .iter()
+            .all(|(c, r)| distance(*c, center) > r + radius + MIN_WEB);
+        if inside && clear && apart {
+            drilled.push((center, radius));
+        }
+    }
+    if drilled.is_empty() {
+        return None;
+    }
+
+    let circles: Vec<Vec<Point>> = drilled
+        .iter()
+        .map(|(center, radius)| circle(*center, *radius))
+        .collect();
+    let (z_bottom, z_top) = (holes.bottom as f64, holes.top as f64);
+
+    let mut out: Vec<[f64; 3]> = vec![];
+    for [a, b, c] in triangulate(top_loops, &circles)? {
+        out.extend(&[at(a, z_top), at(b, z_top), at(c, z_top)]);
+    }
+    for [a, b, c] in triangulate(bottom_loops, &circles)? {
+        out.extend(&[at(a, z_bottom), at(c, z_bottom), at(b, z_bottom)]);
+    }
+    for tri in walls {
+        out.extend(&tri);
+    }
+    for circle in &circles {
+        // Walls face into the hole.
+        for i in 0..circle.len() {
+            let (p, q) = (circle[i], circle[(i + 1) % circle.len()]);
+            out.extend(&[at(q, z_bottom), at(p, z_top), at(p, z_bottom)]);
+            out.extend(&[at(q, z_bottom), at(q, z_top), at(p, z_top)]);
+        }
+    }
+
+    Some(index(out))
+}
+
+// Builds an indexed tessellation from a list of triangle corners, sharing
+// corners which are at exactly the same position.
+fn index(corners: Vec<[f64; 3]>) -> Tessellation {
+    let mut ids: HashMap<[u64; 3], u32> = HashMap::new();
+    let mut verts = vec![];
+    let indices = corners
+        .into_iter()
+        .map(|v| {
+            let key = [v[0].to_bits(), v[1].to_bits(), v[2].to_bits()];
+            *ids.entry(key).or_insert_with(|| {
+                verts.push(v);
+                verts.len() as u32 - 1
+            })
+        })
+        .collect();
+    (verts, indices)
+}
+
+fn at(p: Point, z: f64) -> [f64; 3] {
+    [p[0], p[1], z]
+}
+
+fn normal(tri: &[[f64; 3]; 3]) -> [f64; 3] {
+    let u = [
+        tri[1][0] - tri[0][0],
+        tri[1][1] - tri[0][1],
+        tri[1][2] - tri[0][2],
+    ];
+    let v = [
+        tri[2][0] - tri[0][0],
+        tri[2][1] - tri[0][1],
+        tri[2][2] - tri[0][2],
+    ];
+    [
+        (u[1] * v[2]) - (u[2] * v[1]),
+        (u[2] * v[0]) - (u[0] * v[2]),
+        (u[0] * v[1]) - (u[1] * v[0]),
+    ]
+}
+
+// Returns a clockwise polygon approximating the given circle.
+fn circle(center: Point, radius: f64) -> Vec<Point> {
+    (0..SEGMENTS)
+        .map(|i| {
+            let angle = -2. * std::f64::consts::PI * i as f64 / SEGMENTS as f64;
+            [
+                center[0] + radius * angle.cos(),
+                center[1] + radius * angle.sin(),
+            ]
+        })
+        .collect()
+}
+
+// Finds the loops of edges bounding a set of triangles, keeping the
+// direction of the edges. Returns None if the edges do not form loops.
+fn boundary_loops(triangles: &[[[f64; 3]; 3]]) -> Option<Vec<Vec<Point>>> {
+    // Triangles may not share vertexes, so points are matched by position.
+    let mut ids: HashMap<(i64, i64), usize> = HashMap::new();
+    let mut points: Vec<Point> = vec![];
+    let mut id = |v: [f64; 3]| {
+        let key = ((v[0] / 1e-4).round() as i64, (v[1] / 1e-4).round() as i64);
+        *ids.entry(key).or_insert_with(|| {
+            points.push([v[0], v[1]]);
+            points.len() - 1
+        })
+    };
+
+    let mut edges: Vec<(usize, usize)> = vec![];
+    for tri in triangles {
+        let [a, b, c] = [id(tri[0]), id(tri[1]), id(tri[2])];
+        edges.extend(&[(a, b), (b, c), (c, a)]);
+    }
+    let mut counts: HashMap<(usize, usize), usize> = HashMap::new();
+    for (a, b) in &edges {
+        *counts.entry((*a.min(b), *a.max(b))).or_default() += 1;
+    }
+
+    let mut next: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (a, b) in edges {
+        if a != b && counts[&(a.min(b), a.max(b))] == 1 {
+            next.entry(a).or_default().push(b);
+        }
+    }
+
+    let mut loops = vec![];
+    while let Some(&start) = next.keys().next() {
+        let mut ring = vec![];
+        let mut current = start;
+        loop {
+            ring.push(points[current]);
+            let outgoing = next.get_mut(&current)?;
+            let to = outgoing.pop()?;
+            if outgoing.is_empty() {
+                next.remove(&current);
+            }
+            current = to;
+            if current == start {
+                break;
+            }
+        }
+        loops.push(ring);
+    }
+    Some(loops)
+}
+
+// Triangulates a face bounded by the given loops, with the given holes cut
+// out of it. Triangles are wound counter-clockwise.
+fn triangulate(loops: Vec<Vec<Point>>, holes: &[Vec<Point>]) -> Option<Vec<[Point; 3]>> {
+    let (outers, mut inners): (Vec<_>, Vec<_>) = loops.into_iter().partition(|l| area(l) > 0.);
+    inners.extend(holes.iter().cloned());
+
+    // Each cutout belongs to the smallest outline containing it.
+    let mut cutouts: Vec<Vec<Vec<Point>>> = vec![vec![]; outers.len()];
+    for inner in inners {
+        let owner = outers
+            .iter()
+            .enumerate()
+            .filter(|(_, o)| contains(o, inner[0]))
+            .min_by(|(_, a), (_, b)| area(a).partial_cmp(&area(b)).unwrap())?
+            .0;
+        cutouts[owner].push(inner);
+    }
+
+    let mut triangles = vec![];
+    for (mut outline, mut cutouts) in outers.into_iter().zip(cutouts) {
+        // Cutouts are joined to the outline from right to left, which
+        // keeps bridges short.
+        cutouts.sort_by(|a, b| max_x(b).partial_cmp(&max_x(a)).unwrap());
+        for i in 0..cutouts.len() {
+            bridge(&mut outline, &cutouts[i], &cutouts[i + 1..])?;
+        }
+        triangles.extend(ear_clip(outline)?);
+    }
+    Some(triangles)
+}
+
+// Joins a clockwise cutout to a counter-clockwise outline, with a pair of
+// edges from its rightmost point to the nearest visible point of the
+// outline.
+fn bridge(outline: &mut Vec<Point>, cutout: &[Point], others: &[Vec<Point>]) -> Option<()> {
+    let m = (0..cutout.len()).max_by(|a, b| cutout[*a][0].partial_cmp(&cutout[*b][0]).unwrap())?;
+    let from = cutout[m];
+
+    let n = outline.len();
+    let mut best: Option<(f64, usize)> = None;
+    for i in 0..n {
+        let (prev, v, next) = (outline[(i + n - 1) % n], outline[i], outline[(i + 1) % n]);
+        let d = distance(v, from);
+        if best.map(|(bd, _)| d >= bd).unwrap_or(false) || !in_cone(prev, v, next, from) {
+            continue;
+        }
+        let blocked = crosses(outline, v, from)
+            || crosses(cutout, v, from)
+            || others.iter().any(|o| crosses(o, v, from));
+        if !blocked {
+            best = Some((d, i));
+        }
+    }
+    let (_, i) = best?;
+
+    let mut joined = outline[..=i].to_vec();
+    joined.extend_from_slice(&cutout[m..]);
+    joined.extend_from_slice(&cutout[..=m]);
+    joined.extend_from_slice(&outline[i..]);
+    *outline = joined;
+    Some(())
+}
+
+// Triangulates a counter-clockwise polygon by repeatedly cutting off ears.
+fn ear_clip(mut polygon: Vec<Point>) -> Option<Vec<[Point; 3]>> {
+    let mut triangles = vec![];
+    let (mut i, mut stalled) = (0, 0);
+    while polygon.len() > 3 {
+        let n = polygon.len();
+        i %= n;
+        let (a, b, c) = (polygon[(i + n - 1) % n], polygon[i], polygon[(i + 1) % n]);
+        let turn = cross(a, b, c);
+
+        // Points in line with their neighbours enclose nothing.
+        if turn.abs() < EPSILON {
+            polygon.remove(i);
+            stalled = 0;
+            continue;
+        }
+        let ear = turn > 0.
+            && !polygon
+                .iter()
+                .any(|p| !same(*p, a) && !same(*p, b) && !same(*p, c) && in_triangle(a, b, c, *p));
+        if ear {
+            triangles.push([a, b, c]);
+            polygon.remove(i);
+            stalled = 0;
+        } else {
+            i += 1;
+            stalled += 1;
+            if stalled > n {
+                return None;
+            }
+        }
+    }
+    if polygon.len() == 3 && cross(polygon[0], polygon[1], polygon[2]) > EPSILON {
+        triangles.push([polygon[0], polygon[1], polygon[2]]);
+    }
+    Some(triangles)
+}
+
+// Twice the signed area of the triangle abc, positive if it is wound
+// counter-clockwise.
+fn cross(a: Point, b: Point, c: Point) -> f64 {
+    (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])
+}
+
+fn area(polygon: &[Point]) -> f64 {
+    let n = polygon.len();
+    (0..n)
+        .map(|i| {
+            let (p, q) = (polygon[i], polygon[(i + 1) % n]);
+            p[0] * q[1] - q[0] * p[1]
+        })
+        .sum::<f64>()
+        / 2.
+}
+
+fn max_x(polygon: &[Point]) -> f64 {
+    polygon.iter().fold(std::f64::MIN, |x, p| x.max(p[0]))
+}
+
+fn same(a: Point, b: Point) -> bool {
+    distance(a, b) < EPSILON
+}
+
+fn distance(a: Point, b: Point) -> f64 {
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2)).sqrt()
+}
+
+fn segment_distance(p: Point, a: Point, b: Point) -> f64 {
+    let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+    let length = dx * dx + dy * dy;
+    if length < EPSILON {
+        return distance(p, a);
+    }
+    let t = (((p[0] - a[0]) * dx + (p[1] - a[1]) * dy) / length)
+        .max(0.)
+        .min(1.);
+    distance(p, [a[0] + t * dx, a[1] + t * dy])
+}
+
+// Returns true if p is inside the polygon, by the even-odd rule.
+fn contains(polygon: &[Point], p: Point) -> bool {
+    let n = polygon.len();
+    let mut inside = false;
+    for i in 0..n {
+        let (a, b) = (polygon[i], polygon[(i + 1) % n]);
+        if (a[1] > p[1]) != (b[1] > p[1])
+            && p[0] < a[0] + (p[1] - a[1]) * (b[0] - a[0]) / (b[1] - a[1])
+        {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+fn in_triangle(a: Point, b: Point, c: Point, p: Point) -> bool {
+    cross(a, b, p) >= 0. && cross(b, c, p) >= 0. && cross(c, a, p) >= 0.
+}
+
+// Returns true if p lies within the interior angle of a counter-clockwise
+// polygon at v.
+fn in_cone(prev: Point, v: Point, next: Point, p: Point) -> bool {
+    if cross(prev, v, next) >= 0. {
+        cross(prev, v, p) > 0. && cross(v, next, p) > 0.
+    } else {
+        cross(prev, v, p) > 0. || cross(v, next, p) > 0.
+    }
+}
+
+// Returns true if the segment ab properly crosses an edge of the polygon.
+// Edges touching either end of the segment are ignored.
+fn crosses(polygon: &[Point], a: Point, b: Point) -> bool {
+    let n = polygon.len();
+    (0..n).any(|i| {
+        let (p, q) = (polygon[i], polygon[(i + 1) % n]);
+        if same(p, a) || same(p, b) || same(q, a) || same(q, b) {
+            return false;
+        }
+        let (d1, d2) = (cross(a, b, p), cross(a, b, q));
+        let (d3, d4) = (cross(p, q, a), cross(p, q, b));
+        ((d1 > 0.) != (d2 > 0.)) && ((d3 > 0.) != (d4 > 0.))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::math::Vec2;
+
+    const THICKNESS: f64 = 1.6;
+
+    // A 10mm square panel, with separate corners for each triangle as a
+    // tessellated spec has.
+    fn square() -> Tessellation {
+        let corner = |x: f64, y: f64, z: f64| [x * 10., y * 10., z * THICKNESS];
+        let quad = |a: [f64; 3], b: [f64; 3], c: [f64; 3], d: [f64; 3]| vec![a, b, c, a, c, d];
+        let mut corners = vec![];
+        corners.extend(quad(
+            corner(0., 0., 1.),
+            corner(1., 0., 1.),
+            corner(1., 1., 1.),
+            corner(0., 1., 1.),
+        ));
+        corners.extend(quad(
+            corner(0., 0., 0.),
+            corner(0., 1., 0.),
+            corner(1., 1., 0.),
+            corner(1., 0., 0.),
+        ));
+        let outline = [(0., 0.), (1., 0.), (1., 1.), (0., 1.)];
+        for i in 0..4 {
+            let ((x0, y0), (x1, y1)) = (outline[i], outline[(i + 1) % 4]);
+            corners.extend(quad(
+                corner(x0, y0, 0.),
+                corner(x1, y1, 0.),
+                corner(x1, y1, 1.),
+                corner(x0, y0, 1.),
+            ));
+        }
+        let indices = (0..corners.len() as u32).collect();
+        (corners, indices)
+    }
+
+    fn holes(holes: &[(f32, f32, f32)]) -> PanelHoles {
+        PanelHoles {
+            holes: holes
+                .iter()
+                .map(|(x, y, r)| (Vec2::new(*x, *y), *r))
+                .collect(),
+            bottom: 0.,
+            top: THICKNESS as f32,
+        }
+    }
+
+    // Returns the area of the top face of a tessellation.
+    fn top_area((verts, inds): &Tessellation) -> f64 {
+        inds.chunks_exact(3)
+            .map(|tri| {
+                let tri = [
+                    verts[tri[0] as usize],
+                    verts[tri[1] as usize],
+                    verts[tri[2] as usize],
+                ];
+                if tri.iter().all(|v| (v[2] - THICKNESS).abs() < EPSILON) {
+                    normal(&tri)[2] / 2.
+                } else {
+                    0.
+                }
+            })
+            .sum()
+    }
+
+    fn circle_area(radius: f64) -> f64 {
+        let n = SEGMENTS as f64;
+        n * radius * radius * (2. * std::f64::consts::PI / n).sin() / 2.
+    }
+
+    #[test]
+    fn drills_a_hole() {
+        let drilled = drill(square(), &holes(&[(5., 5., 1.)]));
+        let (verts, inds) = &drilled;
+
+        assert!(inds.iter().all(|i| (*i as usize) < verts.len()));
+        // Corners shared between triangles are shared in the output.
+        assert!(verts.len() < inds.len());
+        assert!((top_area(&drilled) - (100. - circle_area(1.))).abs() < 1e-6);
+        // The top and bottom are joined by a wall around the hole.
+        let wall = verts
+            .iter()
+            .filter(|v| (distance([v[0], v[1]], [5., 5.]) - 1.).abs() < 1e-6)
+            .count();
+        assert_eq!(wall, 2 * SEGMENTS);
+    }
+
+    #[test]
+    fn holes_crossing_the_edge_are_left_solid() {
+        let original = square();
+        let drilled = drill(original.clone(), &holes(&[(0.5, 5., 1.)]));
+        assert_eq!(drilled, original);
+    }
+
+    #[test]
+    fn overlapping_holes_are_left_solid() {
+        let drilled = drill(square(), &holes(&[(4., 5., 1.), (5., 5., 1.)]));
+        assert!((top_area(&drilled) - (100. - circle_area(1.))).abs() < 1e-6);
+    }
+}
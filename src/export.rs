@@ -0,0 +1,1100 @@
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, VertexAttributeValues};
+use bevy::render::texture::TextureFormat;
+use serde_json::json;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+
+use crate::assembly::SubAssembly;
+use crate::gizmo::Gizmo;
+use crate::inspector_gui::SpawnPartEvent;
+use crate::interaction::Selectable;
+use crate::parts::{
+    Insert, LayerVisibility, Nut, PanelDecorations, PanelInfo, Screw, ScrewLength, Standoff,
+    StandoffMaterial, Support, Washer,
+};
+use crate::storage::ObjectRep;
+
+pub struct Plugin;
+
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_event::<ExportMeshEvent>()
+            .add_system(capture.system());
+    }
+}
+
+/// Requests the geometry of the assembly be captured, and a dialog opened
+/// to save it.
+#[derive(Debug)]
+pub struct ExportMeshEvent;
+
+/// The formats an assembly mesh can be written in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MeshFormat {
+    /// Binary STL, with all parts combined into one mesh.
+    Stl,
+    /// Wavefront OBJ, with a group per part and a material library giving
+    /// their colors.
+    Obj,
+    /// glTF 2.0, with its binary buffer written alongside it.
+    Gltf,
+    /// Binary glTF 2.0, in a single file.
+    Glb,
+}
+
+impl MeshFormat {
+    /// Picks the format from the extension of a file name, defaulting to
+    /// STL.
+    pub fn from_path(path: &Path) -> Self {
+        Self::from_extension(path).unwrap_or(MeshFormat::Stl)
+    }
+
+    /// Picks the format from the extension of a file name, returning None
+    /// if it is not one of the supported formats.
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        match path.extension().and_then(std::ffi::OsStr::to_str) {
+            Some("stl") => Some(MeshFormat::Stl),
+            Some("obj") => Some(MeshFormat::Obj),
+            Some("gltf") => Some(MeshFormat::Gltf),
+            Some("glb") => Some(MeshFormat::Glb),
+            _ => None,
+        }
+    }
+}
+
+/// Geometry which may be shared by several parts.
+#[derive(Debug, Clone)]
+pub struct MeshData {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub uvs: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+}
+
+/// The appearance of a part.
+#[derive(Debug, Clone)]
+pub struct MaterialData {
+    pub color: [f32; 3],
+    /// Opacity, which is less than 1 for see-through materials.
+    pub alpha: f32,
+    pub metallic: f32,
+    pub roughness: f32,
+    /// RGBA image, along with its width and height.
+    pub texture: Option<(u32, u32, Vec<u8>)>,
+}
+
+/// A node in the hierarchy of an exported assembly. Parts and
+/// sub-assemblies are named nodes, while the meshes making up a part are
+/// unnamed nodes beneath it.
+#[derive(Debug, Clone)]
+pub struct MeshNode {
+    pub name: Option<String>,
+    /// Transform relative to the parent node.
+    pub transform: Transform,
+    /// Indices of the mesh and material the node is drawn with.
+    pub mesh: Option<(usize, usize)>,
+    pub children: Vec<MeshNode>,
+}
+
+/// The geometry of an assembly, as it was when it was captured.
+#[derive(Debug, Clone, Default)]
+pub struct MeshExport {
+    pub meshes: Vec<MeshData>,
+    pub materials: Vec<MaterialData>,
+    pub nodes: Vec<MeshNode>,
+}
+
+// The geometry of a single part, in world space.
+struct Part<'a> {
+    name: &'a str,
+    color: [f32; 3],
+    triangles: Vec<[Vec3; 3]>,
+}
+
+impl MeshExport {
+    /// Writes the mesh in the format given by the extension of path. OBJ
+    /// and glTF files have their material library or buffer written
+    /// alongside them.
+    pub fn write(&self, w: &mut impl Write, path: &Path) -> std::io::Result<()> {
+        match MeshFormat::from_path(path) {
+            MeshFormat::Stl => self.write_stl(w),
+            MeshFormat::Obj => {
+                let mtl = path.with_extension("mtl");
+                self.write_mtl(&mut std::fs::File::create(&mtl)?)?;
+                let mtl_name = mtl.file_name().unwrap().to_string_lossy().to_string();
+                self.write_obj(w, &mtl_name)
+            }
+            MeshFormat::Gltf => {
+                let bin = path.with_extension("bin");
+                let bin_name = bin.file_name().unwrap().to_string_lossy().to_string();
+                let (doc, buffer) = self.gltf(Some(&bin_name))?;
+                std::fs::write(&bin, buffer)?;
+                serde_json::to_writer_pretty(w, &doc).map_err(|e| e.into())
+            }
+            MeshFormat::Glb => {
+                let (doc, buffer) = self.gltf(None)?;
+                write_glb(w, &doc, buffer)
+            }
+        }
+    }
+
+    /// Builds the geometry of saved objects without spawning them, such as
+    /// when exporting from the command line. Panels are drawn with all their
+    /// layers, and threads are not modelled.
+    pub(crate) fn from_objects(objects: &[ObjectRep]) -> Result<Self, String> {
+        let mut export = MeshExport::default();
+        for obj in objects {
+            if let Some(ev) = obj.clone().into_spawn() {
+                let node = export.build(&ev)?;
+                export.nodes.push(node);
+            }
+        }
+        Ok(export)
+    }
+
+    // Describes the part or sub-assembly the event would spawn, adding its
+    // meshes and materials to the export.
+    fn build(&mut self, ev: &SpawnPartEvent) -> Result<MeshNode, String> {
+        let hardware = |color: Color| MaterialData {
+            color: [color.r(), color.g(), color.b()],
+            alpha: 1.,
+            metallic: 1.,
+            roughness: 0.35,
+            texture: None,
+        };
+        let stainless = || hardware(Color::rgb(0.79, 0.8, 0.81));
+
+        let (name, parts) = match ev {
+            SpawnPartEvent::Panel(panel, convex_hull, decorations, _) => {
+                let panel = panel.with_convex_hull(*convex_hull);
+                let geometry =
+                    crate::parts::build_panel(&panel, decorations, &LayerVisibility::default())
+                        .map_err(|e| format!("{}: {}", panel.name(), e))?;
+                let (metallic, roughness) = decorations.material.finish();
+                let material = |texture: Option<&Texture>| MaterialData {
+                    color: decorations.color,
+                    alpha: decorations.material.alpha(),
+                    metallic,
+                    roughness,
+                    texture: texture.and_then(texture_data),
+                };
+                (
+                    panel.name(),
+                    vec![
+                        (geometry.front.0, material(Some(&geometry.front.1))),
+                        (geometry.back.0, material(Some(&geometry.back.1))),
+                        (geometry.edge, material(None)),
+                    ],
+                )
+            }
+            SpawnPartEvent::Screw(screw, length, _) => (
+                format!("{}x{} {} screw", screw, length, screw.head.name()),
+                vec![(
+                    crate::fastener::screw_mesh(screw, *length, false),
+                    stainless(),
+                )],
+            ),
+            SpawnPartEvent::Washer(washer, _) => (
+                format!("{} washer", washer),
+                vec![(crate::fastener::washer_mesh(washer), stainless())],
+            ),
+            SpawnPartEvent::Nut(nut, _) => (
+                format!("{} nut", nut),
+                vec![(crate::fastener::nut_mesh(nut), stainless())],
+            ),
+            SpawnPartEvent::Standoff(standoff, _) => (
+                format!("{} standoff", standoff),
+                vec![(
+                    crate::fastener::standoff_mesh(standoff),
+                    hardware(standoff.material.color()),
+                )],
+            ),
+            SpawnPartEvent::Insert(insert, _) => (
+                format!("{} heat-set insert", insert),
+                vec![(
+                    crate::fastener::insert_mesh(insert),
+                    hardware(StandoffMaterial::Brass.color()),
+                )],
+            ),
+            SpawnPartEvent::Support(support, _) => (
+                support.to_string(),
+                vec![(
+                    crate::fastener::support_mesh(support),
+                    hardware(StandoffMaterial::Nylon.color()),
+                )],
+            ),
+            SpawnPartEvent::Group(name, _, members, _, _) => {
+                let children = members
+                    .iter()
+                    .map(|member| self.build(member))
+                    .collect::<Result<Vec<_>, _>>()?;
+                return Ok(MeshNode {
+                    name: Some(name.clone()),
+                    transform: ev.transform(),
+                    mesh: None,
+                    children,
+                });
+            }
+        };
+
+        let mut node = MeshNode {
+            name: Some(name),
+            transform: ev.transform(),
+            mesh: None,
+            children: vec![],
+        };
+        for (mesh, material) in parts {
+            let mesh = match mesh_data(&mesh) {
+                Some(mesh) => mesh,
+                None => continue,
+            };
+            self.meshes.push(mesh);
+            self.materials.push(material);
+            node.children.push(MeshNode {
+                name: None,
+                transform: Transform::default(),
+                mesh: Some((self.meshes.len() - 1, self.materials.len() - 1)),
+                children: vec![],
+            });
+        }
+        Ok(node)
+    }
+
+    // Flattens the hierarchy into the parts it contains, transforming their
+    // meshes into world space.
+    fn parts(&self) -> Vec<Part> {
+        let mut parts = vec![];
+        for node in &self.nodes {
+            self.flatten(node, Mat4::identity(), None, &mut parts);
+        }
+        // Sub-assemblies have no geometry of their own.
+        parts.retain(|p| !p.triangles.is_empty());
+        parts
+    }
+
+    fn flatten<'a>(
+        &'a self,
+        node: &'a MeshNode,
+        parent: Mat4,
+        part: Option<usize>,
+        parts: &mut Vec<Part<'a>>,
+    ) {
+        let matrix = parent * node.transform.compute_matrix();
+        let part = match &node.name {
+            Some(name) => {
+                parts.push(Part {
+                    name,
+                    color: [1., 1., 1.],
+                    triangles: vec![],
+                });
+                Some(parts.len() - 1)
+            }
+            None => part,
+        };
+
+        if let (Some((mesh, material)), Some(part)) = (node.mesh, part) {
+            let mesh = &self.meshes[mesh];
+            let positions: Vec<Vec3> = mesh
+                .positions
+                .iter()
+                .map(|p| matrix.transform_point3(Vec3::from(*p)))
+                .collect();
+            let part = &mut parts[part];
+            part.color = self.materials[material].color;
+            part.triangles.extend(mesh.indices.chunks_exact(3).map(|t| {
+                [
+                    positions[t[0] as usize],
+                    positions[t[1] as usize],
+                    positions[t[2] as usize],
+                ]
+            }));
+        }
+
+        for child in &node.children {
+            self.flatten(child, matrix, part, parts);
+        }
+    }
+
+    fn write_stl(&self, w: &mut impl Write) -> std::io::Result<()> {
+        let parts = self.parts();
+        let mut header = [0u8; 80];
+        let title = b"mp-assembler";
+        header[..title.len()].copy_from_slice(title);
+        w.write_all(&header)?;
+
+        // Triangles with no area, such as those meeting at the tip of a
+        // cone, have no normal and are left out.
+        let triangles: Vec<_> = parts
+            .iter()
+            .flat_map(|p| p.triangles.iter())
+            .filter_map(|tri| {
+                let normal = (tri[1] - tri[0]).cross(tri[2] - tri[0]);
+                if normal.length() > 0. {
+                    Some((normal.normalize(), tri))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        w.write_all(&(triangles.len() as u32).to_le_bytes())?;
+
+        for (normal, tri) in triangles {
+            for v in std::iter::once(&normal).chain(tri.iter()) {
+                for c in [v.x, v.y, v.z].iter() {
+                    w.write_all(&c.to_le_bytes())?;
+                }
+            }
+            // Attribute byte count, which is unused.
+            w.write_all(&0u16.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn write_obj(&self, w: &mut impl Write, mtl_name: &str) -> std::io::Result<()> {
+        writeln!(w, "# mp-assembler")?;
+        writeln!(w, "mtllib {}", mtl_name)?;
+
+        let mut next_vertex = 1;
+        for (i, part) in self.parts().iter().enumerate() {
+            writeln!(w, "g {}", part_name(i, part.name))?;
+            writeln!(w, "usemtl part_{}", i)?;
+            for v in part.triangles.iter().flat_map(|t| t.iter()) {
+                writeln!(w, "v {} {} {}", v.x, v.y, v.z)?;
+            }
+            for _ in &part.triangles {
+                writeln!(
+                    w,
+                    "f {} {} {}",
+                    next_vertex,
+                    next_vertex + 1,
+                    next_vertex + 2
+                )?;
+                next_vertex += 3;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_mtl(&self, w: &mut impl Write) -> std::io::Result<()> {
+        writeln!(w, "# mp-assembler")?;
+        for (i, part) in self.parts().iter().enumerate() {
+            writeln!(w, "newmtl part_{}", i)?;
+            writeln!(
+                w,
+                "Kd {} {} {}",
+                part.color[0], part.color[1], part.color[2]
+            )?;
+        }
+        Ok(())
+    }
+
+    // Builds the JSON document and binary buffer of a glTF file. The buffer
+    // is embedded if bin_uri is None, as it is in a GLB file.
+    fn gltf(&self, bin_uri: Option<&str>) -> std::io::Result<(serde_json::Value, Vec<u8>)> {
+        let mut buffer = GltfBuffer::default();
+
+        // Each mesh has its own accessors, which are shared by the
+        // materials it is drawn with.
+        let mut attributes = vec![];
+        for mesh in &self.meshes {
+            let mut attrs = serde_json::Map::new();
+            let positions: Vec<f32> = mesh
+                .positions
+                .iter()
+                .flat_map(|p| p.iter().copied())
+                .collect();
+            attrs.insert(
+                "POSITION".to_string(),
+                json!(buffer.floats(&positions, "VEC3", true)),
+            );
+            if mesh.normals.len() == mesh.positions.len() {
+                let normals: Vec<f32> = mesh
+                    .normals
+                    .iter()
+                    .flat_map(|n| n.iter().copied())
+                    .collect();
+                attrs.insert(
+                    "NORMAL".to_string(),
+                    json!(buffer.floats(&normals, "VEC3", false)),
+                );
+            }
+            if mesh.uvs.len() == mesh.positions.len() {
+                let uvs: Vec<f32> = mesh.uvs.iter().flat_map(|uv| uv.iter().copied()).collect();
+                attrs.insert(
+                    "TEXCOORD_0".to_string(),
+                    json!(buffer.floats(&uvs, "VEC2", false)),
+                );
+            }
+            attributes.push((attrs, buffer.indices(&mesh.indices)));
+        }
+
+        let (mut images, mut textures, mut materials) = (vec![], vec![], vec![]);
+        for material in &self.materials {
+            let mut pbr = json!({
+                "baseColorFactor": [
+                    material.color[0],
+                    material.color[1],
+                    material.color[2],
+                    material.alpha,
+                ],
+                "metallicFactor": material.metallic,
+                "roughnessFactor": material.roughness,
+            });
+            if let Some((width, height, data)) = &material.texture {
+                let png = encode_png(*width, *height, data)?;
+                images.push(json!({
+                    "bufferView": buffer.view(&png, None),
+                    "mimeType": "image/png",
+                }));
+                textures.push(json!({ "source": images.len() - 1 }));
+                // The color is already painted into the texture, which is
+                // opaque, so only the alpha is applied.
+                pbr["baseColorFactor"] = json!([1., 1., 1., material.alpha]);
+                pbr["baseColorTexture"] = json!({ "index": textures.len() - 1 });
+            }
+            let mut out = json!({ "pbrMetallicRoughness": pbr });
+            if material.alpha < 1. {
+                out["alphaMode"] = json!("BLEND");
+            }
+            materials.push(out);
+        }
+
+        let mut gltf = Gltf {
+            attributes,
+            meshes: vec![],
+            mesh_index: HashMap::new(),
+            nodes: vec![],
+        };
+        let roots: Vec<usize> = self.nodes.iter().map(|n| gltf.node(n)).collect();
+        // The assembly is modelled in millimetres, while glTF is in metres.
+        gltf.nodes.push(json!({
+            "name": "assembly",
+            "scale": [0.001, 0.001, 0.001],
+            "children": roots,
+        }));
+
+        let mut buffer_desc = json!({ "byteLength": buffer.data.len() });
+        if let Some(uri) = bin_uri {
+            buffer_desc["uri"] = json!(uri);
+        }
+        let mut doc = json!({
+            "asset": { "version": "2.0", "generator": "mp-assembler" },
+            "scene": 0,
+            "scenes": [{ "nodes": [gltf.nodes.len() - 1] }],
+            "nodes": gltf.nodes,
+            "meshes": gltf.meshes,
+            "materials": materials,
+            "buffers": [buffer_desc],
+            "bufferViews": buffer.views,
+            "accessors": buffer.accessors,
+        });
+        if !images.is_empty() {
+            doc["images"] = json!(images);
+            doc["textures"] = json!(textures);
+        }
+        Ok((doc, buffer.data))
+    }
+}
+
+// Names the group for a part in an OBJ file, which must be unique and
+// cannot contain whitespace.
+fn part_name(index: usize, name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_whitespace() { '_' } else { c })
+        .collect();
+    format!("{}_{}", name, index)
+}
+
+const GLTF_FLOAT: u32 = 5126;
+const GLTF_UNSIGNED_INT: u32 = 5125;
+const GLTF_ARRAY_BUFFER: u32 = 34962;
+const GLTF_ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+// Accumulates the binary buffer of a glTF file, along with the views and
+// accessors describing its contents.
+#[derive(Default)]
+struct GltfBuffer {
+    data: Vec<u8>,
+    views: Vec<serde_json::Value>,
+    accessors: Vec<serde_json::Value>,
+}
+
+impl GltfBuffer {
+    fn view(&mut self, bytes: &[u8], target: Option<u32>) -> usize {
+        // Accessors must be aligned to their component size.
+        while self.data.len() % 4 != 0 {
+            self.data.push(0);
+        }
+        let mut view = json!({
+            "buffer": 0,
+            "byteOffset": self.data.len(),
+            "byteLength": bytes.len(),
+        });
+        if let Some(target) = target {
+            view["target"] = json!(target);
+        }
+        self.data.extend_from_slice(bytes);
+        self.views.push(view);
+        self.views.len() - 1
+    }
+
+    fn floats(&mut self, values: &[f32], kind: &str, bounds: bool) -> usize {
+        let components = if kind == "VEC3" { 3 } else { 2 };
+        let bytes: Vec<u8> = values
+            .iter()
+            .flat_map(|v| v.to_le_bytes().to_vec())
+            .collect();
+        let view = self.view(&bytes, Some(GLTF_ARRAY_BUFFER));
+
+        let mut accessor = json!({
+            "bufferView": view,
+            "componentType": GLTF_FLOAT,
+            "count": values.len() / components,
+            "type": kind,
+        });
+        // Positions must give their bounds.
+        if bounds {
+            let (mut min, mut max) = (vec![f32::MAX; components], vec![f32::MIN; components]);
+            for v in values.chunks_exact(components) {
+                for i in 0..components {
+                    min[i] = min[i].min(v[i]);
+                    max[i] = max[i].max(v[i]);
+                }
+            }
+            accessor["min"] = json!(min);
+            accessor["max"] = json!(max);
+        }
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+
+    fn indices(&mut self, indices: &[u32]) -> usize {
+        let bytes: Vec<u8> = indices
+            .iter()
+            .flat_map(|i| i.to_le_bytes().to_vec())
+            .collect();
+        let view = self.view(&bytes, Some(GLTF_ELEMENT_ARRAY_BUFFER));
+        self.accessors.push(json!({
+            "bufferView": view,
+            "componentType": GLTF_UNSIGNED_INT,
+            "count": indices.len(),
+            "type": "SCALAR",
+        }));
+        self.accessors.len() - 1
+    }
+}
+
+// Accumulates the nodes and meshes of a glTF file. A glTF mesh is created
+// for each combination of mesh and material which is used.
+struct Gltf {
+    attributes: Vec<(serde_json::Map<String, serde_json::Value>, usize)>,
+    meshes: Vec<serde_json::Value>,
+    mesh_index: HashMap<(usize, usize), usize>,
+    nodes: Vec<serde_json::Value>,
+}
+
+impl Gltf {
+    fn node(&mut self, node: &MeshNode) -> usize {
+        let children: Vec<usize> = node.children.iter().map(|c| self.node(c)).collect();
+
+        let translation: [f32; 3] = node.transform.translation.into();
+        let rotation: [f32; 4] = node.transform.rotation.into();
+        let scale: [f32; 3] = node.transform.scale.into();
+        let mut out = json!({
+            "translation": translation,
+            "rotation": rotation,
+            "scale": scale,
+        });
+        if let Some(name) = &node.name {
+            out["name"] = json!(name);
+        }
+        if !children.is_empty() {
+            out["children"] = json!(children);
+        }
+        if let Some((mesh, material)) = node.mesh {
+            let (attributes, meshes) = (&self.attributes, &mut self.meshes);
+            let index = *self.mesh_index.entry((mesh, material)).or_insert_with(|| {
+                let (attrs, indices) = &attributes[mesh];
+                meshes.push(json!({
+                    "primitives": [{
+                        "attributes": attrs,
+                        "indices": indices,
+                        "material": material,
+                    }],
+                }));
+                meshes.len() - 1
+            });
+            out["mesh"] = json!(index);
+        }
+
+        self.nodes.push(out);
+        self.nodes.len() - 1
+    }
+}
+
+fn encode_png(width: u32, height: u32, data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let to_io = |e: png::EncodingError| std::io::Error::new(std::io::ErrorKind::Other, e);
+    let mut out = vec![];
+    {
+        let mut encoder = png::Encoder::new(&mut out, width, height);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(to_io)?;
+        writer.write_image_data(data).map_err(to_io)?;
+    }
+    Ok(out)
+}
+
+// Writes a GLB container, holding the JSON document and its buffer.
+fn write_glb(
+    w: &mut impl Write,
+    doc: &serde_json::Value,
+    mut buffer: Vec<u8>,
+) -> std::io::Result<()> {
+    let mut json = serde_json::to_vec(doc)?;
+    while json.len() % 4 != 0 {
+        json.push(b' ');
+    }
+    while buffer.len() % 4 != 0 {
+        buffer.push(0);
+    }
+
+    let length = 12 + 8 + json.len() + 8 + buffer.len();
+    w.write_all(b"glTF")?;
+    w.write_all(&2u32.to_le_bytes())?;
+    w.write_all(&(length as u32).to_le_bytes())?;
+
+    w.write_all(&(json.len() as u32).to_le_bytes())?;
+    w.write_all(b"JSON")?;
+    w.write_all(&json)?;
+    w.write_all(&(buffer.len() as u32).to_le_bytes())?;
+    w.write_all(b"BIN\0")?;
+    w.write_all(&buffer)
+}
+
+// Collects the meshes and materials of parts, sharing those used by more
+// than one.
+struct Capture<'a> {
+    meshes: &'a Assets<Mesh>,
+    materials: &'a Assets<StandardMaterial>,
+    textures: &'a Assets<Texture>,
+    mesh_index: HashMap<Handle<Mesh>, usize>,
+    material_index: HashMap<Handle<StandardMaterial>, usize>,
+    export: MeshExport,
+}
+
+type ObjectComponents<'a> = (
+    &'a Transform,
+    Option<&'a Children>,
+    Option<&'a SubAssembly>,
+    Option<&'a PanelInfo>,
+    Option<&'a PanelDecorations>,
+    Option<&'a Screw>,
+    Option<&'a ScrewLength>,
+    Option<&'a Washer>,
+    Option<&'a Nut>,
+    Option<&'a Standoff>,
+    Option<&'a Insert>,
+    Option<&'a Support>,
+);
+
+impl<'a> Capture<'a> {
+    // Describes the part or sub-assembly entity, returning None if it is
+    // neither.
+    fn node(
+        &mut self,
+        entity: Entity,
+        objects: &Query<ObjectComponents, (With<Selectable>, Without<Gizmo>)>,
+        geometry: &Query<
+            (&Handle<Mesh>, &Handle<StandardMaterial>, &Transform),
+            (Without<Gizmo>, Without<Selectable>),
+        >,
+    ) -> Option<MeshNode> {
+        let (
+            transform,
+            children,
+            group,
+            panel,
+            decorations,
+            screw,
+            length,
+            washer,
+            nut,
+            standoff,
+            insert,
+            support,
+        ) = objects.get(entity).ok()?;
+        let name = match (group, panel, screw, washer, nut, standoff, insert, support) {
+            (Some(group), ..) => group.name.clone(),
+            (_, Some(panel), ..) => panel.name(),
+            (_, _, Some(screw), ..) => format!(
+                "{}x{} {} screw",
+                screw,
+                length.map(|l| l.0).unwrap_or_default(),
+                screw.head.name()
+            ),
+            (_, _, _, Some(washer), ..) => format!("{} washer", washer),
+            (_, _, _, _, Some(nut), ..) => format!("{} nut", nut),
+            (_, _, _, _, _, Some(standoff), ..) => format!("{} standoff", standoff),
+            (_, _, _, _, _, _, Some(insert), _) => format!("{} heat-set insert", insert),
+            (_, _, _, _, _, _, _, Some(support)) => support.to_string(),
+            _ => return None,
+        };
+
+        let mut node = MeshNode {
+            name: Some(name),
+            transform: *transform,
+            mesh: None,
+            children: vec![],
+        };
+        for child in children.into_iter().flat_map(|c| c.iter()) {
+            if let Some(member) = self.node(*child, objects, geometry) {
+                node.children.push(member);
+                continue;
+            }
+            let (mesh, material, transform) = match geometry.get(*child) {
+                Ok(geometry) => geometry,
+                Err(_) => continue,
+            };
+            let mesh = match self.mesh(mesh) {
+                Some(mesh) => mesh,
+                None => {
+                    eprintln!(
+                        "Mesh for {} is not loaded, omitting it",
+                        node.name.as_ref().unwrap()
+                    );
+                    continue;
+                }
+            };
+            node.children.push(MeshNode {
+                name: None,
+                transform: *transform,
+                mesh: Some((mesh, self.material(material, decorations))),
+                children: vec![],
+            });
+        }
+        Some(node)
+    }
+
+    fn mesh(&mut self, handle: &Handle<Mesh>) -> Option<usize> {
+        if let Some(index) = self.mesh_index.get(handle) {
+            return Some(*index);
+        }
+        let mesh = mesh_data(self.meshes.get(handle)?)?;
+
+        self.export.meshes.push(mesh);
+        self.mesh_index
+            .insert(handle.clone(), self.export.meshes.len() - 1);
+        Some(self.export.meshes.len() - 1)
+    }
+
+    // Describes the material of a mesh. Panels take their color and finish
+    // from their decorations, and everything else is stainless.
+    fn material(
+        &mut self,
+        handle: &Handle<StandardMaterial>,
+        decorations: Option<&PanelDecorations>,
+    ) -> usize {
+        if let Some(index) = self.material_index.get(handle) {
+            return *index;
+        }
+        let material = self.materials.get(handle);
+
+        let texture = material
+            .and_then(|m| m.albedo_texture.as_ref())
+            .and_then(|t| self.textures.get(t))
+            .and_then(texture_data);
+        let data = match decorations {
+            Some(decorations) => {
+                let (metallic, roughness) = decorations.material.finish();
+                MaterialData {
+                    color: decorations.color,
+                    alpha: decorations.material.alpha(),
+                    metallic,
+                    roughness,
+                    texture,
+                }
+            }
+            None => {
+                let albedo = material.map(|m| m.albedo).unwrap_or(Color::WHITE);
+                MaterialData {
+                    color: [albedo.r(), albedo.g(), albedo.b()],
+                    alpha: albedo.a(),
+                    metallic: 1.,
+                    roughness: 0.35,
+                    texture,
+                }
+            }
+        };
+
+        self.export.materials.push(data);
+        self.material_index
+            .insert(handle.clone(), self.export.materials.len() - 1);
+        self.export.materials.len() - 1
+    }
+}
+
+// Copies the geometry of a mesh, returning None if it has no positions.
+fn mesh_data(mesh: &Mesh) -> Option<MeshData> {
+    let positions = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+        Some(VertexAttributeValues::Float3(positions)) => positions.clone(),
+        _ => return None,
+    };
+    let normals = match mesh.attribute(Mesh::ATTRIBUTE_NORMAL) {
+        Some(VertexAttributeValues::Float3(normals)) => normals.clone(),
+        _ => vec![],
+    };
+    let uvs = match mesh.attribute(Mesh::ATTRIBUTE_UV_0) {
+        Some(VertexAttributeValues::Float2(uvs)) => uvs.clone(),
+        _ => vec![],
+    };
+    let indices = match mesh.indices() {
+        Some(Indices::U16(indices)) => indices.iter().map(|i| *i as u32).collect(),
+        Some(Indices::U32(indices)) => indices.clone(),
+        None => (0..positions.len() as u32).collect(),
+    };
+
+    Some(MeshData {
+        positions,
+        normals,
+        uvs,
+        indices,
+    })
+}
+
+// Copies the image of a texture, if it is in a format which can be
+// exported.
+fn texture_data(texture: &Texture) -> Option<(u32, u32, Vec<u8>)> {
+    if texture.format != TextureFormat::Rgba8Unorm {
+        return None;
+    }
+    Some((
+        texture.size.width,
+        texture.size.height,
+        texture.data.clone(),
+    ))
+}
+
+// capture collects the meshes making up each part, and opens a dialog to
+// save them.
+fn capture(
+    ev_export: Res<Events<ExportMeshEvent>>,
+    mut export_reader: Local<EventReader<ExportMeshEvent>>,
+    mut ev_dialog: ResMut<Events<crate::dialog_gui::DialogHotkeyEvent>>,
+    meshes: Res<Assets<Mesh>>,
+    materials: Res<Assets<StandardMaterial>>,
+    textures: Res<Assets<Texture>>,
+    objects: Query<ObjectComponents, (With<Selectable>, Without<Gizmo>)>,
+    roots: Query<Entity, (With<Selectable>, Without<Gizmo>, Without<Parent>)>,
+    geometry: Query<
+        (&Handle<Mesh>, &Handle<StandardMaterial>, &Transform),
+        (Without<Gizmo>, Without<Selectable>),
+    >,
+) {
+    if export_reader.iter(&ev_export).last().is_none() {
+        return;
+    }
+
+    let mut capture = Capture {
+        meshes: &meshes,
+        materials: &materials,
+        textures: &textures,
+        mesh_index: HashMap::new(),
+        material_index: HashMap::new(),
+        export: MeshExport::default(),
+    };
+    for entity in roots.iter() {
+        if let Some(node) = capture.node(entity, &objects, &geometry) {
+            capture.export.nodes.push(node);
+        }
+    }
+
+    ev_dialog.send(crate::dialog_gui::DialogHotkeyEvent::ExportMesh(
+        capture.export,
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parts::{Metric, ScrewDrive, ScrewHead};
+
+    // A square of two triangles, used by a part and a part within a
+    // sub-assembly.
+    fn square() -> MeshExport {
+        let part = |name: &str, x: f32| MeshNode {
+            name: Some(name.to_string()),
+            transform: Transform::from_translation(Vec3::new(x, 0., 0.)),
+            mesh: None,
+            children: vec![MeshNode {
+                name: None,
+                transform: Transform::default(),
+                mesh: Some((0, 0)),
+                children: vec![],
+            }],
+        };
+
+        MeshExport {
+            meshes: vec![MeshData {
+                positions: vec![[0., 0., 0.], [1., 0., 0.], [1., 1., 0.], [0., 1., 0.]],
+                normals: vec![[0., 0., 1.]; 4],
+                uvs: vec![[0., 0.], [1., 0.], [1., 1.], [0., 1.]],
+                indices: vec![0, 1, 2, 0, 2, 3],
+            }],
+            materials: vec![MaterialData {
+                color: [1., 0., 0.],
+                alpha: 1.,
+                metallic: 0.,
+                roughness: 1.,
+                // An odd-sized image, so the buffer needs padding.
+                texture: Some((1, 1, vec![255, 0, 0, 255])),
+            }],
+            nodes: vec![
+                part("a", 0.),
+                MeshNode {
+                    name: Some("group".to_string()),
+                    transform: Transform::from_translation(Vec3::new(0., 5., 0.)),
+                    mesh: None,
+                    children: vec![part("b", 2.)],
+                },
+            ],
+        }
+    }
+
+    fn u32_at(data: &[u8], offset: usize) -> usize {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&data[offset..offset + 4]);
+        u32::from_le_bytes(bytes) as usize
+    }
+
+    #[test]
+    fn formats_are_picked_by_extension() {
+        let format = |name: &str| MeshFormat::from_extension(Path::new(name));
+        assert_eq!(format("a.stl"), Some(MeshFormat::Stl));
+        assert_eq!(format("a.glb"), Some(MeshFormat::Glb));
+        assert_eq!(format("a.step"), None);
+        assert_eq!(format("a"), None);
+        assert_eq!(MeshFormat::from_path(Path::new("a")), MeshFormat::Stl);
+    }
+
+    #[test]
+    fn stl_has_a_record_per_triangle() {
+        let mut out = vec![];
+        square().write_stl(&mut out).unwrap();
+
+        assert_eq!(u32_at(&out, 80), 4);
+        assert_eq!(out.len(), 80 + 4 + 4 * 50);
+    }
+
+    #[test]
+    fn stl_normals_are_finite() {
+        // Screw meshes have rings of zero radius at their tips.
+        let mut export = MeshExport::default();
+        for head in [
+            ScrewHead::Pan,
+            ScrewHead::SocketCap,
+            ScrewHead::Countersunk,
+            ScrewHead::Button,
+            ScrewHead::Hex,
+        ]
+        .iter()
+        {
+            let screw = Screw {
+                size: Metric::M3,
+                head: *head,
+                drive: ScrewDrive::default(),
+            };
+            let node = export
+                .build(&SpawnPartEvent::Screw(screw, 7, None))
+                .unwrap();
+            export.nodes.push(node);
+        }
+        let mut out = vec![];
+        export.write_stl(&mut out).unwrap();
+
+        let count = u32_at(&out, 80);
+        assert!(count > 0);
+        assert_eq!(out.len(), 80 + 4 + count * 50);
+        for i in 0..count {
+            let record = 84 + i * 50;
+            for c in out[record..record + 12].chunks(4) {
+                let mut bytes = [0u8; 4];
+                bytes.copy_from_slice(c);
+                assert!(f32::from_le_bytes(bytes).is_finite());
+            }
+        }
+    }
+
+    #[test]
+    fn stl_vertices_are_in_world_space() {
+        let mut out = vec![];
+        square().write_stl(&mut out).unwrap();
+
+        // The first vertex of the third triangle belongs to part b, which
+        // is offset by its own and its group's translation.
+        let vertex = 84 + 2 * 50 + 12;
+        let coord = |i: usize| {
+            let mut bytes = [0u8; 4];
+            bytes.copy_from_slice(&out[vertex + 4 * i..vertex + 4 * i + 4]);
+            f32::from_le_bytes(bytes)
+        };
+        assert_eq!((coord(0), coord(1), coord(2)), (2., 5., 0.));
+    }
+
+    #[test]
+    fn glb_chunks_are_aligned() {
+        let export = square();
+        let (doc, buffer) = export.gltf(None).unwrap();
+        let buffer_len = buffer.len();
+        let mut out = vec![];
+        write_glb(&mut out, &doc, buffer).unwrap();
+
+        assert_eq!(&out[0..4], b"glTF");
+        assert_eq!(u32_at(&out, 4), 2);
+        assert_eq!(u32_at(&out, 8), out.len());
+
+        let json_len = u32_at(&out, 12);
+        assert_eq!(&out[16..20], b"JSON");
+        assert_eq!(json_len % 4, 0);
+        let json: serde_json::Value = serde_json::from_slice(&out[20..20 + json_len]).unwrap();
+        assert_eq!(json["buffers"][0]["byteLength"], buffer_len);
+        assert!(json["buffers"][0].get("uri").is_none());
+
+        let bin = 20 + json_len;
+        let bin_len = u32_at(&out, bin);
+        assert_eq!(&out[bin + 4..bin + 8], b"BIN\0");
+        assert_eq!(bin_len % 4, 0);
+        assert!(bin_len >= buffer_len);
+        assert_eq!(bin + 8 + bin_len, out.len());
+    }
+
+    #[test]
+    fn gltf_shares_meshes_between_parts() {
+        let (doc, _) = square().gltf(Some("square.bin")).unwrap();
+
+        assert_eq!(doc["meshes"].as_array().unwrap().len(), 1);
+        assert_eq!(doc["buffers"][0]["uri"], "square.bin");
+        // Both parts, their mesh nodes, the group and the root.
+        assert_eq!(doc["nodes"].as_array().unwrap().len(), 6);
+    }
+
+    #[test]
+    fn gltf_blends_see_through_materials() {
+        let mut export = square();
+        let mut acrylic = export.materials[0].clone();
+        acrylic.alpha = 0.4;
+        export.materials.push(acrylic);
+
+        let (doc, _) = export.gltf(None).unwrap();
+        let materials = doc["materials"].as_array().unwrap();
+        assert!(materials[0].get("alphaMode").is_none());
+        assert_eq!(materials[1]["alphaMode"], "BLEND");
+        let factor = materials[1]["pbrMetallicRoughness"]["baseColorFactor"][3]
+            .as_f64()
+            .unwrap();
+        assert!((factor - 0.4).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,572 @@
+use bevy::prelude::*;
+use bevy::render::mesh::Indices;
+use bevy::render::pipeline::PrimitiveTopology;
+use std::f32::consts::PI;
+
+use crate::gizmo::Gizmo;
+use crate::parts::{
+    Insert, MetricDims, Nut, PanelDecorations, Screw, ScrewDrive, ScrewHead, ScrewLength, Standoff,
+    StandoffGender, Support, SupportKind, Washer,
+};
+
+pub struct Plugin;
+
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(ModelThreads::default())
+            .add_system(rethread.system());
+    }
+}
+
+/// Number of points around each ring of a fastener mesh.
+const SEGMENTS: usize = 48;
+
+/// Number of rings each turn of a modelled thread is made of.
+const RINGS_PER_TURN: usize = 8;
+
+/// Depth of a thread, as a fraction of its pitch.
+const THREAD_DEPTH: f32 = 0.6;
+
+/// Resource describing whether screw threads are modelled, rather than
+/// drawn as a plain shaft.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ModelThreads(pub bool);
+
+// Collects the surfaces making up a mesh.
+#[derive(Default)]
+struct MeshBuilder {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    indices: Vec<u32>,
+}
+
+impl MeshBuilder {
+    // Adds a surface joining successive rings of points, each of which go
+    // counter-clockwise around the Z axis. The surface faces away from the
+    // solid if the rings go counter-clockwise around its outline, when seen
+    // in the XZ plane. Smooth surfaces share normals between neighbouring
+    // triangles, while the rest have flat faces.
+    fn surface(&mut self, rings: &[Vec<Vec3>], smooth: bool) {
+        let n = rings[0].len();
+        let mut triangles = vec![];
+        for r in 0..rings.len() - 1 {
+            for j in 0..n {
+                let k = (j + 1) % n;
+                triangles.push([(r, j), (r, k), (r + 1, j)]);
+                triangles.push([(r, k), (r + 1, k), (r + 1, j)]);
+            }
+        }
+        let normal = |tri: &[(usize, usize); 3]| {
+            let [a, b, c] = [
+                rings[tri[0].0][tri[0].1],
+                rings[tri[1].0][tri[1].1],
+                rings[tri[2].0][tri[2].1],
+            ];
+            (b - a).cross(c - a)
+        };
+
+        if smooth {
+            // Normals are summed over the triangles around each point,
+            // weighting each by its area.
+            let base = self.positions.len();
+            let mut normals = vec![Vec3::zero(); rings.len() * n];
+            for tri in &triangles {
+                let normal = normal(tri);
+                for (r, j) in tri {
+                    normals[r * n + j] += normal;
+                    self.indices.push((base + r * n + j) as u32);
+                }
+            }
+            for (p, normal) in rings.iter().flatten().zip(normals) {
+                self.positions.push([p.x, p.y, p.z]);
+                self.normals.push(unit(normal));
+            }
+        } else {
+            for tri in &triangles {
+                let normal = unit(normal(tri));
+                for (r, j) in tri {
+                    let p = rings[*r][*j];
+                    self.indices.push(self.positions.len() as u32);
+                    self.positions.push([p.x, p.y, p.z]);
+                    self.normals.push(normal);
+                }
+            }
+        }
+    }
+
+    // Adds a solid of revolution about the Z axis, with an outline made of
+    // straight lines between the given (radius, z) points. Each line is a
+    // separate surface, so the corners between them stay sharp.
+    fn lathe(&mut self, outline: &[(f32, f32)]) {
+        for (a, b) in outline.iter().zip(outline.iter().skip(1)) {
+            self.surface(&[circle(a.1, a.0), circle(b.1, b.0)], true);
+        }
+    }
+
+    fn into_mesh(self) -> Mesh {
+        let uvs: Vec<[f32; 2]> = self.positions.iter().map(|_| [0., 0.]).collect();
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, self.positions);
+        mesh.set_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+        mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+        mesh.set_indices(Some(Indices::U32(self.indices)));
+        mesh
+    }
+}
+
+fn unit(v: Vec3) -> [f32; 3] {
+    let v = if v.length() > 0. {
+        v.normalize()
+    } else {
+        Vec3::unit_z()
+    };
+    [v.x, v.y, v.z]
+}
+
+// Returns a ring of points at height z, with the distance of each point
+// from the Z axis given by the radius function of its angle.
+fn ring(z: f32, radius: impl Fn(f32) -> f32) -> Vec<Vec3> {
+    (0..SEGMENTS)
+        .map(|i| {
+            let angle = i as f32 / SEGMENTS as f32 * 2. * PI;
+            let r = radius(angle);
+            Vec3::new(r * angle.cos(), r * angle.sin(), z)
+        })
+        .collect()
+}
+
+fn circle(z: f32, radius: f32) -> Vec<Vec3> {
+    ring(z, |_| radius)
+}
+
+// Returns the distance to the edge of a hexagon at each angle, with a flat
+// facing along the X axis.
+fn hexagon(apothem: f32) -> impl Fn(f32) -> f32 + Copy {
+    move |angle| {
+        let sector = PI / 3.;
+        let offset = (angle + sector / 2.).rem_euclid(sector) - sector / 2.;
+        apothem / offset.cos()
+    }
+}
+
+// Returns the distance to the edge of the recess for a drive at each angle,
+// as a fraction of the size of the recess.
+fn drive_outline(drive: ScrewDrive) -> impl Fn(f32) -> f32 + Copy {
+    move |angle| match drive {
+        ScrewDrive::Hex => hexagon((PI / 6.).cos())(angle),
+        ScrewDrive::Torx => 0.86 + 0.14 * (6. * angle).cos(),
+        ScrewDrive::Phillips => {
+            // A cross, with arms a quarter as wide as they are long.
+            let offset = (angle + PI / 4.).rem_euclid(PI / 2.) - PI / 4.;
+            (1. / offset.cos()).min(0.25 / offset.sin().abs())
+        }
+    }
+}
+
+// Adds a shaft, with its tip at the origin and the given length, returning
+// the ring of points around its top.
+fn shaft(mesh: &mut MeshBuilder, dims: &MetricDims, length: f32, threads: bool) -> Vec<Vec3> {
+    let radius = dims.diameter / 2.;
+    if threads {
+        // A right-handed thread, the crest of which is at z = pitch * (turn +
+        // angle / 2π). The first turn is chamfered to the root.
+        let (pitch, depth) = (dims.pitch, dims.pitch * THREAD_DEPTH);
+        let count = ((length / pitch) * RINGS_PER_TURN as f32).ceil() as usize;
+        let rings: Vec<Vec<Vec3>> = (0..=count)
+            .map(|i| {
+                let z = length * i as f32 / count as f32;
+                ring(z, |angle| {
+                    let phase = (z / pitch - angle / (2. * PI)).rem_euclid(1.);
+                    let crest = 1. - (2. * phase - 1.).abs();
+                    radius - depth + depth * crest * (z / pitch).min(1.)
+                })
+            })
+            .collect();
+        mesh.surface(&[circle(0., 0.), rings[0].clone()], true);
+        mesh.surface(&rings, true);
+        rings.last().unwrap().clone()
+    } else {
+        let chamfer = dims.pitch * THREAD_DEPTH;
+        mesh.lathe(&[
+            (0., 0.),
+            (radius - chamfer, 0.),
+            (radius, chamfer),
+            (radius, length),
+        ]);
+        circle(length, radius)
+    }
+}
+
+/// Builds the mesh of a screw with its tip at the origin, pointing down the
+/// Z axis, and the underside of its head length mm above it. Countersunk
+/// heads are counted in the length, so their tops are length mm above it.
+pub(crate) fn screw_mesh(screw: &Screw, length: usize, threads: bool) -> Mesh {
+    let dims = screw.size.dims();
+    let (head_diameter, height) = screw.head.dims(screw.size);
+    let (radius, head_radius, length) = (dims.diameter / 2., head_diameter / 2., length as f32);
+    let mut mesh = MeshBuilder::default();
+
+    // The recess for the drive is cut into the middle of the head, which is
+    // flat out to the edge radius.
+    let recess = head_radius * 0.5;
+    let edge = recess * 1.1;
+
+    let top = match screw.head {
+        ScrewHead::Pan | ScrewHead::Button => {
+            // Straight sides, rounding over into a domed top.
+            let side = if screw.head == ScrewHead::Pan {
+                0.6
+            } else {
+                0.2
+            };
+            let shaft_top = shaft(&mut mesh, &dims, length, threads);
+            mesh.surface(&[shaft_top, circle(length, head_radius)], true);
+            mesh.lathe(&[(head_radius, length), (head_radius, length + height * side)]);
+            let end = (edge / head_radius).acos();
+            let dome: Vec<Vec<Vec3>> = (0..=8)
+                .map(|i| {
+                    let angle = end * i as f32 / 8.;
+                    circle(
+                        length + height * (side + (1. - side) * angle.sin()),
+                        head_radius * angle.cos(),
+                    )
+                })
+                .collect();
+            mesh.surface(&dome, true);
+            length + height * (side + (1. - side) * end.sin())
+        }
+        ScrewHead::SocketCap => {
+            let chamfer = height * 0.1;
+            let shaft_top = shaft(&mut mesh, &dims, length, threads);
+            mesh.surface(&[shaft_top, circle(length, head_radius)], true);
+            mesh.lathe(&[
+                (head_radius, length),
+                (head_radius, length + height - chamfer),
+                (head_radius - chamfer, length + height),
+                (edge, length + height),
+            ]);
+            length + height
+        }
+        ScrewHead::Countersunk => {
+            let shaft_top = shaft(&mut mesh, &dims, length - height, threads);
+            mesh.surface(&[shaft_top, circle(length - height, radius)], true);
+            mesh.lathe(&[
+                (radius, length - height),
+                (head_radius, length),
+                (edge, length),
+            ]);
+            length
+        }
+        ScrewHead::Hex => {
+            let hex = hexagon(head_radius);
+            let shaft_top = shaft(&mut mesh, &dims, length, threads);
+            mesh.surface(&[shaft_top, ring(length, hex)], true);
+            mesh.surface(&[ring(length, hex), ring(length + height, hex)], false);
+            mesh.surface(
+                &[ring(length + height, hex), circle(length + height, 0.)],
+                true,
+            );
+            return mesh.into_mesh();
+        }
+    };
+
+    let outline = drive_outline(screw.drive);
+    let recess_ring = |z: f32| ring(z, |angle| recess * outline(angle));
+    let floor = top - height * 0.5;
+    mesh.surface(&[circle(top, edge), recess_ring(top)], true);
+    mesh.surface(
+        &[recess_ring(top), recess_ring(floor)],
+        screw.drive == ScrewDrive::Torx,
+    );
+    mesh.surface(
+        &[recess_ring(floor), circle(floor - recess * 0.3, 0.)],
+        true,
+    );
+    mesh.into_mesh()
+}
+
+/// Builds the mesh of a plain washer, lying on the XY plane.
+pub(crate) fn washer_mesh(washer: &Washer) -> Mesh {
+    let dims = washer.0.dims();
+    let (inner, outer) = (dims.clearance / 2., dims.washer_diameter / 2.);
+    let thickness = dims.washer_thickness;
+
+    let mut mesh = MeshBuilder::default();
+    mesh.lathe(&[
+        (inner, 0.),
+        (outer, 0.),
+        (outer, thickness),
+        (inner, thickness),
+        (inner, 0.),
+    ]);
+    mesh.into_mesh()
+}
+
+/// Builds the mesh of a hex nut, lying on the XY plane.
+pub(crate) fn nut_mesh(nut: &Nut) -> Mesh {
+    let dims = nut.0.dims();
+    let bore = dims.diameter / 2. - dims.pitch * THREAD_DEPTH;
+    let thickness = dims.nut_thickness;
+
+    let hex = hexagon(dims.nut_width / 2.);
+    let mut mesh = MeshBuilder::default();
+    mesh.surface(&[circle(0., bore), ring(0., hex)], true);
+    mesh.surface(&[ring(0., hex), ring(thickness, hex)], false);
+    mesh.surface(&[ring(thickness, hex), circle(thickness, bore)], true);
+    mesh.surface(&[circle(thickness, bore), circle(0., bore)], true);
+    mesh.into_mesh()
+}
+
+/// Builds the mesh of a hex standoff, with the face the female end is
+/// tapped into at the origin, and any male thread pointing down the Z axis.
+pub(crate) fn standoff_mesh(standoff: &Standoff) -> Mesh {
+    let dims = standoff.size.dims();
+    let (radius, length) = (dims.diameter / 2., standoff.length as f32);
+    let bore = radius - dims.pitch * THREAD_DEPTH;
+    let hex = hexagon(dims.nut_width / 2.);
+
+    let mut mesh = MeshBuilder::default();
+    mesh.surface(&[ring(0., hex), ring(length, hex)], false);
+    mesh.surface(&[ring(length, hex), circle(length, bore)], true);
+    match standoff.gender {
+        // Female-female standoffs are tapped all the way through.
+        StandoffGender::FemaleFemale => {
+            mesh.surface(&[circle(length, bore), circle(0., bore)], true);
+            mesh.surface(&[circle(0., bore), ring(0., hex)], true);
+        }
+        StandoffGender::MaleFemale => {
+            let depth = (dims.diameter * 2.).min(length * 0.8);
+            mesh.surface(&[circle(length, bore), circle(length - depth, bore)], true);
+            mesh.surface(
+                &[circle(length - depth, bore), circle(length - depth, 0.)],
+                true,
+            );
+
+            let (stud, chamfer) = (standoff.stud_length(), dims.pitch * THREAD_DEPTH);
+            mesh.lathe(&[
+                (0., -stud),
+                (radius - chamfer, -stud),
+                (radius, chamfer - stud),
+                (radius, 0.),
+            ]);
+            mesh.surface(&[circle(0., radius), ring(0., hex)], true);
+        }
+    }
+    mesh.into_mesh()
+}
+
+/// Builds the mesh of a heat-set insert, with its top, which is set flush
+/// with the surface of the part it is in, length mm up the Z axis.
+pub(crate) fn insert_mesh(insert: &Insert) -> Mesh {
+    let dims = insert.0.dims();
+    let (diameter, length) = insert.dims();
+    let bore = dims.diameter / 2. - dims.pitch * THREAD_DEPTH;
+
+    // The knurls twist in opposite directions in each half of the insert, so
+    // it resists both pulling out and turning.
+    let knurl = |z: f32| {
+        let twist = (z - length / 2.).abs() / length * 4.;
+        ring(z, move |angle| {
+            let phase = (angle / (2. * PI) * 12. + twist).rem_euclid(1.);
+            diameter / 2. * (1. - 0.08 * (1. - (2. * phase - 1.).abs()))
+        })
+    };
+    let rings: Vec<Vec<Vec3>> = (0..=16).map(|i| knurl(length * i as f32 / 16.)).collect();
+
+    let mut mesh = MeshBuilder::default();
+    mesh.surface(&rings, false);
+    mesh.surface(&[knurl(length), circle(length, bore)], true);
+    mesh.surface(&[circle(length, bore), circle(0., bore)], true);
+    mesh.surface(&[circle(0., bore), knurl(0.)], true);
+    mesh.into_mesh()
+}
+
+/// Builds the mesh of a nylon rivet or PCB support. Rivets have their tip at
+/// the origin, pointing down the Z axis, and the underside of their head
+/// length mm above it. Supports have the face the bottom panel rests on at
+/// the origin, with a pin pointing down through it.
+pub(crate) fn support_mesh(support: &Support) -> Mesh {
+    let hole = support.size.dims().clearance / 2.;
+    let (pin, barb, barb_length) = (hole * 0.95, hole * 1.3, hole * 1.6);
+    let length = support.length as f32;
+
+    let mut mesh = MeshBuilder::default();
+    match support.kind {
+        SupportKind::Rivet => {
+            // The barb flares out from the tip, and the head is domed.
+            let (head_radius, height) = (hole * 2.2, hole * 0.8);
+            mesh.lathe(&[
+                (0., -barb_length),
+                (pin * 0.8, -barb_length),
+                (barb, 0.),
+                (pin, 0.),
+                (pin, length),
+                (head_radius, length),
+                (head_radius, length + height * 0.3),
+            ]);
+            let dome: Vec<Vec<Vec3>> = (0..=8)
+                .map(|i| {
+                    let angle = i as f32 / 8. * PI / 2.;
+                    circle(
+                        length + height * (0.3 + 0.7 * angle.sin()),
+                        head_radius * angle.cos(),
+                    )
+                })
+                .collect();
+            mesh.surface(&dome, true);
+        }
+        SupportKind::PcbSupport => {
+            // A barbed pin goes through each panel, which is taken to be
+            // the default thickness.
+            let thickness = PanelDecorations::default().thickness;
+            let body = hole * 2.;
+            let top = length + thickness + barb_length;
+            mesh.lathe(&[
+                (0., -thickness - barb_length),
+                (pin * 0.8, -thickness - barb_length),
+                (barb, -thickness),
+                (pin, -thickness),
+                (pin, 0.),
+                (body, 0.),
+                (body, length),
+                (pin, length),
+                (pin, length + thickness),
+                (barb, length + thickness),
+                (pin * 0.8, top),
+                (0., top),
+            ]);
+        }
+    }
+    mesh.into_mesh()
+}
+
+// rethread rebuilds the meshes of screws when modelling of threads is
+// turned on or off.
+fn rethread(
+    threads: Res<ModelThreads>,
+    mut last: Local<Option<ModelThreads>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    screws: Query<(&Screw, &ScrewLength, &Children)>,
+    geometry: Query<&Handle<Mesh>, Without<Gizmo>>,
+) {
+    let changed = last.map(|l| l != *threads).unwrap_or(false);
+    *last = Some(*threads);
+    if !changed {
+        return;
+    }
+
+    for (screw, length, children) in screws.iter() {
+        for handle in children.iter().filter_map(|c| geometry.get(*c).ok()) {
+            if let Some(mesh) = meshes.get_mut(handle) {
+                *mesh = screw_mesh(screw, length.0, threads.0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parts::{Metric, StandoffMaterial};
+    use bevy::render::mesh::VertexAttributeValues;
+
+    const HEADS: [ScrewHead; 5] = [
+        ScrewHead::Pan,
+        ScrewHead::SocketCap,
+        ScrewHead::Countersunk,
+        ScrewHead::Button,
+        ScrewHead::Hex,
+    ];
+
+    fn screw(head: ScrewHead) -> Screw {
+        Screw {
+            size: Metric::M3,
+            head,
+            drive: ScrewDrive::default(),
+        }
+    }
+
+    // Checks a mesh is well formed, returning its positions.
+    fn check(mesh: &Mesh) -> Vec<[f32; 3]> {
+        let positions = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+            Some(VertexAttributeValues::Float3(positions)) => positions.clone(),
+            _ => panic!("mesh has no positions"),
+        };
+        let normals = match mesh.attribute(Mesh::ATTRIBUTE_NORMAL) {
+            Some(VertexAttributeValues::Float3(normals)) => normals.clone(),
+            _ => panic!("mesh has no normals"),
+        };
+        let indices = match mesh.indices() {
+            Some(Indices::U32(indices)) => indices.clone(),
+            _ => panic!("mesh has no 32-bit indices"),
+        };
+
+        assert_eq!(normals.len(), positions.len());
+        assert!(!indices.is_empty());
+        assert_eq!(indices.len() % 3, 0);
+        assert!(indices.iter().all(|i| (*i as usize) < positions.len()));
+        for n in normals.iter() {
+            assert!(n.iter().all(|c| c.is_finite()));
+            assert!((Vec3::from(*n).length() - 1.).abs() < 1e-4);
+        }
+        positions
+    }
+
+    // Returns the lowest and highest Z of a set of positions.
+    fn z_range(positions: &[[f32; 3]]) -> (f32, f32) {
+        positions.iter().fold((f32::MAX, f32::MIN), |(lo, hi), p| {
+            (lo.min(p[2]), hi.max(p[2]))
+        })
+    }
+
+    #[test]
+    fn screw_meshes_are_well_formed() {
+        for head in HEADS.iter() {
+            for threads in [false, true].iter() {
+                check(&screw_mesh(&screw(*head), 7, *threads));
+            }
+        }
+    }
+
+    #[test]
+    fn screws_are_as_long_as_their_length() {
+        // Countersunk heads are counted in the length.
+        let (bottom, top) = z_range(&check(&screw_mesh(
+            &screw(ScrewHead::Countersunk),
+            7,
+            false,
+        )));
+        assert!(bottom.abs() < 1e-4);
+        assert!((top - bottom - 7.).abs() < 1e-4);
+
+        // Other heads sit on top of the length.
+        for head in HEADS.iter().filter(|h| **h != ScrewHead::Countersunk) {
+            let (bottom, top) = z_range(&check(&screw_mesh(&screw(*head), 7, false)));
+            let (_, height) = head.dims(Metric::M3);
+            assert!(bottom.abs() < 1e-4);
+            assert!(top > 7. && top <= 7. + height + 1e-4, "{:?}", head);
+        }
+    }
+
+    #[test]
+    fn hardware_meshes_are_well_formed() {
+        check(&washer_mesh(&Washer(Metric::M3)));
+        check(&nut_mesh(&Nut(Metric::M3)));
+        check(&insert_mesh(&Insert(Metric::M3)));
+        for gender in [StandoffGender::FemaleFemale, StandoffGender::MaleFemale].iter() {
+            check(&standoff_mesh(&Standoff {
+                size: Metric::M3,
+                length: 10,
+                gender: *gender,
+                material: StandoffMaterial::Brass,
+            }));
+        }
+        for kind in [SupportKind::Rivet, SupportKind::PcbSupport].iter() {
+            check(&support_mesh(&Support {
+                size: Metric::M3,
+                length: 5,
+                kind: *kind,
+            }));
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use crate::interaction::{Selectable, Selection};
+use crate::interaction::{HotkeyEvent, Selectable, Selection};
 use bevy::prelude::*;
 use bevy_mod_raycast::{Intersection, Primitive3d};
 
@@ -42,6 +42,47 @@ impl Gizmo {
             ),
         }
     }
+
+    fn ring_transform(&self) -> (Vec3, Quat) {
+        match self {
+            Gizmo::X => (
+                Vec3::zero(),
+                Quat::from_rotation_y(std::f32::consts::PI / 2.),
+            ),
+            Gizmo::Y => (
+                Vec3::zero(),
+                Quat::from_rotation_x(-std::f32::consts::PI / 2.),
+            ),
+            Gizmo::Z => (Vec3::zero(), Quat::identity()),
+        }
+    }
+}
+
+/// Which set of gizmo handles is shown on the selected entity.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GizmoMode {
+    Translate,
+    Rotate,
+}
+
+/// Resource describing how the gizmo behaves.
+#[derive(Debug)]
+pub struct GizmoSettings {
+    pub mode: GizmoMode,
+    /// Increment that rotations are snapped to, or 0 for no snapping.
+    pub snap_degrees: f32,
+    /// Whether fasteners snap into panel holes as they are dragged.
+    pub snap_to_holes: bool,
+}
+
+impl Default for GizmoSettings {
+    fn default() -> Self {
+        Self {
+            mode: GizmoMode::Translate,
+            snap_degrees: 15.,
+            snap_to_holes: true,
+        }
+    }
 }
 
 /// Component that is present on translate gizmo handles.
@@ -114,22 +155,96 @@ impl TranslateHandle {
     }
 }
 
+/// Component that is present on rotate gizmo rings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RotateHandle {
+    X,
+    Y,
+    Z,
+}
+
+impl RotateHandle {
+    fn axis(&self) -> Vec3 {
+        match self {
+            RotateHandle::X => Vec3::unit_x(),
+            RotateHandle::Y => Vec3::unit_y(),
+            RotateHandle::Z => Vec3::unit_z(),
+        }
+    }
+
+    /// Returns the plane of the ring through pivot, facing both ways.
+    pub fn intersection_plane(&self, pivot: Vec3) -> (Primitive3d, Primitive3d) {
+        let normal = self.axis();
+        (
+            Primitive3d::Plane {
+                point: pivot,
+                normal: normal,
+            },
+            Primitive3d::Plane {
+                point: pivot,
+                normal: normal * Vec3::from([-1., -1., -1.]),
+            },
+        )
+    }
+
+    /// Computes the rotation about pivot after the ring has been dragged
+    /// from one point on its plane to another, snapping the angle to
+    /// snap_degrees.
+    pub fn calc_rotation(&self, pivot: Vec3, from: Vec3, to: Vec3, snap_degrees: f32) -> Quat {
+        let axis = self.axis();
+        let (a, b) = (from - pivot, to - pivot);
+        let (a, b) = (a - axis * a.dot(axis), b - axis * b.dot(axis));
+        if a.length() < 0.001 || b.length() < 0.001 {
+            return Quat::identity();
+        }
+
+        let mut angle = a.cross(b).dot(axis).atan2(a.dot(b));
+        if snap_degrees > 0. {
+            let step = snap_degrees.to_radians();
+            angle = (angle / step).round() * step;
+        }
+        Quat::from_axis_angle(axis, angle)
+    }
+}
+
 pub struct Plugin;
 
 impl bevy::prelude::Plugin for Plugin {
     fn build(&self, app: &mut AppBuilder) {
-        app.add_system(gizmo_update_visibility.system())
+        app.add_resource(GizmoSettings::default())
+            .add_system(gizmo_hotkeys.system())
+            .add_system(gizmo_update_visibility.system())
             .add_system_to_stage(stage::POST_UPDATE, gizmo_update_pos.system());
     }
 }
 
+fn gizmo_hotkeys(
+    ev_hotkey: Res<Events<HotkeyEvent>>,
+    mut hotkey_reader: Local<EventReader<HotkeyEvent>>,
+    mut settings: ResMut<GizmoSettings>,
+) {
+    for ev in hotkey_reader.iter(&ev_hotkey) {
+        if let HotkeyEvent::ToggleGizmo = ev {
+            settings.mode = match settings.mode {
+                GizmoMode::Translate => GizmoMode::Rotate,
+                GizmoMode::Rotate => GizmoMode::Translate,
+            };
+        }
+    }
+}
+
 fn gizmo_update_visibility(
     selection: Res<Selection>,
-    mut gizmos: Query<(&mut Visible, &Parent), With<Gizmo>>,
+    settings: Res<GizmoSettings>,
+    mut gizmos: Query<(&mut Visible, &Parent, Option<&RotateHandle>), With<Gizmo>>,
 ) {
-    let entity = selection.entity();
-    for (mut vis, parent) in gizmos.iter_mut() {
-        vis.is_visible = Some(parent.0) == entity;
+    for (mut vis, parent, ring) in gizmos.iter_mut() {
+        let mode = if ring.is_some() {
+            GizmoMode::Rotate
+        } else {
+            GizmoMode::Translate
+        };
+        vis.is_visible = selection.contains(parent.0) && settings.mode == mode;
     }
 }
 
@@ -139,16 +254,21 @@ fn gizmo_update_pos(
         &Parent,
         &Gizmo,
         Option<&TranslateHandle>,
+        Option<&RotateHandle>,
     )>,
-    parent_query: Query<&Transform, Without<Gizmo>>,
+    parent_query: Query<&GlobalTransform, Without<Gizmo>>,
 ) {
-    for (mut transform, parent, gizmo, handle) in gizmos.iter_mut() {
-        let (t, r) = if handle.is_some() {
+    for (mut transform, parent, gizmo, handle, ring) in gizmos.iter_mut() {
+        let (t, r) = if ring.is_some() {
+            gizmo.ring_transform()
+        } else if handle.is_some() {
             gizmo.handle_transform()
         } else {
             gizmo.arm_transform()
         };
         transform.rotation = r;
+        // Gizmos stay upright, but follow their part in world space, as it
+        // may be a member of a sub-assembly.
         if let Ok(base) = parent_query.get(parent.0) {
             transform.translation = t + base.translation;
         }
@@ -276,6 +396,37 @@ pub fn spawn_translate(
         .with(Gizmo::Z);
 }
 
+pub fn spawn_rotate(
+    commands: &mut ChildBuilder,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<StandardMaterial>>,
+) {
+    let ring = meshes.add(ring());
+
+    for (gizmo, handle, color) in &[
+        (Gizmo::X, RotateHandle::X, Color::rgb(1.0, 0.0, 0.0)),
+        (Gizmo::Y, RotateHandle::Y, Color::rgb(0.0, 1.0, 0.0)),
+        (Gizmo::Z, RotateHandle::Z, Color::rgb(0.0, 0.0, 1.0)),
+    ] {
+        let (_, rotation) = gizmo.ring_transform();
+        commands
+            .spawn(PbrBundle {
+                mesh: ring.clone(),
+                material: materials.add(color.clone().into()),
+                transform: Transform::from_rotation(rotation),
+                visible: Visible {
+                    is_visible: false,
+                    is_transparent: false,
+                },
+                ..Default::default()
+            })
+            .with(handle.clone())
+            .with(gizmo.clone())
+            .with(bevy_mod_picking::PickableMesh::default())
+            .with(Selectable);
+    }
+}
+
 use bevy::render::mesh::Indices;
 use bevy::render::pipeline::*;
 use genmesh::{generators::*, Triangulate};
@@ -342,3 +493,40 @@ fn cylinder() -> Mesh {
     mesh.set_indices(Some(indices));
     mesh
 }
+
+fn ring() -> Mesh {
+    torus(12., 0.35)
+}
+
+/// Builds a torus around the Z axis.
+pub(crate) fn torus(radius: f32, tubular_radius: f32) -> Mesh {
+    let s = Torus::new(radius, tubular_radius, 48, 8);
+
+    let positions: Vec<[f32; 3]> = s
+        .shared_vertex_iter()
+        .map(|v| [v.pos.x, v.pos.y, v.pos.z])
+        .collect();
+    let normals: Vec<[f32; 3]> = s
+        .shared_vertex_iter()
+        .map(|v| [v.normal.x, v.normal.y, v.normal.z])
+        .collect();
+    let uvs: Vec<[f32; 2]> = (0..s.shared_vertex_count())
+        .into_iter()
+        .map(|_| [0., 0.])
+        .collect();
+
+    let indices = Indices::U32(
+        s.indexed_polygon_iter()
+            .triangulate()
+            .map(|tr| vec![tr.x as u32, tr.y as u32, tr.z as u32])
+            .flatten()
+            .collect(),
+    );
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.set_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.set_indices(Some(indices));
+    mesh
+}
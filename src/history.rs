@@ -0,0 +1,1054 @@
+use bevy::prelude::*;
+
+use crate::constraints::{Constraint, Constraints};
+use crate::fastener::ModelThreads;
+use crate::interaction::{Selectable, Selection};
+use crate::parts::{LayerVisibility, PanelDecorations};
+use crate::storage::{ObjectComponents, ObjectRep};
+
+pub struct Plugin;
+
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(History::default())
+            .add_event::<EditEvent>()
+            .add_system(editor.system());
+    }
+}
+
+/// Maximum number of changes which can be undone.
+const MAX_UNDO: usize = 256;
+
+/// Requests an edit to the scene which is tracked in the history.
+#[derive(Debug)]
+pub enum EditEvent {
+    Undo,
+    Redo,
+    Delete(Vec<Entity>),
+    /// Gathers the entities into a new sub-assembly.
+    Assemble(Vec<Entity>),
+    /// Dissolves a sub-assembly, leaving its members in place.
+    Dissolve(Entity),
+    /// Replaces the members of a linked sub-assembly with the current
+    /// contents of its source file.
+    Reload(Entity),
+}
+
+/// A reversible change to the scene.
+#[derive(Debug, Clone)]
+pub enum Change {
+    Transform {
+        entity: Entity,
+        from: Transform,
+        to: Transform,
+    },
+    /// The decorations of a panel were edited.
+    Decorate {
+        entity: Entity,
+        from: PanelDecorations,
+        to: PanelDecorations,
+    },
+    /// The constraints on a part were added to or removed.
+    Constrain {
+        entity: Entity,
+        from: Vec<Constraint>,
+        to: Vec<Constraint>,
+    },
+    /// An object was spawned. Members holds the entities of the members of
+    /// a sub-assembly in depth-first order, and constraints those held by
+    /// the object or its members which are not part of rep.
+    Spawn {
+        entity: Entity,
+        members: Vec<Entity>,
+        parent: Option<Entity>,
+        rep: ObjectRep,
+        constraints: Vec<(Entity, Constraint)>,
+    },
+    /// An object was deleted, with the same fields as Spawn.
+    Delete {
+        entity: Entity,
+        members: Vec<Entity>,
+        parent: Option<Entity>,
+        rep: ObjectRep,
+        constraints: Vec<(Entity, Constraint)>,
+    },
+    /// Parts were gathered into a new sub-assembly.
+    Assemble {
+        group: Entity,
+        name: String,
+        source: Option<String>,
+        transform: Transform,
+        parent: Option<Entity>,
+        members: Vec<Entity>,
+    },
+    /// A sub-assembly was dissolved, leaving its members where they were.
+    Dissolve {
+        group: Entity,
+        name: String,
+        source: Option<String>,
+        transform: Transform,
+        parent: Option<Entity>,
+        members: Vec<Entity>,
+    },
+    /// Several changes made together, such as to a multi-selection.
+    Batch(Vec<Change>),
+}
+
+impl Change {
+    pub fn describe(&self) -> String {
+        match self {
+            Change::Transform { .. } => "Move".to_string(),
+            Change::Decorate { .. } => "Change panel".to_string(),
+            Change::Constrain { from, to, .. } if to.len() > from.len() => {
+                "Add constraint".to_string()
+            }
+            Change::Constrain { .. } => "Remove constraint".to_string(),
+            Change::Spawn { rep, .. } => format!("Spawn {}", rep.name()),
+            Change::Delete { rep, .. } => format!("Delete {}", rep.name()),
+            Change::Assemble { name, .. } => format!("Group {}", name),
+            Change::Dissolve { name, .. } => format!("Ungroup {}", name),
+            // A deletion followed by a spawn replaces an object, such as
+            // when a linked sub-assembly is reloaded.
+            Change::Batch(changes)
+                if changes.len() == 2 && matches!(changes[1], Change::Spawn { .. }) =>
+            {
+                match &changes[0] {
+                    Change::Delete { rep, .. } => format!("Reload {}", rep.name()),
+                    _ => format!("{} changes", changes.len()),
+                }
+            }
+            Change::Batch(changes) => match changes.first() {
+                Some(Change::Transform { .. }) => format!("Move {} objects", changes.len()),
+                Some(Change::Decorate { .. }) => format!("Change {} panels", changes.len()),
+                Some(Change::Delete { .. }) => format!("Delete {} objects", changes.len()),
+                Some(Change::Spawn { .. }) => format!("Spawn {} objects", changes.len()),
+                _ => format!("{} changes", changes.len()),
+            },
+        }
+    }
+
+    // Wraps the given changes into a batch if there is more than one.
+    pub(crate) fn from_changes(mut changes: Vec<Change>) -> Option<Change> {
+        match changes.len() {
+            0 => None,
+            1 => changes.pop(),
+            _ => Some(Change::Batch(changes)),
+        }
+    }
+
+    fn remap(&mut self, old: Entity, new: Entity) {
+        let fix = |entity: &mut Entity| {
+            if *entity == old {
+                *entity = new;
+            }
+        };
+        match self {
+            Change::Transform { entity, .. } | Change::Decorate { entity, .. } => fix(entity),
+            Change::Constrain { entity, from, to } => {
+                fix(entity);
+                for constraint in from.iter_mut().chain(to.iter_mut()) {
+                    fix(&mut constraint.target);
+                }
+            }
+            Change::Spawn {
+                entity,
+                members,
+                parent,
+                constraints,
+                ..
+            }
+            | Change::Delete {
+                entity,
+                members,
+                parent,
+                constraints,
+                ..
+            } => {
+                fix(entity);
+                members.iter_mut().for_each(fix);
+                if let Some(parent) = parent {
+                    fix(parent);
+                }
+                for (part, constraint) in constraints.iter_mut() {
+                    fix(part);
+                    fix(&mut constraint.target);
+                }
+            }
+            Change::Assemble {
+                group,
+                parent,
+                members,
+                ..
+            }
+            | Change::Dissolve {
+                group,
+                parent,
+                members,
+                ..
+            } => {
+                fix(group);
+                if let Some(parent) = parent {
+                    fix(parent);
+                }
+                members.iter_mut().for_each(fix);
+            }
+            Change::Batch(changes) => {
+                for change in changes.iter_mut() {
+                    change.remap(old, new);
+                }
+            }
+        }
+    }
+
+    // Returns true if the change refers to the entity.
+    fn mentions(&self, e: Entity) -> bool {
+        match self {
+            Change::Transform { entity, .. } | Change::Decorate { entity, .. } => *entity == e,
+            Change::Constrain { entity, from, to } => {
+                *entity == e || from.iter().chain(to.iter()).any(|c| c.target == e)
+            }
+            Change::Spawn {
+                entity,
+                members,
+                parent,
+                constraints,
+                ..
+            }
+            | Change::Delete {
+                entity,
+                members,
+                parent,
+                constraints,
+                ..
+            } => {
+                *entity == e
+                    || members.contains(&e)
+                    || *parent == Some(e)
+                    || constraints
+                        .iter()
+                        .any(|(part, c)| *part == e || c.target == e)
+            }
+            Change::Assemble {
+                group,
+                parent,
+                members,
+                ..
+            }
+            | Change::Dissolve {
+                group,
+                parent,
+                members,
+                ..
+            } => *group == e || *parent == Some(e) || members.contains(&e),
+            Change::Batch(changes) => changes.iter().any(|c| c.mentions(e)),
+        }
+    }
+
+    // Returns the transform changes which make up this change, if it is
+    // only made up of transform changes.
+    fn transforms_mut(&mut self) -> Option<Vec<(&mut Entity, &mut Transform)>> {
+        match self {
+            Change::Transform { entity, to, .. } => Some(vec![(entity, to)]),
+            Change::Batch(changes) => {
+                let mut out = Vec::with_capacity(changes.len());
+                for change in changes.iter_mut() {
+                    match change {
+                        Change::Transform { entity, to, .. } => out.push((entity, to)),
+                        _ => return None,
+                    }
+                }
+                Some(out)
+            }
+            _ => None,
+        }
+    }
+
+    // Returns the decoration changes which make up this change, if it is
+    // only made up of decoration changes.
+    fn decorations_mut(&mut self) -> Option<Vec<(&mut Entity, &mut PanelDecorations)>> {
+        match self {
+            Change::Decorate { entity, to, .. } => Some(vec![(entity, to)]),
+            Change::Batch(changes) => {
+                let mut out = Vec::with_capacity(changes.len());
+                for change in changes.iter_mut() {
+                    match change {
+                        Change::Decorate { entity, to, .. } => out.push((entity, to)),
+                        _ => return None,
+                    }
+                }
+                Some(out)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Resource which tracks changes to the scene, so they can be undone.
+#[derive(Debug, Default)]
+pub struct History {
+    undo: Vec<Change>,
+    redo: Vec<Change>,
+    // Set while successive transform or decoration changes to the same
+    // entities should be merged into one, such as while a value is being
+    // dragged.
+    open: bool,
+}
+
+impl History {
+    /// Records a change which has been made to the scene.
+    pub fn record(&mut self, change: Change) {
+        self.open = false;
+        self.redo.clear();
+        self.undo.push(change);
+        if self.undo.len() > MAX_UNDO {
+            self.undo.remove(0);
+        }
+    }
+
+    /// Records changes to the transforms of a set of entities, as
+    /// (entity, from, to). Entities which did not actually move are ignored.
+    /// If merge is set, the changes are merged with the previous change
+    /// while it concerns the same entities, until seal() is called.
+    pub fn record_transforms(&mut self, moves: Vec<(Entity, Transform, Transform)>, merge: bool) {
+        let moves: Vec<_> = moves
+            .into_iter()
+            .filter(|(_, from, to)| transform_changed(from, to))
+            .collect();
+
+        if merge && self.open {
+            if let Some(mut last) = self.undo.last_mut().and_then(|c| c.transforms_mut()) {
+                let same = last.len() == moves.len()
+                    && last
+                        .iter()
+                        .zip(moves.iter())
+                        .all(|((e, _), (m, _, _))| **e == *m);
+                if same {
+                    for ((_, last_to), (_, _, to)) in last.iter_mut().zip(moves.iter()) {
+                        **last_to = *to;
+                    }
+                    return;
+                }
+            }
+        }
+
+        let changes = moves
+            .into_iter()
+            .map(|(entity, from, to)| Change::Transform { entity, from, to })
+            .collect();
+        if let Some(change) = Change::from_changes(changes) {
+            self.record(change);
+            self.open = merge;
+        }
+    }
+
+    /// Records changes to the decorations of a set of panels, as
+    /// (entity, from, to). Merging works as for record_transforms().
+    pub fn record_decorations(
+        &mut self,
+        edits: Vec<(Entity, PanelDecorations, PanelDecorations)>,
+        merge: bool,
+    ) {
+        let edits: Vec<_> = edits
+            .into_iter()
+            .filter(|(_, from, to)| from != to)
+            .collect();
+
+        if merge && self.open {
+            if let Some(mut last) = self.undo.last_mut().and_then(|c| c.decorations_mut()) {
+                let same = last.len() == edits.len()
+                    && last
+                        .iter()
+                        .zip(edits.iter())
+                        .all(|((e, _), (m, _, _))| **e == *m);
+                if same {
+                    for ((_, last_to), (_, _, to)) in last.iter_mut().zip(edits.iter()) {
+                        **last_to = to.clone();
+                    }
+                    return;
+                }
+            }
+        }
+
+        let changes = edits
+            .into_iter()
+            .map(|(entity, from, to)| Change::Decorate { entity, from, to })
+            .collect();
+        if let Some(change) = Change::from_changes(changes) {
+            self.record(change);
+            self.open = merge;
+        }
+    }
+
+    /// Stops any further changes being merged into the last change.
+    pub fn seal(&mut self) {
+        self.open = false;
+    }
+
+    /// Changes which can be undone, most recent last.
+    pub fn undo_list(&self) -> &[Change] {
+        &self.undo
+    }
+
+    /// Changes which can be redone, most recent last.
+    pub fn redo_list(&self) -> &[Change] {
+        &self.redo
+    }
+
+    /// Returns true if a change which can be undone or redone refers to the
+    /// entity, so it may be respawned.
+    pub fn mentions(&self, entity: Entity) -> bool {
+        self.undo
+            .iter()
+            .chain(self.redo.iter())
+            .any(|c| c.mentions(entity))
+    }
+
+    // Updates references to an entity which has been respawned.
+    fn remap(&mut self, old: Entity, new: Entity) {
+        for change in self.undo.iter_mut().chain(self.redo.iter_mut()) {
+            change.remap(old, new);
+        }
+    }
+}
+
+/// Returns true if two transforms differ by more than rounding error.
+pub fn transform_changed(a: &Transform, b: &Transform) -> bool {
+    (a.translation - b.translation).length() > 0.0001
+        || a.rotation.dot(b.rotation).abs() < 0.999999
+        || (a.scale - b.scale).length() > 0.0001
+}
+
+// Applies a change to the scene, or reverts it if forward is false. Returns
+// the (old, new) entity pairs for any parts which were respawned, including
+// the members of sub-assemblies. Parts which cannot be respawned are
+// described in errors.
+fn apply(
+    change: &mut Change,
+    forward: bool,
+    errors: &mut Vec<String>,
+    selection: &mut Selection,
+    commands: &mut Commands,
+    threads: &ModelThreads,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<StandardMaterial>>,
+    textures: &mut ResMut<Assets<Texture>>,
+    layers: &LayerVisibility,
+    queries: &mut QuerySet<(
+        Query<ObjectComponents, With<Selectable>>,
+        Query<&mut Transform, With<Selectable>>,
+        Query<&mut PanelDecorations, With<Selectable>>,
+        Query<&mut Constraints, With<Selectable>>,
+    )>,
+    children: &Query<&Children>,
+) -> Vec<(Entity, Entity)> {
+    let (entity, members, parent, rep, constraints, create) = match change {
+        Change::Transform { entity, from, to } => {
+            if let Ok(mut transform) = queries.q1_mut().get_mut(*entity) {
+                *transform = if forward { *to } else { *from };
+            }
+            return vec![];
+        }
+        Change::Decorate { entity, from, to } => {
+            if let Ok(mut decorations) = queries.q2_mut().get_mut(*entity) {
+                *decorations = if forward { to.clone() } else { from.clone() };
+            }
+            return vec![];
+        }
+        Change::Constrain { entity, from, to } => {
+            // Parts are solved afresh against the restored constraints.
+            let restored = if forward { &*to } else { &*from };
+            let restored: Vec<Constraint> = restored
+                .iter()
+                .map(|c| Constraint::new(c.target, c.kind.clone()))
+                .collect();
+            let exists = queries.q0().get(*entity).is_ok();
+            match queries.q3_mut().get_mut(*entity) {
+                Ok(mut constraints) if !restored.is_empty() => constraints.0 = restored,
+                Ok(_) => {
+                    commands.remove_one::<Constraints>(*entity);
+                }
+                Err(_) if exists && !restored.is_empty() => {
+                    commands.insert_one(*entity, Constraints(restored));
+                }
+                Err(_) => {}
+            }
+            return vec![];
+        }
+        Change::Batch(changes) => {
+            let mut respawned = vec![];
+            let mut run = |change: &mut Change| {
+                respawned.extend(apply(
+                    change, forward, errors, selection, commands, threads, meshes, materials,
+                    textures, layers, queries, children,
+                ))
+            };
+            if forward {
+                changes.iter_mut().for_each(&mut run);
+            } else {
+                changes.iter_mut().rev().for_each(&mut run);
+            }
+            return respawned;
+        }
+        Change::Assemble { .. } | Change::Dissolve { .. } => {
+            let assemble = matches!(change, Change::Assemble { .. }) == forward;
+            return apply_grouping(
+                change, assemble, selection, commands, meshes, materials, queries, children,
+            );
+        }
+        Change::Spawn {
+            entity,
+            members,
+            parent,
+            rep,
+            constraints,
+        } => (entity, members, parent, rep, constraints, forward),
+        Change::Delete {
+            entity,
+            members,
+            parent,
+            rep,
+            constraints,
+        } => (entity, members, parent, rep, constraints, !forward),
+    };
+
+    if create {
+        if let Some(ev) = rep.clone().into_spawn() {
+            let order = match crate::parts::spawn_part(
+                &ev, commands, threads, meshes, materials, textures, layers,
+            ) {
+                Ok(order) => order,
+                Err(e) => {
+                    errors.push(format!("{}: {}", rep.name(), e));
+                    return vec![];
+                }
+            };
+            if let Some(parent) = parent {
+                commands.push_children(*parent, &order[..1]);
+            }
+
+            // The object was respawned from the same description it was
+            // captured from, so its entities come back in the same order.
+            let respawned: Vec<(Entity, Entity)> = std::iter::once(*entity)
+                .chain(members.iter().copied())
+                .zip(order.into_iter())
+                .collect();
+            let renamed = |e: Entity| {
+                respawned
+                    .iter()
+                    .find(|(old, _)| *old == e)
+                    .map_or(e, |(_, new)| *new)
+            };
+            let mut restored = constraints.clone();
+            for (part, constraint) in restored.iter_mut() {
+                *part = renamed(*part);
+                constraint.target = renamed(constraint.target);
+            }
+            crate::constraints::insert(commands, restored);
+            return respawned;
+        }
+    } else {
+        // Capture the current state, so a redo restores the part exactly
+        // as it was.
+        let (captured, order, external) =
+            crate::storage::capture_detached(*entity, queries.q0(), children);
+        if !matches!(captured, ObjectRep::None) {
+            *rep = captured;
+            *members = order[1..].to_vec();
+            *constraints = external;
+        }
+        selection.remove(*entity);
+        commands.despawn_recursive(*entity);
+    }
+    vec![]
+}
+
+// Applies an Assemble or Dissolve change, gathering the members into a
+// sub-assembly if assemble is set, and dissolving it otherwise.
+fn apply_grouping(
+    change: &mut Change,
+    assemble: bool,
+    selection: &mut Selection,
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<StandardMaterial>>,
+    queries: &mut QuerySet<(
+        Query<ObjectComponents, With<Selectable>>,
+        Query<&mut Transform, With<Selectable>>,
+        Query<&mut PanelDecorations, With<Selectable>>,
+        Query<&mut Constraints, With<Selectable>>,
+    )>,
+    children: &Query<&Children>,
+) -> Vec<(Entity, Entity)> {
+    let (group, name, source, transform, parent, members) = match change {
+        Change::Assemble {
+            group,
+            name,
+            source,
+            transform,
+            parent,
+            members,
+        }
+        | Change::Dissolve {
+            group,
+            name,
+            source,
+            transform,
+            parent,
+            members,
+        } => (group, name, source, transform, parent, members),
+        _ => return vec![],
+    };
+
+    if assemble {
+        let member_transforms: Vec<(Entity, Transform)> = members
+            .iter()
+            .filter_map(|e| queries.q0().get(*e).ok().map(|obj| (*e, *obj.0)))
+            .collect();
+        for (entity, _) in member_transforms.iter() {
+            selection.remove(*entity);
+        }
+        let new = crate::assembly::assemble(
+            commands,
+            meshes,
+            materials,
+            name.clone(),
+            source.clone(),
+            *transform,
+            *parent,
+            &member_transforms,
+        );
+        return vec![(*group, new)];
+    }
+
+    // Capture the current state, so the sub-assembly can be put back
+    // exactly as it was.
+    if let Ok(obj) = queries.q0().get(*group) {
+        *transform = *obj.0;
+        if let Some(sub_assembly) = crate::storage::sub_assembly(&obj) {
+            *name = sub_assembly.name.clone();
+            *source = sub_assembly.source.clone();
+        }
+    }
+    let mut member_transforms = vec![];
+    let mut gizmos = vec![];
+    if let Ok(group_children) = children.get(*group) {
+        for entity in group_children.iter() {
+            match queries.q0().get(*entity) {
+                Ok(obj) => {
+                    let t = *obj.0;
+                    let rep: ObjectRep = obj.into();
+                    if matches!(rep, ObjectRep::None) {
+                        gizmos.push(*entity);
+                    } else {
+                        member_transforms.push((*entity, t));
+                    }
+                }
+                Err(_) => gizmos.push(*entity),
+            }
+        }
+    }
+    *members = member_transforms.iter().map(|(e, _)| *e).collect();
+
+    selection.remove(*group);
+    crate::assembly::dissolve(
+        commands,
+        *group,
+        *transform,
+        *parent,
+        &member_transforms,
+        gizmos.into_iter(),
+    );
+    vec![]
+}
+
+fn editor(
+    ev_edit: Res<Events<EditEvent>>,
+    mut edit_reader: Local<EventReader<EditEvent>>,
+
+    mut history: ResMut<History>,
+    mut selection: ResMut<Selection>,
+    commands: &mut Commands,
+    threads: Res<ModelThreads>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut textures: ResMut<Assets<Texture>>,
+    layers: Res<LayerVisibility>,
+    mut queries: QuerySet<(
+        Query<ObjectComponents, With<Selectable>>,
+        Query<&mut Transform, With<Selectable>>,
+        Query<&mut PanelDecorations, With<Selectable>>,
+        Query<&mut Constraints, With<Selectable>>,
+    )>,
+    children: Query<&Children>,
+    parents: Query<&Parent>,
+    mut ev_dialog: ResMut<Events<crate::dialog_gui::DialogHotkeyEvent>>,
+) {
+    for ev in edit_reader.iter(&ev_edit) {
+        let (mut change, forward) = match ev {
+            EditEvent::Delete(entities) => {
+                let mut changes = vec![];
+                for entity in entities {
+                    if queries.q0().get(*entity).is_err() {
+                        continue;
+                    }
+                    let (rep, order, constraints) =
+                        crate::storage::capture_detached(*entity, queries.q0(), &children);
+                    selection.remove(*entity);
+                    commands.despawn_recursive(*entity);
+                    if !matches!(rep, ObjectRep::None) {
+                        changes.push(Change::Delete {
+                            entity: *entity,
+                            members: order[1..].to_vec(),
+                            parent: parents.get(*entity).ok().map(|p| p.0),
+                            rep,
+                            constraints,
+                        });
+                    }
+                }
+                if let Some(change) = Change::from_changes(changes) {
+                    history.record(change);
+                }
+                continue;
+            }
+
+            EditEvent::Assemble(entities) => {
+                let members: Vec<(Entity, Transform)> = entities
+                    .iter()
+                    .filter_map(|e| queries.q0().get(*e).ok().map(|obj| (*e, *obj.0)))
+                    .collect();
+                if members.is_empty() {
+                    continue;
+                }
+                let parent = parents.get(members[0].0).ok().map(|p| p.0);
+                if members
+                    .iter()
+                    .any(|(e, _)| parents.get(*e).ok().map(|p| p.0) != parent)
+                {
+                    eprintln!("Cannot group parts which belong to different sub-assemblies");
+                    continue;
+                }
+
+                // The sub-assembly is placed at the center of its members.
+                let center = members
+                    .iter()
+                    .fold(Vec3::zero(), |acc, (_, t)| acc + t.translation)
+                    / members.len() as f32;
+                let transform = Transform::from_translation(center);
+                let name = crate::assembly::SubAssembly::default().name;
+
+                let group = crate::assembly::assemble(
+                    commands,
+                    &mut meshes,
+                    &mut materials,
+                    name.clone(),
+                    None,
+                    transform,
+                    parent,
+                    &members,
+                );
+                *selection = Selection::Focused(vec![(group, transform)]);
+                history.record(Change::Assemble {
+                    group,
+                    name,
+                    source: None,
+                    transform,
+                    parent,
+                    members: members.iter().map(|(e, _)| *e).collect(),
+                });
+                continue;
+            }
+
+            EditEvent::Dissolve(entity) => match queries.q0().get(*entity) {
+                Ok(obj) if crate::storage::sub_assembly(&obj).is_some() => (
+                    Change::Dissolve {
+                        group: *entity,
+                        name: String::new(),
+                        source: None,
+                        transform: *obj.0,
+                        parent: parents.get(*entity).ok().map(|p| p.0),
+                        members: vec![],
+                    },
+                    true,
+                ),
+                _ => continue,
+            },
+
+            EditEvent::Reload(entity) => {
+                let (old, old_order, old_constraints) =
+                    crate::storage::capture_detached(*entity, queries.q0(), &children);
+                let mut problems = vec![];
+                let new = match &old {
+                    ObjectRep::Group {
+                        pos,
+                        name,
+                        source: Some(source),
+                        ..
+                    } => match crate::storage::load_linked(source, &mut problems) {
+                        Ok((objects, constraints)) => ObjectRep::Group {
+                            pos: pos.clone(),
+                            name: name.clone(),
+                            source: Some(source.clone()),
+                            objects,
+                            constraints,
+                        },
+                        Err(e) => {
+                            ev_dialog.send(crate::dialog_gui::DialogHotkeyEvent::SpawnErrors(
+                                vec![format!("Failed reloading {}: {}", source, e)],
+                            ));
+                            continue;
+                        }
+                    },
+                    _ => continue,
+                };
+                if !problems.is_empty() {
+                    let name = new.name();
+                    ev_dialog.send(crate::dialog_gui::DialogHotkeyEvent::SpawnErrors(
+                        problems
+                            .into_iter()
+                            .map(|p| format!("Reloading {}: {}", name, p))
+                            .collect(),
+                    ));
+                }
+                let parent = parents.get(*entity).ok().map(|p| p.0);
+
+                // The new contents are spawned first, so the old ones are
+                // kept if they cannot be.
+                let ev = new.clone().into_spawn().unwrap();
+                let new_order = match crate::parts::spawn_part(
+                    &ev,
+                    commands,
+                    &threads,
+                    &mut meshes,
+                    &mut materials,
+                    &mut textures,
+                    &layers,
+                ) {
+                    Ok(new_order) => new_order,
+                    Err(e) => {
+                        ev_dialog.send(crate::dialog_gui::DialogHotkeyEvent::SpawnErrors(vec![
+                            format!("Failed reloading {}: {}", new.name(), e),
+                        ]));
+                        continue;
+                    }
+                };
+                selection.remove(*entity);
+                commands.despawn_recursive(*entity);
+                if let Some(parent) = parent {
+                    commands.push_children(parent, &new_order[..1]);
+                }
+
+                history.record(Change::Batch(vec![
+                    Change::Delete {
+                        entity: *entity,
+                        members: old_order[1..].to_vec(),
+                        parent,
+                        rep: old,
+                        constraints: old_constraints,
+                    },
+                    Change::Spawn {
+                        entity: new_order[0],
+                        members: new_order[1..].to_vec(),
+                        parent,
+                        rep: new,
+                        constraints: vec![],
+                    },
+                ]));
+                continue;
+            }
+
+            EditEvent::Undo => match history.undo.pop() {
+                Some(change) => (change, false),
+                None => continue,
+            },
+            EditEvent::Redo => match history.redo.pop() {
+                Some(change) => (change, true),
+                None => continue,
+            },
+        };
+        history.open = false;
+
+        let mut errors = vec![];
+        let respawned = apply(
+            &mut change,
+            forward,
+            &mut errors,
+            &mut selection,
+            commands,
+            &threads,
+            &mut meshes,
+            &mut materials,
+            &mut textures,
+            &layers,
+            &mut queries,
+            &children,
+        );
+
+        // A change which could not be applied in full cannot be reversed
+        // either, so it is dropped.
+        if !errors.is_empty() {
+            ev_dialog.send(crate::dialog_gui::DialogHotkeyEvent::SpawnErrors(errors));
+        } else {
+            match ev {
+                EditEvent::Undo => history.redo.push(change),
+                EditEvent::Redo => history.undo.push(change),
+                _ => history.record(change),
+            }
+        }
+        for (old, new) in respawned {
+            history.remap(old, new);
+            // Constraints on a deleted part are kept, so they apply again
+            // once it is respawned.
+            for mut constraints in queries.q3_mut().iter_mut() {
+                if constraints.0.iter().any(|c| c.target == old) {
+                    for c in constraints.0.iter_mut().filter(|c| c.target == old) {
+                        c.target = new;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn moved(x: f32) -> Transform {
+        Transform::from_translation(Vec3::new(x, 0., 0.))
+    }
+
+    fn delete(entity: Entity, members: Vec<Entity>) -> Change {
+        Change::Delete {
+            entity,
+            members,
+            parent: None,
+            rep: ObjectRep::None,
+            constraints: vec![],
+        }
+    }
+
+    #[test]
+    fn record_clears_redo_and_limits_undo() {
+        let mut history = History::default();
+        history.redo.push(delete(Entity::new(1), vec![]));
+        for i in 0..MAX_UNDO + 10 {
+            history.record(delete(Entity::new(i as u32), vec![]));
+        }
+        assert!(history.redo_list().is_empty());
+        assert_eq!(history.undo_list().len(), MAX_UNDO);
+        // The oldest changes are the ones forgotten.
+        assert!(matches!(
+            history.undo_list()[0],
+            Change::Delete { entity, .. } if entity == Entity::new(10)
+        ));
+    }
+
+    #[test]
+    fn drags_merge_until_sealed() {
+        let mut history = History::default();
+        let e = Entity::new(1);
+        history.record_transforms(vec![(e, moved(0.), moved(1.))], true);
+        history.record_transforms(vec![(e, moved(1.), moved(2.))], true);
+        assert_eq!(history.undo_list().len(), 1);
+        match &history.undo_list()[0] {
+            Change::Transform { from, to, .. } => {
+                assert_eq!(from.translation.x, 0.);
+                assert_eq!(to.translation.x, 2.);
+            }
+            other => panic!("unexpected change {:?}", other),
+        }
+
+        history.seal();
+        history.record_transforms(vec![(e, moved(2.), moved(3.))], true);
+        assert_eq!(history.undo_list().len(), 2);
+    }
+
+    #[test]
+    fn moves_are_not_merged_across_entities_or_without_merge() {
+        let mut history = History::default();
+        let (a, b) = (Entity::new(1), Entity::new(2));
+        history.record_transforms(vec![(a, moved(0.), moved(1.))], true);
+        history.record_transforms(vec![(b, moved(0.), moved(1.))], true);
+        history.record_transforms(vec![(b, moved(1.), moved(2.))], false);
+        history.record_transforms(vec![(b, moved(2.), moved(3.))], false);
+        assert_eq!(history.undo_list().len(), 4);
+    }
+
+    #[test]
+    fn unchanged_transforms_are_not_recorded() {
+        let mut history = History::default();
+        history.record_transforms(vec![(Entity::new(1), moved(1.), moved(1.))], false);
+        assert!(history.undo_list().is_empty());
+    }
+
+    #[test]
+    fn remap_updates_members_and_constraints() {
+        let (group, member, panel, new_member) = (
+            Entity::new(1),
+            Entity::new(2),
+            Entity::new(3),
+            Entity::new(4),
+        );
+        let mut history = History::default();
+        history.record(Change::Transform {
+            entity: member,
+            from: moved(0.),
+            to: moved(1.),
+        });
+        history.record(Change::Batch(vec![Change::Delete {
+            entity: group,
+            members: vec![member],
+            parent: None,
+            rep: ObjectRep::None,
+            constraints: vec![(
+                panel,
+                Constraint::new(
+                    member,
+                    crate::constraints::ConstraintKind::Offset { distance: 1. },
+                ),
+            )],
+        }]));
+
+        history.remap(member, new_member);
+        assert!(matches!(
+            history.undo_list()[0],
+            Change::Transform { entity, .. } if entity == new_member
+        ));
+        match &history.undo_list()[1] {
+            Change::Batch(changes) => match &changes[0] {
+                Change::Delete {
+                    entity,
+                    members,
+                    constraints,
+                    ..
+                } => {
+                    assert_eq!(*entity, group);
+                    assert_eq!(members, &vec![new_member]);
+                    assert_eq!(constraints[0].0, panel);
+                    assert_eq!(constraints[0].1.target, new_member);
+                }
+                other => panic!("unexpected change {:?}", other),
+            },
+            other => panic!("unexpected change {:?}", other),
+        }
+    }
+
+    #[test]
+    fn deleted_targets_are_mentioned_until_forgotten() {
+        let (part, target) = (Entity::new(1), Entity::new(2));
+        let mut history = History::default();
+        history.record(Change::Constrain {
+            entity: part,
+            from: vec![],
+            to: vec![Constraint::new(
+                target,
+                crate::constraints::ConstraintKind::Offset { distance: 1. },
+            )],
+        });
+        assert!(history.mentions(target));
+        assert!(!history.mentions(Entity::new(3)));
+
+        history.undo.clear();
+        assert!(!history.mentions(target));
+    }
+}
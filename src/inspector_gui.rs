@@ -10,6 +10,7 @@ impl bevy::prelude::Plugin for Plugin {
     fn build(&self, app: &mut AppBuilder) {
         app.add_plugin(EguiPlugin)
             .add_event::<SpawnPartEvent>()
+            .add_event::<SpawnSceneEvent>()
             .add_event::<FocusUIEvent>()
             .add_resource(GUIState::default())
             .add_resource(WidgetIDs::default())
@@ -21,12 +22,52 @@ pub struct Library(pub Vec<PanelInfo>);
 
 #[derive(Debug)]
 pub enum SpawnPartEvent {
-    Panel(PanelInfo, bool, [f32; 3], Option<Transform>),
+    Panel(PanelInfo, bool, parts::PanelDecorations, Option<Transform>),
     Screw(parts::Screw, usize, Option<Transform>),
     Washer(parts::Washer, Option<Transform>),
     Nut(parts::Nut, Option<Transform>),
+    Standoff(parts::Standoff, Option<Transform>),
+    Insert(parts::Insert, Option<Transform>),
+    Support(parts::Support, Option<Transform>),
+    /// A named sub-assembly containing the given parts and the constraints
+    /// between them, and the file it is linked to if any.
+    Group(
+        String,
+        Option<String>,
+        Vec<SpawnPartEvent>,
+        Vec<crate::constraints::ConstraintRep>,
+        Option<Transform>,
+    ),
 }
 
+impl SpawnPartEvent {
+    /// Returns the transform the part should be spawned with.
+    pub fn transform(&self) -> Transform {
+        match self {
+            SpawnPartEvent::Panel(_, _, _, Some(t))
+            | SpawnPartEvent::Screw(_, _, Some(t))
+            | SpawnPartEvent::Washer(_, Some(t))
+            | SpawnPartEvent::Nut(_, Some(t))
+            | SpawnPartEvent::Standoff(_, Some(t))
+            | SpawnPartEvent::Insert(_, Some(t))
+            | SpawnPartEvent::Support(_, Some(t))
+            | SpawnPartEvent::Group(_, _, _, _, Some(t)) => t.clone(),
+            SpawnPartEvent::Panel(_, _, _, None) | SpawnPartEvent::Group(_, _, _, _, None) => {
+                Transform::identity()
+            }
+            _ => Transform::from_translation(Vec3::new(0., 10., 0.)),
+        }
+    }
+}
+
+/// Spawns the objects of an assembly, along with the constraints between
+/// them.
+#[derive(Debug)]
+pub struct SpawnSceneEvent(
+    pub Vec<SpawnPartEvent>,
+    pub Vec<crate::constraints::ConstraintRep>,
+);
+
 #[derive(Debug)]
 pub enum FocusUIEvent {
     TranslateInput,
@@ -36,13 +77,20 @@ pub enum FocusUIEvent {
 struct GUIState {
     pub spawn_selected: usize,
     pub spawn_mm: u32,
+    pub spawn_head: parts::ScrewHead,
+    pub spawn_drive: parts::ScrewDrive,
+    pub spawn_standoff: parts::Standoff,
+    pub spawn_support: parts::SupportKind,
 
     pub spawn_panel_hull: bool,
-    pub spawn_panel_color: [f32; 3],
+    pub spawn_panel: parts::PanelDecorations,
 
     pub translation: Vec3,
     pub rotation: Vec4,
     pub cur_axis: Option<TranslateHandle>,
+    /// Decorations of the selected panel which are being dragged, and which
+    /// are applied once the drag ends.
+    pub dragged_decorations: Option<(Entity, parts::PanelDecorations)>,
 }
 
 impl Default for GUIState {
@@ -50,11 +98,16 @@ impl Default for GUIState {
         Self {
             spawn_selected: 0,
             spawn_mm: 12,
+            spawn_head: parts::ScrewHead::default(),
+            spawn_drive: parts::ScrewDrive::default(),
+            spawn_standoff: parts::Standoff::default(),
+            spawn_support: parts::SupportKind::default(),
             spawn_panel_hull: false,
-            spawn_panel_color: [0.1, 0.4, 0.1],
+            spawn_panel: parts::PanelDecorations::default(),
             translation: Vec3::default(),
             rotation: Vec4::default(),
             cur_axis: None,
+            dragged_decorations: None,
         }
     }
 }
@@ -81,7 +134,6 @@ enum RotationAction {
 }
 
 fn ui(
-    commands: &mut Commands,
     library: Res<Library>,
 
     mut egui_context: ResMut<EguiContext>,
@@ -98,26 +150,91 @@ fn ui(
             Option<&crate::parts::Screw>,
             Option<&crate::parts::Washer>,
             Option<&crate::parts::Nut>,
+            Option<&crate::parts::Standoff>,
+            Option<&crate::parts::Insert>,
+            Option<&crate::parts::Support>,
+            Option<&mut crate::parts::PanelDecorations>,
         ),
         With<crate::interaction::Selectable>,
     >,
 
-    mut spawner: ResMut<Events<SpawnPartEvent>>,
-    mut ev_dialog: ResMut<Events<crate::dialog_gui::DialogHotkeyEvent>>,
-    mut ev_storage: ResMut<Events<crate::storage::StorageEvent>>,
+    mut groups: Query<&mut crate::assembly::SubAssembly>,
+    parents: Query<&Parent>,
+    constraints: Query<&crate::constraints::Constraints>,
+
+    mut history: ResMut<crate::history::History>,
+    settings: (
+        ResMut<crate::gizmo::GizmoSettings>,
+        ResMut<crate::parts::LayerVisibility>,
+        ResMut<crate::fastener::ModelThreads>,
+    ),
+    hole_selection: Res<crate::stackup::HoleSelection>,
+    mouse: Res<Input<MouseButton>>,
+    out_events: (
+        ResMut<Events<SpawnPartEvent>>,
+        ResMut<Events<crate::dialog_gui::DialogHotkeyEvent>>,
+        ResMut<Events<crate::storage::StorageEvent>>,
+        ResMut<Events<crate::history::EditEvent>>,
+        ResMut<Events<crate::constraints::ConstraintEvent>>,
+        ResMut<Events<crate::stackup::FastenEvent>>,
+        ResMut<Events<crate::export::ExportMeshEvent>>,
+        ResMut<Events<crate::spec_editor::EditSpecEvent>>,
+    ),
 ) {
-    let selected = match sel.entity() {
-        Some(e) => {
-            if let Ok(e) = sel_query.get_mut(e) {
-                state.translation = e.0.translation.clone();
-                state.rotation = e.0.rotation.into();
-                Some(e)
-            } else {
-                None
+    let (
+        mut spawner,
+        mut ev_dialog,
+        mut ev_storage,
+        mut ev_edit,
+        mut ev_constraint,
+        mut ev_fasten,
+        mut ev_export,
+        mut ev_spec,
+    ) = out_events;
+    let (mut gizmo_settings, mut layers, mut threads) = settings;
+    // The inspector shows the primary selection; edits to it are applied to
+    // the whole selection.
+    let (selected, object_name, decorations) =
+        match sel.entity().and_then(|e| sel_query.get_mut(e).ok()) {
+            Some((transform, pcb, screw, washer, nut, standoff, insert, support, decorations)) => {
+                state.translation = transform.translation.clone();
+                state.rotation = transform.rotation.into();
+                let name = match (pcb, screw, washer, nut, standoff, insert, support) {
+                    (_, Some(screw), ..) => format!("{} {} screw", screw, screw.head.name()),
+                    (_, _, Some(washer), ..) => format!("{} washer", washer),
+                    (_, _, _, Some(nut), ..) => format!("{} nut", nut),
+                    (_, _, _, _, Some(standoff), ..) => format!("{} standoff", standoff),
+                    (_, _, _, _, _, Some(insert), _) => format!("{} heat-set insert", insert),
+                    (_, _, _, _, _, _, Some(support)) => support.to_string(),
+                    (Some(pcb), ..) => format!("{}", pcb.name()),
+                    _ => "<none>".to_string(),
+                };
+                (Some(*transform), name, decorations.map(|d| d.clone()))
             }
+            None => (None, "<none>".to_string(), None),
+        };
+    let mut edited_decorations = match state.dragged_decorations.take() {
+        Some((entity, dragged)) if decorations.is_some() && Some(entity) == sel.entity() => {
+            Some(dragged)
         }
+        _ => decorations.clone(),
+    };
+    let mut dragging_decorations = false;
+    let group_name = match sel.entity() {
+        Some(e) => groups.get_mut(e).ok().map(|g| g.name.clone()),
         None => None,
     };
+    let object_name = match (sel.members().len(), group_name) {
+        (0, _) | (1, None) => object_name,
+        (1, Some(name)) => name,
+        (n, _) => format!("{} objects", n),
+    };
+    // The sub-assembly whose members are being edited, if any.
+    let editing_group = sel
+        .entity()
+        .and_then(|e| parents.get(e).ok())
+        .map(|p| p.0)
+        .filter(|p| groups.get_mut(*p).map(|g| g.expanded).unwrap_or(false));
     if sel.is_dragging() {
         if let Some(h) = sel.gizmo_handle() {
             state.cur_axis = Some(h);
@@ -145,22 +262,47 @@ fn ui(
                 .show(ui, |ui| {
                     ui.horizontal(|ui| {
                         ui.label("Object:");
-                        ui.label(match selected {
-                            Some((_, _, Some(screw), _, _)) => {
-                                format!("{:?} screw", screw)
-                            }
-                            Some((_, _, _, Some(washer), _)) => {
-                                format!("{:?} washer", washer)
+                        ui.label(object_name.as_str());
+                    });
+                    if let Some(entity) = sel.entity() {
+                        if let Ok(mut group) = groups.get_mut(entity) {
+                            ui.horizontal(|ui| {
+                                ui.label("Name:");
+                                ui.text_edit_singleline(&mut group.name);
+                            });
+                            ui.checkbox(&mut group.expanded, "Edit members");
+
+                            let mut unlink = false;
+                            if let Some(source) = &group.source {
+                                ui.horizontal(|ui| {
+                                    ui.label("Linked to:");
+                                    ui.label(source.split("/").last().unwrap());
+                                });
+                                ui.horizontal(|ui| {
+                                    if ui.small_button("Reload").clicked() {
+                                        ev_edit.send(crate::history::EditEvent::Reload(entity));
+                                    }
+                                    unlink = ui.small_button("Unlink").clicked();
+                                });
                             }
-                            Some((_, _, _, _, Some(nut))) => {
-                                format!("{:?} nut", nut)
+                            if unlink {
+                                group.source = None;
                             }
-                            Some((_, Some(pcb), _, _, _)) => {
-                                format!("{}", pcb.name())
+                        }
+                    }
+                    if let Some(decorations) = &mut edited_decorations {
+                        dragging_decorations = decorations_ui(ui, decorations);
+                    }
+                    if let Some(entity) = editing_group {
+                        if let Ok(mut group) = groups.get_mut(entity) {
+                            if ui
+                                .button(format!("Finish editing {}", group.name))
+                                .clicked()
+                            {
+                                group.expanded = false;
                             }
-                            _ => "<none>".to_string(),
-                        });
-                    });
+                        }
+                    }
 
                     ui.separator();
                     ui.columns(3, |columns| {
@@ -229,13 +371,31 @@ fn ui(
                     ui.allocate_space(egui::Vec2::new(0., 1.));
                     ui.separator();
                     ui.allocate_space(egui::Vec2::new(0., 1.));
+                    gizmo_ui(ui, &mut gizmo_settings);
 
-                    if ui.button("Delete").clicked() {
-                        if let Some(sel) = sel.entity() {
-                            state.cur_axis = None;
-                            commands.despawn_recursive(sel);
+                    ui.allocate_space(egui::Vec2::new(0., 1.));
+                    ui.separator();
+                    ui.allocate_space(egui::Vec2::new(0., 1.));
+
+                    ui.horizontal(|ui| {
+                        if ui.button("Delete").clicked() {
+                            let entities = sel.entities();
+                            if entities.len() > 0 {
+                                state.cur_axis = None;
+                                ev_edit.send(crate::history::EditEvent::Delete(entities));
+                            }
                         }
-                    }
+                        if sel.members().len() > 1 && ui.button("Group").clicked() {
+                            ev_edit.send(crate::history::EditEvent::Assemble(sel.entities()));
+                        }
+                        if let Some(group) = sel.entity().filter(|e| groups.get_mut(*e).is_ok()) {
+                            if sel.members().len() == 1 && ui.button("Ungroup").clicked() {
+                                ev_edit.send(crate::history::EditEvent::Dissolve(group));
+                            }
+                        }
+                    });
+                    constraints_ui(ui, &sel, &constraints, &mut ev_constraint);
+                    hole_ui(ui, &hole_selection, &mut ev_fasten);
                     ui.allocate_space(egui::Vec2::new(0., 4.));
                 });
 
@@ -249,54 +409,110 @@ fn ui(
                         {
                             state.spawn_selected = 0;
                         };
-                        if ui
-                            .selectable_label(state.spawn_selected == 1, "m3")
-                            .clicked()
-                        {
-                            state.spawn_selected = 1;
-                        };
-                        if ui
-                            .selectable_label(state.spawn_selected == 2, "m5")
-                            .clicked()
-                        {
-                            state.spawn_selected = 2;
-                        };
+                        // Hardware sizes follow the panel, in order.
+                        for (i, size) in parts::Metric::ALL.iter().enumerate() {
+                            if ui
+                                .selectable_label(
+                                    state.spawn_selected == i + 1,
+                                    size.to_string().to_lowercase(),
+                                )
+                                .clicked()
+                            {
+                                state.spawn_selected = i + 1;
+                            };
+                        }
                     });
 
                     if state.spawn_selected >= 1 {
+                        let size = parts::Metric::ALL[state.spawn_selected - 1];
                         ui.add(
                             egui::Slider::u32(&mut state.spawn_mm, 6..=60)
                                 .smallest_positive(2.0)
                                 .text("mm"),
                         );
+                        egui::combo_box_with_label(ui, "Head", state.spawn_head.name(), |ui| {
+                            for h in parts::ScrewHead::ALL.iter() {
+                                ui.selectable_value(&mut state.spawn_head, *h, h.name());
+                            }
+                        });
+                        if state.spawn_head != parts::ScrewHead::Hex {
+                            egui::combo_box_with_label(
+                                ui,
+                                "Drive",
+                                state.spawn_drive.name(),
+                                |ui| {
+                                    for d in parts::ScrewDrive::ALL.iter() {
+                                        ui.selectable_value(&mut state.spawn_drive, *d, d.name());
+                                    }
+                                },
+                            );
+                        }
+                        ui.checkbox(&mut threads.0, "Model threads");
                         ui.columns(3, |columns| {
                             if columns[0].add(egui::Button::new("screw")).clicked() {
                                 spawner.send(SpawnPartEvent::Screw(
-                                    match state.spawn_selected {
-                                        1 => parts::Screw::M3,
-                                        2 => parts::Screw::M5,
-                                        _ => unreachable!(),
+                                    parts::Screw {
+                                        size,
+                                        head: state.spawn_head,
+                                        drive: state.spawn_drive,
                                     },
                                     state.spawn_mm as usize,
                                     None,
                                 ));
                             };
                             if columns[1].add(egui::Button::new("washer")).clicked() {
-                                spawner.send(SpawnPartEvent::Washer(
-                                    match state.spawn_selected {
-                                        1 => parts::Washer::M3,
-                                        2 => parts::Washer::M5,
-                                        _ => unreachable!(),
+                                spawner.send(SpawnPartEvent::Washer(parts::Washer(size), None));
+                            };
+                            if columns[2].add(egui::Button::new("nut")).clicked() {
+                                spawner.send(SpawnPartEvent::Nut(parts::Nut(size), None));
+                            };
+                        });
+
+                        ui.separator();
+                        let standoff = &mut state.spawn_standoff;
+                        ui.horizontal(|ui| {
+                            for g in parts::StandoffGender::ALL.iter() {
+                                ui.selectable_value(&mut standoff.gender, *g, g.name());
+                            }
+                            egui::combo_box_with_label(
+                                ui,
+                                "Standoff material",
+                                standoff.material.name(),
+                                |ui| {
+                                    for m in parts::StandoffMaterial::ALL.iter() {
+                                        ui.selectable_value(&mut standoff.material, *m, m.name());
+                                    }
+                                },
+                            );
+                        });
+                        ui.horizontal(|ui| {
+                            for k in parts::SupportKind::ALL.iter() {
+                                ui.selectable_value(&mut state.spawn_support, *k, k.name());
+                            }
+                        });
+                        ui.columns(3, |columns| {
+                            if columns[0].add(egui::Button::new("standoff")).clicked() {
+                                spawner.send(SpawnPartEvent::Standoff(
+                                    parts::Standoff {
+                                        size,
+                                        length: state.spawn_mm as usize,
+                                        ..state.spawn_standoff
                                     },
                                     None,
                                 ));
                             };
-                            if columns[2].add(egui::Button::new("nut")).clicked() {
-                                spawner.send(SpawnPartEvent::Nut(
-                                    match state.spawn_selected {
-                                        1 => parts::Nut::M3,
-                                        2 => parts::Nut::M5,
-                                        _ => unreachable!(),
+                            if columns[1].add(egui::Button::new("insert")).clicked() {
+                                spawner.send(SpawnPartEvent::Insert(parts::Insert(size), None));
+                            };
+                            if columns[2]
+                                .add(egui::Button::new(state.spawn_support.name()))
+                                .clicked()
+                            {
+                                spawner.send(SpawnPartEvent::Support(
+                                    parts::Support {
+                                        size,
+                                        length: state.spawn_mm as usize,
+                                        kind: state.spawn_support,
                                     },
                                     None,
                                 ));
@@ -306,23 +522,26 @@ fn ui(
                         for panel in library.0.iter() {
                             ui.columns(2, |columns| {
                                 columns[0].label(format!("{}", panel.name()));
-                                columns[1].with_layout(
-                                    egui::Layout::top_down(egui::Align::Max),
-                                    |ui| {
-                                        if panel.well_formed() {
-                                            if ui.small_button("+").clicked() {
-                                                spawner.send(SpawnPartEvent::Panel(
-                                                    panel.clone(),
-                                                    state.spawn_panel_hull,
-                                                    state.spawn_panel_color,
-                                                    None,
-                                                ));
-                                            }
-                                        } else {
-                                            ui.colored_label(egui::Color32::RED, ":/");
+                                columns[1].with_layout(egui::Layout::right_to_left(), |ui| {
+                                    if panel.well_formed() {
+                                        if ui.small_button("+").clicked() {
+                                            spawner.send(SpawnPartEvent::Panel(
+                                                panel.clone(),
+                                                state.spawn_panel_hull,
+                                                state.spawn_panel.clone(),
+                                                None,
+                                            ));
                                         }
-                                    },
-                                );
+                                    } else {
+                                        ui.colored_label(egui::Color32::RED, ":/")
+                                            .on_hover_ui(|ui| spec_error_ui(ui, panel));
+                                    }
+                                    if ui.small_button("Edit").clicked() {
+                                        ev_spec.send(crate::spec_editor::EditSpecEvent(
+                                            panel.path().to_string(),
+                                        ));
+                                    }
+                                });
                             });
                         }
                         ui.with_layout(egui::Layout::top_down(egui::Align::Max), |ui| {
@@ -332,10 +551,8 @@ fn ui(
                         });
                         ui.separator();
 
-                        ui.horizontal(|ui| {
-                            ui.checkbox(&mut state.spawn_panel_hull, "Convex hull");
-                            ui.color_edit_button_rgb(&mut state.spawn_panel_color);
-                        });
+                        ui.checkbox(&mut state.spawn_panel_hull, "Convex hull");
+                        decorations_ui(ui, &mut state.spawn_panel);
                     }
                 });
 
@@ -349,73 +566,167 @@ fn ui(
                         if ui.button("Save As").clicked() {
                             ev_storage.send(crate::storage::StorageEvent::Save);
                         }
+                        if ui.button("Export BOM").clicked() {
+                            ev_storage.send(crate::storage::StorageEvent::ExportBom);
+                        }
+                        if ui.button("Export mesh").clicked() {
+                            ev_export.send(crate::export::ExportMeshEvent);
+                        }
+                    });
+                });
+
+            egui::CollapsingHeader::new("Layers")
+                .default_open(false)
+                .show(ui, |ui| layers_ui(ui, &mut layers));
+
+            egui::CollapsingHeader::new("History")
+                .default_open(false)
+                .show(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        if ui.button("Undo").clicked() {
+                            ev_edit.send(crate::history::EditEvent::Undo);
+                        }
+                        if ui.button("Redo").clicked() {
+                            ev_edit.send(crate::history::EditEvent::Redo);
+                        }
                     });
+                    ui.separator();
+
+                    // Clicking an entry undoes or redoes up to that point.
+                    for (i, change) in history.redo_list().iter().enumerate() {
+                        if ui.selectable_label(false, change.describe()).clicked() {
+                            for _ in i..history.redo_list().len() {
+                                ev_edit.send(crate::history::EditEvent::Redo);
+                            }
+                        }
+                    }
+                    for (i, change) in history.undo_list().iter().rev().enumerate() {
+                        if ui.selectable_label(i == 0, change.describe()).clicked() {
+                            for _ in 0..i {
+                                ev_edit.send(crate::history::EditEvent::Undo);
+                            }
+                        }
+                    }
                 });
         });
 
-    if let Some(mut selected) = selected {
-        selected.0.translation = state.translation.clone();
+    // While the selection is being dragged, the gizmo owns its transform,
+    // and records the move once the drag ends.
+    if let Some(before) = selected.filter(|_| !sel.is_dragging()) {
+        let mut after = before;
+        after.translation = state.translation.clone();
         if reset_rotation {
-            selected.0.rotation = Quat::identity();
+            after.rotation = Quat::identity();
         } else {
             let rotation: Quat = state.rotation.clone().into();
-            selected.0.rotation = rotation.normalize();
+            after.rotation = rotation.normalize();
             if rotation.is_nan() {
-                selected.0.rotation = Quat::identity();
+                after.rotation = Quat::identity();
             }
         }
 
         match rotation_action_x {
             RotationAction::Add => {
-                selected.0.rotation *= Quat::from_rotation_x(std::f32::consts::PI / 20.);
+                after.rotation *= Quat::from_rotation_x(std::f32::consts::PI / 20.);
             }
             RotationAction::Sub => {
-                selected.0.rotation *= Quat::from_rotation_x(-std::f32::consts::PI / 20.);
+                after.rotation *= Quat::from_rotation_x(-std::f32::consts::PI / 20.);
             }
             RotationAction::Negate => {
-                selected.0.rotation *= Quat::from_xyzw(1., 0., 0., 0.);
+                after.rotation *= Quat::from_xyzw(1., 0., 0., 0.);
             }
             RotationAction::Reset => {
-                let mut tmp: [f32; 4] = selected.0.rotation.into();
+                let mut tmp: [f32; 4] = after.rotation.into();
                 tmp[0] = 0.;
-                selected.0.rotation = Quat::from(tmp).normalize();
+                after.rotation = Quat::from(tmp).normalize();
             }
             _ => {}
         }
         match rotation_action_y {
             RotationAction::Add => {
-                selected.0.rotation *= Quat::from_rotation_y(std::f32::consts::PI / 20.);
+                after.rotation *= Quat::from_rotation_y(std::f32::consts::PI / 20.);
             }
             RotationAction::Sub => {
-                selected.0.rotation *= Quat::from_rotation_y(-std::f32::consts::PI / 20.);
+                after.rotation *= Quat::from_rotation_y(-std::f32::consts::PI / 20.);
             }
             RotationAction::Negate => {
-                selected.0.rotation *= Quat::from_xyzw(0., 1., 0., 0.);
+                after.rotation *= Quat::from_xyzw(0., 1., 0., 0.);
             }
             RotationAction::Reset => {
-                let mut tmp: [f32; 4] = selected.0.rotation.into();
+                let mut tmp: [f32; 4] = after.rotation.into();
                 tmp[1] = 0.;
-                selected.0.rotation = Quat::from(tmp).normalize();
+                after.rotation = Quat::from(tmp).normalize();
             }
             _ => {}
         }
         match rotation_action_z {
             RotationAction::Add => {
-                selected.0.rotation *= Quat::from_rotation_z(std::f32::consts::PI / 20.);
+                after.rotation *= Quat::from_rotation_z(std::f32::consts::PI / 20.);
             }
             RotationAction::Sub => {
-                selected.0.rotation *= Quat::from_rotation_z(-std::f32::consts::PI / 20.);
+                after.rotation *= Quat::from_rotation_z(-std::f32::consts::PI / 20.);
             }
             RotationAction::Negate => {
-                selected.0.rotation *= Quat::from_xyzw(0., 0., 1., 0.);
+                after.rotation *= Quat::from_xyzw(0., 0., 1., 0.);
             }
             RotationAction::Reset => {
-                let mut tmp: [f32; 4] = selected.0.rotation.into();
+                let mut tmp: [f32; 4] = after.rotation.into();
                 tmp[2] = 0.;
-                selected.0.rotation = Quat::from(tmp).normalize();
+                after.rotation = Quat::from(tmp).normalize();
             }
             _ => {}
         }
+
+        if crate::history::transform_changed(&before, &after) {
+            // Apply the same change to every member, rotating about their
+            // centroid.
+            let entities = sel.entities();
+            let positions: Vec<Vec3> = entities
+                .iter()
+                .filter_map(|e| sel_query.get_mut(*e).ok().map(|t| t.0.translation))
+                .collect();
+            let pivot = if positions.len() > 1 {
+                positions.iter().fold(Vec3::zero(), |acc, p| acc + *p) / positions.len() as f32
+            } else {
+                before.translation
+            };
+            let rotation = after.rotation * before.rotation.conjugate();
+            let offset = after.translation - pivot - rotation * (before.translation - pivot);
+
+            let mut moves = Vec::with_capacity(entities.len());
+            for entity in entities {
+                if let Ok(mut t) = sel_query.get_mut(entity) {
+                    let from = *t.0;
+                    *t.0 = if Some(entity) == sel.entity() {
+                        after
+                    } else {
+                        crate::interaction::transform_about(&from, pivot, rotation, offset)
+                    };
+                    moves.push((entity, from, *t.0));
+                }
+            }
+            history.record_transforms(moves, true);
+        }
+    }
+    // Panels are rebuilt whenever their decorations change, so a dragged
+    // value is only applied once the drag ends.
+    if dragging_decorations {
+        state.dragged_decorations = sel.entity().zip(edited_decorations);
+    } else if let Some(after) = edited_decorations.filter(|d| Some(d) != decorations.as_ref()) {
+        // As with transforms, every selected panel is given the same
+        // decorations.
+        let mut edits = vec![];
+        for entity in sel.entities() {
+            if let Ok((.., Some(mut d))) = sel_query.get_mut(entity) {
+                let from = d.clone();
+                *d = after.clone();
+                edits.push((entity, from, after.clone()));
+            }
+        }
+        history.record_decorations(edits, true);
+    }
+    if !mouse.pressed(MouseButton::Left) {
+        history.seal();
     }
 
     for ev in focus_reader.iter(&ev_focus) {
@@ -455,3 +766,205 @@ fn rotation_component_ui(
         });
     });
 }
+
+fn constraints_ui(
+    ui: &mut egui::Ui,
+    sel: &crate::interaction::Selection,
+    constraints: &Query<&crate::constraints::Constraints>,
+    ev_constraint: &mut ResMut<Events<crate::constraints::ConstraintEvent>>,
+) {
+    use crate::constraints::{ConstraintEvent, ConstraintKind, ConstraintType};
+    let entity = match sel.entity() {
+        Some(entity) => entity,
+        None => return,
+    };
+
+    // With two parts selected, the primary selection can be constrained to
+    // the other.
+    if let [(target, _), _] = sel.members() {
+        ui.horizontal(|ui| {
+            ui.label("Constrain:");
+            for (ty, label) in &[
+                (ConstraintType::Coaxial, "Coaxial"),
+                (ConstraintType::FaceContact, "Face"),
+                (ConstraintType::Offset, "Offset"),
+            ] {
+                if ui.small_button(*label).clicked() {
+                    ev_constraint.send(ConstraintEvent::Add(entity, *target, *ty));
+                }
+            }
+        });
+    }
+
+    if let Ok(c) = constraints.get(entity) {
+        for (i, constraint) in c.0.iter().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label(constraint.kind.name());
+                if let ConstraintKind::Offset { distance } = constraint.kind {
+                    let mut d = distance;
+                    ui.add(egui::widgets::DragValue::f32(&mut d).speed(0.1));
+                    ui.label("mm");
+                    if d != distance {
+                        ev_constraint.send(ConstraintEvent::SetOffset(entity, i, d));
+                    }
+                }
+                if ui.small_button("Remove").clicked() {
+                    ev_constraint.send(ConstraintEvent::Remove(entity, i));
+                }
+            });
+        }
+    }
+}
+
+fn hole_ui(
+    ui: &mut egui::Ui,
+    selection: &crate::stackup::HoleSelection,
+    ev_fasten: &mut ResMut<Events<crate::stackup::FastenEvent>>,
+) {
+    if let Some(hole) = &selection.hole {
+        ui.horizontal(|ui| {
+            ui.label(format!("Hole: {:.1} mm", hole.radius * 2.));
+            if ui.small_button("Add fasteners").clicked() {
+                ev_fasten.send(crate::stackup::FastenEvent);
+            }
+        });
+    }
+    if let Some(report) = &selection.report {
+        ui.label(report.as_str());
+    }
+}
+
+// Describes why a spec is malformed, quoting the lines leading up to the
+// problem.
+pub(crate) fn spec_error_ui(ui: &mut egui::Ui, panel: &PanelInfo) {
+    let error = match panel.error() {
+        Some(error) => error,
+        None => return,
+    };
+    match error.location {
+        Some((line, column)) => ui.label(format!("Line {}, column {}:", line, column)),
+        None => ui.label("Invalid spec:"),
+    };
+    ui.label(error.message.as_str());
+
+    if let Some((line, column)) = error.location {
+        ui.separator();
+        for (i, text) in panel
+            .spec()
+            .lines()
+            .enumerate()
+            .take(line)
+            .skip(line.saturating_sub(3))
+        {
+            let label = egui::Label::new(format!("{:>4} | {}", i + 1, text)).monospace();
+            ui.add(if i + 1 == line {
+                label.text_color(egui::Color32::RED)
+            } else {
+                label
+            });
+        }
+        ui.add(
+            egui::Label::new(format!("{:>4} | {}^", "", " ".repeat(column - 1)))
+                .monospace()
+                .text_color(egui::Color32::RED),
+        );
+    }
+}
+
+// decorations_ui edits the decorations of a panel, returning true while a
+// value is being dragged.
+fn decorations_ui(ui: &mut egui::Ui, decorations: &mut parts::PanelDecorations) -> bool {
+    let mut material = decorations.material;
+    egui::combo_box_with_label(ui, "Material", material.name(), |ui| {
+        for m in parts::PanelMaterial::ALL.iter() {
+            ui.selectable_value(&mut material, *m, m.name());
+        }
+    });
+    if material != decorations.material {
+        decorations.material = material;
+        decorations.color = material.color();
+    }
+
+    ui.horizontal(|ui| {
+        ui.label("Thickness:");
+        for t in parts::PANEL_THICKNESSES.iter() {
+            if ui
+                .selectable_label(decorations.thickness == *t, format!("{}", t))
+                .clicked()
+            {
+                decorations.thickness = *t;
+            }
+        }
+    });
+    ui.horizontal(|ui| {
+        let dragged = ui
+            .add(
+                egui::DragValue::f32(&mut decorations.thickness)
+                    .speed(0.05)
+                    .range(0.1..=10.),
+            )
+            .dragged();
+        ui.label("mm");
+        ui.color_edit_button_rgb(&mut decorations.color);
+        dragged
+    })
+    .0
+}
+
+fn layers_ui(ui: &mut egui::Ui, layers: &mut crate::parts::LayerVisibility) {
+    use crate::parts::{LayerKind, Side};
+
+    egui::Grid::new("layers_grid").show(ui, |ui| {
+        ui.label("");
+        ui.label("Front");
+        ui.label("Back");
+        ui.end_row();
+
+        for kind in LayerKind::ALL.iter() {
+            ui.label(kind.name());
+            for side in &[Side::Front, Side::Back] {
+                let mut shown = layers.shown(*side, *kind);
+                if ui.checkbox(&mut shown, "").changed() {
+                    *layers.shown_mut(*side, *kind) = shown;
+                }
+            }
+            ui.end_row();
+        }
+    });
+}
+
+fn gizmo_ui(ui: &mut egui::Ui, settings: &mut crate::gizmo::GizmoSettings) {
+    use crate::gizmo::GizmoMode;
+
+    ui.horizontal(|ui| {
+        ui.label("Gizmo:");
+        if ui
+            .selectable_label(settings.mode == GizmoMode::Translate, "translate")
+            .clicked()
+        {
+            settings.mode = GizmoMode::Translate;
+        }
+        if ui
+            .selectable_label(settings.mode == GizmoMode::Rotate, "rotate")
+            .clicked()
+        {
+            settings.mode = GizmoMode::Rotate;
+        }
+    });
+    if settings.mode == GizmoMode::Translate {
+        ui.checkbox(&mut settings.snap_to_holes, "Snap fasteners to holes");
+    }
+    if settings.mode == GizmoMode::Rotate {
+        ui.horizontal(|ui| {
+            ui.label("Snap:");
+            for (label, degrees) in &[("off", 0.), ("15°", 15.), ("45°", 45.), ("90°", 90.)] {
+                if ui
+                    .selectable_label(settings.snap_degrees == *degrees, *label)
+                    .clicked()
+                {
+                    settings.snap_degrees = *degrees;
+                }
+            }
+        });
+    }
+}
@@ -1,7 +1,12 @@
 use bevy::{input::keyboard::KeyboardInput, prelude::*, render::camera::Camera};
+use bevy_egui::{egui, EguiContext};
 use bevy_mod_picking::*;
 
-use crate::gizmo::TranslateHandle;
+use crate::assembly::{parent_frame, selection_root, to_local, to_world, SubAssembly};
+use crate::gizmo::{Gizmo, GizmoSettings, RotateHandle, TranslateHandle};
+use crate::parts::{Insert, Nut, PanelHoles, Screw, ScrewLength, Standoff, Support, Washer};
+use crate::snap::{self, SnapTarget};
+use crate::stackup::HoleClickedEvent;
 
 #[derive(Default, Debug)]
 pub struct Selectable;
@@ -19,16 +24,27 @@ impl Default for DraggingKind {
     }
 }
 
+/// The entities which are selected, each paired with its transform at the
+/// time the current interaction began. The last entity is the primary
+/// selection, whose gizmo handle is being used and which is shown in the
+/// inspector.
+pub type Members = Vec<(Entity, Transform)>;
+
 #[derive(Debug)]
 pub enum Selection {
     None,
-    Focused(Entity, Transform),
+    Focused(Members),
     AxisFocused {
-        entity: Entity,
+        members: Members,
         handle: TranslateHandle,
-        start_transform: Transform,
         dragging: DraggingKind,
     },
+    RingFocused {
+        members: Members,
+        handle: RotateHandle,
+        // Where the drag started on the plane of the ring, once known.
+        start_point: Option<Vec3>,
+    },
 }
 
 impl Default for Selection {
@@ -38,14 +54,52 @@ impl Default for Selection {
 }
 
 impl Selection {
-    pub fn entity(&self) -> Option<Entity> {
+    pub fn members(&self) -> &[(Entity, Transform)] {
         match self {
-            Selection::None => None,
-            Selection::Focused(e, _) => Some(e.clone()),
-            Selection::AxisFocused { entity, .. } => Some(entity.clone()),
+            Selection::None => &[],
+            Selection::Focused(members) => members,
+            Selection::AxisFocused { members, .. } => members,
+            Selection::RingFocused { members, .. } => members,
         }
     }
 
+    /// Returns the primary selected entity.
+    pub fn entity(&self) -> Option<Entity> {
+        self.members().last().map(|(e, _)| e.clone())
+    }
+
+    /// Returns all selected entities.
+    pub fn entities(&self) -> Vec<Entity> {
+        self.members().iter().map(|(e, _)| e.clone()).collect()
+    }
+
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.members().iter().any(|(e, _)| *e == entity)
+    }
+
+    /// Deselects the given entity.
+    pub fn remove(&mut self, entity: Entity) {
+        let members: Members = self
+            .members()
+            .iter()
+            .filter(|(e, _)| *e != entity)
+            .cloned()
+            .collect();
+        if members.len() == self.members().len() {
+            return;
+        }
+
+        *self = match self {
+            _ if members.is_empty() => Selection::None,
+            Selection::AxisFocused { handle, .. } => Selection::AxisFocused {
+                handle: *handle,
+                dragging: DraggingKind::None,
+                members,
+            },
+            _ => Selection::Focused(members),
+        };
+    }
+
     pub fn gizmo_handle(&self) -> Option<TranslateHandle> {
         match self {
             Selection::AxisFocused { handle, .. } => Some(handle.clone()),
@@ -56,11 +110,21 @@ impl Selection {
     pub fn is_dragging(&self) -> bool {
         match self {
             Selection::AxisFocused { dragging, .. } => *dragging != DraggingKind::None,
+            Selection::RingFocused { .. } => true,
             _ => false,
         }
     }
 }
 
+/// Computes where an entity ends up when the selection it is a member of is
+/// rotated around pivot and then offset.
+pub fn transform_about(start: &Transform, pivot: Vec3, rotation: Quat, offset: Vec3) -> Transform {
+    let mut t = start.clone();
+    t.translation = pivot + rotation * (start.translation - pivot) + offset;
+    t.rotation = (rotation * start.rotation).normalize();
+    t
+}
+
 pub struct Plugin;
 
 impl bevy::prelude::Plugin for Plugin {
@@ -77,12 +141,23 @@ impl bevy::prelude::Plugin for Plugin {
             .add_system(gcd.system())
             .add_event::<EntityDragEvent>()
             .add_system(compute_drag.system())
+            .add_system(compute_rotate.system())
+            .add_resource(BoxSelect::default())
+            .add_system(box_select.system())
+            .add_system(draw_box_select.system())
             .add_system(update_from_drag.system());
     }
 }
 
 #[derive(Debug)]
-struct ParentClickedEvent(pub Entity, pub Option<TranslateHandle>);
+struct ParentClickedEvent {
+    pub entity: Entity,
+    pub handle: Option<TranslateHandle>,
+    pub ring: Option<RotateHandle>,
+    /// Set if the entity should be added to (or removed from) the
+    /// current selection rather than replacing it.
+    pub additive: bool,
+}
 
 #[derive(Debug)]
 struct ReleaseEvent;
@@ -92,15 +167,44 @@ struct ReleaseEvent;
 fn get_picks(
     pick_state: Res<PickState>,
     mouse_inputs: Res<Input<MouseButton>>,
-    parent_query: Query<(&Parent, Option<&TranslateHandle>)>,
+    key_state: Res<Input<KeyCode>>,
+    parent_query: Query<(&Parent, Option<&TranslateHandle>, Option<&RotateHandle>)>,
+    parents: Query<&Parent>,
+    groups: Query<&SubAssembly>,
     mut ev_clicked: ResMut<Events<ParentClickedEvent>>,
     mut ev_released: ResMut<Events<ReleaseEvent>>,
+    mut ev_hole: ResMut<Events<HoleClickedEvent>>,
 ) {
     if mouse_inputs.just_pressed(MouseButton::Left) {
         let top = pick_state.top(Group::default());
         if let Some(top) = top {
-            if let Ok((parent, hnd)) = parent_query.get(top.0) {
-                ev_clicked.send(ParentClickedEvent(parent.0, hnd.map(|t| t.clone())));
+            if let Ok((parent, hnd, ring)) = parent_query.get(top.0) {
+                // Alt-clicking a panel selects the hole under the cursor.
+                if hnd.is_none()
+                    && ring.is_none()
+                    && (key_state.pressed(KeyCode::LAlt) || key_state.pressed(KeyCode::RAlt))
+                {
+                    ev_hole.send(HoleClickedEvent {
+                        panel: parent.0,
+                        point: *top.1.position(),
+                    });
+                    return;
+                }
+
+                // Parts in a sub-assembly are selected through it, unless
+                // it is being edited. Gizmos always act on their parent.
+                let entity = if hnd.is_none() && ring.is_none() {
+                    selection_root(parent.0, &parents, &groups)
+                } else {
+                    parent.0
+                };
+                ev_clicked.send(ParentClickedEvent {
+                    entity,
+                    handle: hnd.map(|t| t.clone()),
+                    ring: ring.map(|r| r.clone()),
+                    additive: key_state.pressed(KeyCode::LShift)
+                        || key_state.pressed(KeyCode::RShift),
+                });
             }
         }
     } else if mouse_inputs.just_released(MouseButton::Left) {
@@ -116,6 +220,9 @@ pub enum HotkeyEvent {
     AxisY,
     AxisZ,
     Edit,
+    Undo,
+    Redo,
+    ToggleGizmo,
 
     Open,
 
@@ -127,9 +234,13 @@ fn get_keyboard(
     dialog: Res<crate::dialog_gui::DialogState>,
     ev_keys: Res<Events<KeyboardInput>>,
     mut keys_reader: Local<EventReader<KeyboardInput>>,
+    key_state: Res<Input<KeyCode>>,
 
     mut ev_hotkey: ResMut<Events<HotkeyEvent>>,
 ) {
+    let ctrl = key_state.pressed(KeyCode::LControl) || key_state.pressed(KeyCode::RControl);
+    let shift = key_state.pressed(KeyCode::LShift) || key_state.pressed(KeyCode::RShift);
+
     let mut keys: Vec<HotkeyEvent> = Vec::new();
     for event in keys_reader.iter(&ev_keys) {
         if event.state.is_pressed() {
@@ -157,6 +268,16 @@ fn get_keyboard(
                     Some(HotkeyEvent::Load)
                 }
                 (Some(KeyCode::R), crate::dialog_gui::DialogState::None) => Some(HotkeyEvent::Edit),
+                (Some(KeyCode::T), crate::dialog_gui::DialogState::None) => {
+                    Some(HotkeyEvent::ToggleGizmo)
+                }
+                (Some(KeyCode::Z), crate::dialog_gui::DialogState::None) if ctrl => {
+                    Some(if shift {
+                        HotkeyEvent::Redo
+                    } else {
+                        HotkeyEvent::Undo
+                    })
+                }
                 _ => None,
             };
 
@@ -172,7 +293,7 @@ fn get_keyboard(
 }
 
 #[derive(Debug)]
-struct DragEvent(Entity, Transform, DraggingKind, TranslateHandle);
+struct DragEvent(Members, DraggingKind, TranslateHandle);
 
 #[derive(Default, Debug)]
 struct AxisEntity(Option<Entity>);
@@ -240,42 +361,98 @@ fn gcd(
     mut axis_entity: ResMut<AxisEntity>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    mut history: ResMut<crate::history::History>,
     mut out_events: (
         ResMut<Events<DragEvent>>,
         ResMut<Events<crate::inspector_gui::FocusUIEvent>>,
         ResMut<Events<crate::dialog_gui::DialogHotkeyEvent>>,
         ResMut<Events<crate::storage::StorageEvent>>,
+        ResMut<Events<crate::history::EditEvent>>,
     ),
 ) {
-    let (mut ev_dragging, mut ev_focus, mut ev_dialog, mut ev_storage) = out_events;
+    let (mut ev_dragging, mut ev_focus, mut ev_dialog, mut ev_storage, mut ev_edit) = out_events;
 
     // Handle any 'parent clicked' event, updating the Selection resource.
     for ev in clicked_reader.iter(&ev_clicked) {
-        if let Ok(transform) = selection_query.get(ev.0) {
-            if let Some(handle) = ev.1 {
+        if let Ok(transform) = selection_query.get(ev.entity) {
+            // Other members keep their place in the selection, with their
+            // current transforms as the basis for any interaction.
+            let mut members: Members = selection
+                .entities()
+                .into_iter()
+                .filter(|e| *e != ev.entity)
+                .filter_map(|e| selection_query.get(e).ok().map(|t| (e, t.clone())))
+                .collect();
+            let was_selected = selection.contains(ev.entity);
+
+            if ev.handle.is_none() && ev.ring.is_none() {
+                if !ev.additive {
+                    members.clear();
+                } else if was_selected {
+                    // Shift-clicking a selected entity deselects it.
+                    *selection = if members.is_empty() {
+                        Selection::None
+                    } else {
+                        Selection::Focused(members)
+                    };
+                    continue;
+                }
+            } else if !was_selected {
+                members.clear();
+            }
+            members.push((ev.entity, transform.clone()));
+
+            *selection = match (ev.handle, ev.ring) {
                 // Handle clicked
-                *selection = Selection::AxisFocused {
+                (Some(handle), _) => Selection::AxisFocused {
                     handle,
-                    entity: ev.0,
+                    members,
                     dragging: DraggingKind::Gizmo,
-                    start_transform: transform.clone(),
-                };
-            } else {
+                },
+                // Rotate ring clicked
+                (_, Some(handle)) => Selection::RingFocused {
+                    handle,
+                    members,
+                    start_point: None,
+                },
                 // Entity focused
-                *selection = Selection::Focused(ev.0, transform.clone());
-            }
+                _ => Selection::Focused(members),
+            };
         } else {
             *selection = Selection::None;
         }
     }
 
     for _ev in released_reader.iter(&ev_released) {
-        // If the mouse was released while dragging a translate axis.
-        if let Selection::AxisFocused {
-            ref mut dragging, ..
-        } = *selection
-        {
-            *dragging = DraggingKind::None;
+        // If the mouse was released while dragging a gizmo, record where
+        // everything ended up.
+        if selection.is_dragging() {
+            let moves: Vec<(Entity, Transform, Transform)> = selection
+                .members()
+                .iter()
+                .filter_map(|(e, start)| {
+                    selection_query
+                        .get(*e)
+                        .ok()
+                        .map(|t| (e.clone(), start.clone(), t.clone()))
+                })
+                .collect();
+            history.record_transforms(moves, false);
+        }
+
+        match &mut *selection {
+            Selection::AxisFocused { dragging, .. } => {
+                *dragging = DraggingKind::None;
+            }
+            // Rings are only focused while being dragged.
+            Selection::RingFocused { members, .. } => {
+                let members = members
+                    .iter()
+                    .filter_map(|(e, _)| selection_query.get(*e).ok().map(|t| (*e, t.clone())))
+                    .collect();
+                *selection = Selection::Focused(members);
+            }
+            _ => {}
         }
     }
 
@@ -289,37 +466,40 @@ fn gcd(
                 }
             }
             HotkeyEvent::Delete => {
-                if let Some(sel) = selection.entity() {
-                    commands.despawn_recursive(sel);
+                let entities = selection.entities();
+                if entities.len() > 0 {
+                    ev_edit.send(crate::history::EditEvent::Delete(entities));
                 }
                 *selection = Selection::None;
             }
+            HotkeyEvent::Undo => ev_edit.send(crate::history::EditEvent::Undo),
+            HotkeyEvent::Redo => ev_edit.send(crate::history::EditEvent::Redo),
 
             HotkeyEvent::AxisX | HotkeyEvent::AxisY | HotkeyEvent::AxisZ => {
-                let entity = match *selection {
-                    Selection::Focused(entity, _) => Some(entity),
-                    Selection::AxisFocused { entity, .. } => Some(entity),
-                    _ => None,
+                let members: Members = match *selection {
+                    Selection::Focused(_) | Selection::AxisFocused { .. } => selection
+                        .entities()
+                        .into_iter()
+                        .filter_map(|e| selection_query.get(e).ok().map(|t| (e, t.clone())))
+                        .collect(),
+                    _ => vec![],
                 };
-                if let Some(entity) = entity {
-                    if let Ok(transform) = selection_query.get(entity) {
-                        *selection = Selection::AxisFocused {
-                            entity,
-                            start_transform: transform.clone(),
-                            handle: match ev {
-                                HotkeyEvent::AxisX => TranslateHandle::X,
-                                HotkeyEvent::AxisY => TranslateHandle::Y,
-                                HotkeyEvent::AxisZ => TranslateHandle::Z,
-                                _ => unreachable!(),
-                            },
-                            dragging: DraggingKind::Hotkey,
-                        };
-                        if let Some(entity) = axis_entity.0 {
-                            // Theres already an axis visualization, yeet it on
-                            // outta here.
-                            commands.despawn(entity);
-                            *axis_entity = AxisEntity(None);
-                        }
+                if members.len() > 0 {
+                    *selection = Selection::AxisFocused {
+                        members,
+                        handle: match ev {
+                            HotkeyEvent::AxisX => TranslateHandle::X,
+                            HotkeyEvent::AxisY => TranslateHandle::Y,
+                            HotkeyEvent::AxisZ => TranslateHandle::Z,
+                            _ => unreachable!(),
+                        },
+                        dragging: DraggingKind::Hotkey,
+                    };
+                    if let Some(entity) = axis_entity.0 {
+                        // Theres already an axis visualization, yeet it on
+                        // outta here.
+                        commands.despawn(entity);
+                        *axis_entity = AxisEntity(None);
                     }
                 }
             }
@@ -328,6 +508,7 @@ fn gcd(
                     ev_focus.send(crate::inspector_gui::FocusUIEvent::TranslateInput);
                 }
             }
+            HotkeyEvent::ToggleGizmo => {}
             HotkeyEvent::Open => ev_dialog.send(crate::dialog_gui::DialogHotkeyEvent::AddSpec),
             HotkeyEvent::Load => ev_dialog.send(crate::dialog_gui::DialogHotkeyEvent::LoadScene),
             HotkeyEvent::Save => ev_storage.send(crate::storage::StorageEvent::Save),
@@ -336,17 +517,17 @@ fn gcd(
 
     if let Selection::AxisFocused {
         dragging,
-        entity,
+        ref members,
         handle,
-        start_transform,
     } = *selection
     {
         if dragging != DraggingKind::None {
-            ev_dragging.send(DragEvent(entity, start_transform, dragging, handle));
+            ev_dragging.send(DragEvent(members.clone(), dragging, handle));
         }
         match (dragging == DraggingKind::Hotkey, axis_entity.0) {
             (true, None) => {
                 // We are in an axis hotkey mode but no entity for the visuals exists.
+                let start_transform = members.last().unwrap().1;
                 let (mesh, material) =
                     AxisEntity::build(start_transform, handle, &mut meshes, &mut materials);
                 *axis_entity = AxisEntity(Some(
@@ -388,13 +569,21 @@ fn compute_drag(
 
     windows: Res<Windows>,
     mut camera_query: Query<(&GlobalTransform, &Camera)>,
+    parents: Query<&Parent>,
+    globals: Query<&GlobalTransform>,
 
     mut ev_entity_dragging: ResMut<Events<EntityDragEvent>>,
 ) {
     use bevy_mod_raycast::RayCastSource;
 
     for ev in drag_reader.iter(&ev_dragging) {
-        let start_transform = ev.1;
+        // Gizmo positions are computed in world space for the primary
+        // selection, and the same offset is applied to the rest of the
+        // selection in whatever sub-assembly each is a member of.
+        let start_transform = match ev.0.last() {
+            Some((e, t)) => to_world(&parent_frame(*e, &parents, &globals), t),
+            None => continue,
+        };
         for event in cursor_reader.iter(&ev_cursor) {
             for (global_transform, camera) in &mut camera_query.iter_mut() {
                 let p: [f32; 2] = event.position.into();
@@ -405,31 +594,283 @@ fn compute_drag(
                     global_transform,
                 );
 
-                let (hit_plane_t, hit_plane_b) = ev.3.intersection_plane(start_transform);
+                let (hit_plane_t, hit_plane_b) = ev.2.intersection_plane(start_transform);
+                let cast_result = if let Some(i) = source.intersect_primitive(hit_plane_t) {
+                    Some(i)
+                } else {
+                    source.intersect_primitive(hit_plane_b)
+                };
+                if let Some(i) = cast_result {
+                    let offset =
+                        ev.2.calc_position(start_transform, i, ev.1 == DraggingKind::Gizmo)
+                            .translation
+                            - start_transform.translation;
+
+                    for (entity, start) in ev.0.iter() {
+                        let frame = parent_frame(*entity, &parents, &globals);
+                        let mut transform = to_world(&frame, start);
+                        transform.translation += offset;
+                        ev_entity_dragging
+                            .send(EntityDragEvent(*entity, to_local(&frame, &transform)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn compute_rotate(
+    ev_cursor: Res<Events<CursorMoved>>,
+    mut cursor_reader: Local<EventReader<CursorMoved>>,
+
+    windows: Res<Windows>,
+    mut camera_query: Query<(&GlobalTransform, &Camera)>,
+    settings: Res<GizmoSettings>,
+    mut selection: ResMut<Selection>,
+    parents: Query<&Parent>,
+    globals: Query<&GlobalTransform>,
+
+    mut ev_entity_dragging: ResMut<Events<EntityDragEvent>>,
+) {
+    use bevy_mod_raycast::RayCastSource;
+
+    for event in cursor_reader.iter(&ev_cursor) {
+        if let Selection::RingFocused {
+            members,
+            handle,
+            start_point,
+        } = &mut *selection
+        {
+            // The whole selection rotates about its center, in world space.
+            let world: Members = members
+                .iter()
+                .map(|(e, t)| (*e, to_world(&parent_frame(*e, &parents, &globals), t)))
+                .collect();
+            let pivot = world
+                .iter()
+                .fold(Vec3::zero(), |acc, (_, t)| acc + t.translation)
+                / world.len().max(1) as f32;
+
+            for (global_transform, camera) in &mut camera_query.iter_mut() {
+                let p: [f32; 2] = event.position.into();
+                let source: RayCastSource<()> = RayCastSource::new().with_screenspace_ray(
+                    p.into(),
+                    &windows,
+                    camera,
+                    global_transform,
+                );
+
+                let (hit_plane_t, hit_plane_b) = handle.intersection_plane(pivot);
                 let cast_result = if let Some(i) = source.intersect_primitive(hit_plane_t) {
                     Some(i)
                 } else {
                     source.intersect_primitive(hit_plane_b)
                 };
                 if let Some(i) = cast_result {
-                    ev_entity_dragging.send(EntityDragEvent(
-                        ev.0,
-                        ev.3.calc_position(start_transform, i, ev.2 == DraggingKind::Gizmo),
-                    ));
+                    match start_point {
+                        None => *start_point = Some(i.position()),
+                        Some(from) => {
+                            let rotation = handle.calc_rotation(
+                                pivot,
+                                *from,
+                                i.position(),
+                                settings.snap_degrees,
+                            );
+                            for (entity, start) in world.iter() {
+                                let frame = parent_frame(*entity, &parents, &globals);
+                                let transform =
+                                    transform_about(start, pivot, rotation, Vec3::zero());
+                                ev_entity_dragging
+                                    .send(EntityDragEvent(*entity, to_local(&frame, &transform)));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Tracks a rubber-band selection being dragged out with shift held.
+#[derive(Debug, Default)]
+pub struct BoxSelect {
+    start: Option<Vec2>,
+    cursor: Vec2,
+}
+
+impl BoxSelect {
+    pub fn is_active(&self) -> bool {
+        self.start.is_some()
+    }
+}
+
+// box_select starts a box selection when the user shift-drags over empty
+// space, and adds all parts inside the box to the selection on release.
+fn box_select(
+    pick_state: Res<PickState>,
+    mouse_inputs: Res<Input<MouseButton>>,
+    key_state: Res<Input<KeyCode>>,
+    ev_cursor: Res<Events<CursorMoved>>,
+    mut cursor_reader: Local<EventReader<CursorMoved>>,
+
+    windows: Res<Windows>,
+    mut box_select: ResMut<BoxSelect>,
+    mut selection: ResMut<Selection>,
+    camera_query: Query<(&GlobalTransform, &Camera)>,
+    part_query: Query<
+        (Entity, &GlobalTransform),
+        (With<Selectable>, Without<Gizmo>, Without<SubAssembly>),
+    >,
+    transforms: Query<&Transform, With<Selectable>>,
+    parents: Query<&Parent>,
+    groups: Query<&SubAssembly>,
+) {
+    if let Some(event) = cursor_reader.iter(&ev_cursor).last() {
+        box_select.cursor = event.position;
+    }
+
+    if mouse_inputs.just_pressed(MouseButton::Left)
+        && pick_state.enabled
+        && pick_state.top(Group::default()).is_none()
+        && (key_state.pressed(KeyCode::LShift) || key_state.pressed(KeyCode::RShift))
+    {
+        box_select.start = Some(box_select.cursor);
+    }
+
+    if !mouse_inputs.just_released(MouseButton::Left) {
+        return;
+    }
+    let start = match box_select.start.take() {
+        Some(start) => start,
+        None => return,
+    };
+    let (min, max) = (start.min(box_select.cursor), start.max(box_select.cursor));
+    if (max - min).length() < 3. {
+        return;
+    }
+    let window = match windows.get_primary() {
+        Some(w) => w,
+        None => return,
+    };
+
+    let mut members: Members = selection
+        .entities()
+        .into_iter()
+        .filter_map(|e| transforms.get(e).ok().map(|t| (e, *t)))
+        .collect();
+
+    for (cam_transform, camera) in camera_query.iter() {
+        if camera.name.as_deref() != Some(bevy::render::render_graph::base::camera::CAMERA_3D) {
+            continue;
+        }
+        let view_proj = camera.projection_matrix * cam_transform.compute_matrix().inverse();
+
+        for (entity, global_transform) in part_query.iter() {
+            // Parts in a sub-assembly are selected through it, as if
+            // they had been clicked.
+            let entity = selection_root(entity, &parents, &groups);
+            let clip = view_proj * global_transform.translation.extend(1.);
+            if clip.w <= 0. || members.iter().any(|(e, _)| *e == entity) {
+                continue;
+            }
+            let ndc = clip.truncate() / clip.w;
+            let screen = Vec2::new(
+                (ndc.x + 1.) / 2. * window.width(),
+                (ndc.y + 1.) / 2. * window.height(),
+            );
+            if screen.x >= min.x && screen.x <= max.x && screen.y >= min.y && screen.y <= max.y {
+                if let Ok(transform) = transforms.get(entity) {
+                    members.push((entity, *transform));
                 }
             }
         }
     }
+
+    if members.len() > 0 {
+        *selection = Selection::Focused(members);
+    }
+}
+
+fn draw_box_select(
+    mut egui_context: ResMut<EguiContext>,
+    windows: Res<Windows>,
+    box_select: Res<BoxSelect>,
+) {
+    let (start, end) = match (box_select.start, windows.get_primary()) {
+        // Cursor positions are measured from the bottom of the window,
+        // egui positions from the top.
+        (Some(start), Some(window)) => (
+            egui::pos2(start.x, window.height() - start.y),
+            egui::pos2(box_select.cursor.x, window.height() - box_select.cursor.y),
+        ),
+        _ => return,
+    };
+
+    let painter = egui_context.ctx.layer_painter(egui::LayerId::new(
+        egui::Order::Foreground,
+        egui::Id::new("box_select"),
+    ));
+    painter.rect(
+        egui::Rect::from_two_pos(start, end),
+        0.,
+        egui::Color32::from_rgba_premultiplied(20, 30, 40, 40),
+        egui::Stroke::new(1., egui::Color32::LIGHT_GRAY),
+    );
 }
 
 fn update_from_drag(
     ev_dragging: Res<Events<EntityDragEvent>>,
     mut drag_reader: Local<EventReader<EntityDragEvent>>,
+    selection: Res<Selection>,
+    settings: Res<GizmoSettings>,
+    mut snap_target: ResMut<SnapTarget>,
     mut selection_query: Query<&mut Transform, With<Selectable>>,
+    fasteners: Query<(
+        Option<&Screw>,
+        Option<&ScrewLength>,
+        Option<&Washer>,
+        Option<&Nut>,
+        Option<&Standoff>,
+        Option<&Insert>,
+        Option<&Support>,
+    )>,
+    panels: Query<(Entity, &GlobalTransform, &PanelHoles)>,
+    parents: Query<&Parent>,
+    globals: Query<&GlobalTransform>,
 ) {
+    if !selection.is_dragging() {
+        snap_target.0 = None;
+    }
+
+    // Only a single fastener being moved along an axis is snapped.
+    let snapping = settings.snap_to_holes
+        && match &*selection {
+            Selection::AxisFocused { members, .. } => members.len() == 1,
+            _ => false,
+        };
+
     for ev in drag_reader.iter(&ev_dragging) {
+        let mut target = ev.1;
+        snap_target.0 = None;
+
+        if snapping {
+            if let Ok((screw, length, washer, nut, standoff, insert, support)) = fasteners.get(ev.0)
+            {
+                let fit = snap::fastener_fit(screw, length, washer, nut, standoff, insert, support);
+                if let Some(fit) = fit {
+                    let frame = parent_frame(ev.0, &parents, &globals);
+                    let world = to_world(&frame, &target);
+                    let others = panels.iter().filter(|(e, _, _)| *e != ev.0);
+                    if let Some((snapped, hole)) = snap::snap_to_hole(&world, fit, others) {
+                        target = to_local(&frame, &snapped);
+                        snap_target.0 = Some(hole);
+                    }
+                }
+            }
+        }
+
         if let Ok(mut transform) = selection_query.get_mut(ev.0) {
-            *transform = ev.1;
+            *transform = target;
         }
     }
 }
@@ -5,23 +5,36 @@ use bevy_mod_picking::*;
 
 use structopt::StructOpt;
 
+mod assembly;
+mod bom;
+mod cli;
+mod constraints;
 mod dialog_gui;
+mod drill;
+mod export;
+mod fastener;
 mod gizmo;
 mod grid;
+mod history;
 mod inspector_gui;
 mod interaction;
 mod parts;
+mod snap;
+mod spec_editor;
+mod stackup;
 mod storage;
+mod watch;
 
 fn interaction_state(
     egui: Res<EguiContext>,
     sel: Res<interaction::Selection>,
+    box_select: Res<interaction::BoxSelect>,
     mut cameras: Query<&mut CameraRig>,
     mut pick_state: ResMut<PickState>,
 ) {
     let using_gui = egui.ctx.wants_mouse_input();
     for mut c in cameras.iter_mut() {
-        c.disable = using_gui || sel.is_dragging();
+        c.disable = using_gui || sel.is_dragging() || box_select.is_active();
     }
     pick_state.enabled = !using_gui;
 }
@@ -89,6 +102,10 @@ fn startup(commands: &mut Commands) {
 #[structopt(name = "mp-assembler", about = "Visualize maker-panel geometry")]
 pub struct Opt {
     spec_dirs: Vec<String>,
+
+    /// Run a command without opening a window.
+    #[structopt(subcommand)]
+    cmd: Option<cli::Command>,
 }
 
 fn load_specs(spec_dirs: &Vec<String>) -> Result<Vec<parts::PanelInfo>, std::io::Error> {
@@ -127,6 +144,9 @@ pub struct CmdArgs(pub Opt);
 
 fn main() {
     let opt = Opt::from_args();
+    if let Some(cmd) = opt.cmd.clone() {
+        std::process::exit(cli::run(cmd));
+    }
     let specs = load_specs(&opt.spec_dirs).unwrap();
 
     App::build()
@@ -136,16 +156,23 @@ fn main() {
         .add_resource(CmdArgs(opt))
         .add_plugins(DefaultPlugins)
         .add_plugin(PickingPlugin)
-        .add_plugin(bevy_stl::StlPlugin)
         .add_plugin(FourXCameraPlugin)
         .add_startup_system(startup.system())
         .add_system(interaction_state.system())
         .add_plugin(grid::Plugin)
         .add_plugin(gizmo::Plugin)
+        .add_plugin(history::Plugin)
         .add_plugin(interaction::Plugin)
         .add_plugin(inspector_gui::Plugin)
+        .add_plugin(constraints::Plugin)
         .add_plugin(dialog_gui::Plugin)
+        .add_plugin(export::Plugin)
+        .add_plugin(fastener::Plugin)
         .add_plugin(parts::Plugin)
+        .add_plugin(snap::Plugin)
+        .add_plugin(spec_editor::Plugin)
+        .add_plugin(stackup::Plugin)
         .add_plugin(storage::Plugin)
+        .add_plugin(watch::Plugin)
         .run();
 }
@@ -4,13 +4,17 @@ use bevy::render::texture::{Extent3d, TextureDimension, TextureFormat};
 use maker_panel::{Panel, SpecErr};
 use serde::{Deserialize, Serialize};
 
-use crate::inspector_gui::SpawnPartEvent;
+use crate::fastener::ModelThreads;
+use crate::inspector_gui::{SpawnPartEvent, SpawnSceneEvent};
 
 pub struct Plugin;
 
 impl bevy::prelude::Plugin for Plugin {
     fn build(&self, app: &mut AppBuilder) {
-        app.add_system(spawner.system());
+        app.add_resource(LayerVisibility::default())
+            .add_system(spawner.system())
+            .add_system(redraw_layers.system())
+            .add_system(redecorate.system());
     }
 }
 
@@ -23,16 +27,211 @@ impl Default for ScrewLength {
     }
 }
 
-/// Component that is present on all screw entities
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub enum Screw {
+/// Standard lengths, in mm, screws are available in.
+pub const SCREW_LENGTHS: &[usize] = &[
+    6, 8, 10, 12, 14, 16, 18, 20, 22, 25, 30, 35, 40, 45, 50, 55, 60,
+];
+
+/// ISO metric thread sizes which hardware is available in.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Metric {
+    M2,
+    #[serde(rename = "M2.5")]
+    M2_5,
     M3,
+    M4,
     M5,
+    M6,
 }
 
-impl Default for Screw {
+impl Default for Metric {
     fn default() -> Self {
-        Screw::M3
+        Metric::M3
+    }
+}
+
+impl std::fmt::Display for Metric {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Metric::M2_5 => write!(f, "M2.5"),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+/// Dimensions, in mm, of the hardware for a thread size.
+#[derive(Debug, Clone, Copy)]
+pub struct MetricDims {
+    /// Nominal diameter and coarse pitch of the thread (ISO 261).
+    pub diameter: f32,
+    pub pitch: f32,
+    /// Diameter of a fine clearance hole (ISO 273), and of the hole in a
+    /// washer.
+    pub clearance: f32,
+    /// Diameter and height of a pan head (ISO 7045).
+    pub head_diameter: f32,
+    pub head_height: f32,
+    /// Width across flats and thickness of a hex nut (ISO 4032).
+    pub nut_width: f32,
+    pub nut_thickness: f32,
+    /// Outside diameter and thickness of a plain washer (ISO 7089).
+    pub washer_diameter: f32,
+    pub washer_thickness: f32,
+}
+
+impl Metric {
+    pub const ALL: [Metric; 6] = [
+        Metric::M2,
+        Metric::M2_5,
+        Metric::M3,
+        Metric::M4,
+        Metric::M5,
+        Metric::M6,
+    ];
+
+    pub fn dims(&self) -> MetricDims {
+        let (thread, head_diameter, head_height, nut_width, nut_thickness, washer) = match self {
+            Metric::M2 => ((2.0, 0.4, 2.2), 4.0, 1.6, 4.0, 1.6, (5.0, 0.3)),
+            Metric::M2_5 => ((2.5, 0.45, 2.7), 5.0, 2.0, 5.0, 2.0, (6.0, 0.5)),
+            Metric::M3 => ((3.0, 0.5, 3.2), 5.6, 2.4, 5.5, 2.4, (7.0, 0.5)),
+            Metric::M4 => ((4.0, 0.7, 4.3), 8.0, 3.1, 7.0, 3.2, (9.0, 0.8)),
+            Metric::M5 => ((5.0, 0.8, 5.3), 9.5, 3.7, 8.0, 4.7, (10.0, 1.0)),
+            Metric::M6 => ((6.0, 1.0, 6.4), 12.0, 4.6, 10.0, 5.2, (12.0, 1.6)),
+        };
+        MetricDims {
+            diameter: thread.0,
+            pitch: thread.1,
+            clearance: thread.2,
+            head_diameter,
+            head_height,
+            nut_width,
+            nut_thickness,
+            washer_diameter: washer.0,
+            washer_thickness: washer.1,
+        }
+    }
+
+    /// Returns the radius of the thread in mm.
+    pub fn radius(&self) -> f32 {
+        self.dims().diameter / 2.
+    }
+}
+
+/// Component that is present on all screw entities
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Screw {
+    pub size: Metric,
+    pub head: ScrewHead,
+    pub drive: ScrewDrive,
+}
+
+impl Screw {
+    /// Describes the head and drive of the screw, such as "socket cap, hex
+    /// drive". Hex heads are turned with a wrench, so have no drive.
+    pub fn style(&self) -> String {
+        match self.head {
+            ScrewHead::Hex => self.head.name().to_string(),
+            _ => format!("{}, {} drive", self.head.name(), self.drive.name()),
+        }
+    }
+}
+
+impl std::fmt::Display for Screw {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.size.fmt(f)
+    }
+}
+
+/// Shape of the head of a screw.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScrewHead {
+    Pan,
+    SocketCap,
+    Countersunk,
+    Button,
+    Hex,
+}
+
+impl Default for ScrewHead {
+    fn default() -> Self {
+        ScrewHead::Pan
+    }
+}
+
+impl ScrewHead {
+    pub const ALL: [ScrewHead; 5] = [
+        ScrewHead::Pan,
+        ScrewHead::SocketCap,
+        ScrewHead::Countersunk,
+        ScrewHead::Button,
+        ScrewHead::Hex,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScrewHead::Pan => "pan head",
+            ScrewHead::SocketCap => "socket cap",
+            ScrewHead::Countersunk => "countersunk",
+            ScrewHead::Button => "button head",
+            ScrewHead::Hex => "hex head",
+        }
+    }
+
+    /// Returns the diameter and height, in mm, of the head on a screw of
+    /// the given size. The diameter of a hex head is its width across flats.
+    pub fn dims(&self, size: Metric) -> (f32, f32) {
+        let i = Metric::ALL.iter().position(|m| *m == size).unwrap();
+        match self {
+            ScrewHead::Pan => (size.dims().head_diameter, size.dims().head_height),
+            // ISO 4762.
+            ScrewHead::SocketCap => (
+                [3.8, 4.5, 5.5, 7.0, 8.5, 10.0][i],
+                [2.0, 2.5, 3.0, 4.0, 5.0, 6.0][i],
+            ),
+            // ISO 7046, with a 90° countersink.
+            ScrewHead::Countersunk => (
+                [3.8, 4.7, 5.5, 8.4, 9.3, 11.3][i],
+                [1.2, 1.5, 1.65, 2.7, 2.7, 3.3][i],
+            ),
+            // ISO 7380.
+            ScrewHead::Button => (
+                [3.5, 4.4, 5.7, 7.6, 9.5, 10.5][i],
+                [1.1, 1.4, 1.65, 2.2, 2.75, 3.3][i],
+            ),
+            // ISO 4017.
+            ScrewHead::Hex => (
+                [4.0, 5.0, 5.5, 7.0, 8.0, 10.0][i],
+                [1.4, 1.7, 2.0, 2.8, 3.5, 4.0][i],
+            ),
+        }
+    }
+}
+
+/// Shape of the recess in the head of a screw.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScrewDrive {
+    Phillips,
+    Hex,
+    Torx,
+}
+
+impl Default for ScrewDrive {
+    fn default() -> Self {
+        ScrewDrive::Phillips
+    }
+}
+
+impl ScrewDrive {
+    pub const ALL: [ScrewDrive; 3] = [ScrewDrive::Phillips, ScrewDrive::Hex, ScrewDrive::Torx];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScrewDrive::Phillips => "Phillips",
+            ScrewDrive::Hex => "hex",
+            ScrewDrive::Torx => "Torx",
+        }
     }
 }
 
@@ -47,15 +246,19 @@ pub struct ScrewBundle {
 }
 
 /// Component that is present on all washer entities
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub enum Washer {
-    M3,
-    M5,
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Washer(pub Metric);
+
+impl std::fmt::Display for Washer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
 }
 
-impl Default for Washer {
-    fn default() -> Self {
-        Washer::M3
+impl Washer {
+    /// Returns the thickness of the washer in mm.
+    pub fn thickness(&self) -> f32 {
+        self.0.dims().washer_thickness
     }
 }
 
@@ -69,15 +272,19 @@ pub struct WasherBundle {
 }
 
 /// Component that is present on all nut entities
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub enum Nut {
-    M3,
-    M5,
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Nut(pub Metric);
+
+impl std::fmt::Display for Nut {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
 }
 
-impl Default for Nut {
-    fn default() -> Self {
-        Nut::M3
+impl Nut {
+    /// Returns the thickness of the nut in mm.
+    pub fn thickness(&self) -> f32 {
+        self.0.dims().nut_thickness
     }
 }
 
@@ -90,9 +297,354 @@ pub struct NutBundle {
     pub global_transform: GlobalTransform,
 }
 
-#[derive(Debug, Clone)]
+/// Component that is present on all standoff entities. The length excludes
+/// any male thread.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Standoff {
+    pub size: Metric,
+    pub length: usize,
+    pub gender: StandoffGender,
+    pub material: StandoffMaterial,
+}
+
+impl Default for Standoff {
+    fn default() -> Self {
+        Standoff {
+            size: Metric::default(),
+            length: 10,
+            gender: StandoffGender::default(),
+            material: StandoffMaterial::default(),
+        }
+    }
+}
+
+impl std::fmt::Display for Standoff {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}x{} {}", self.size, self.length, self.gender.name())
+    }
+}
+
+impl Standoff {
+    /// Returns the length, in mm, of the male thread if there is one.
+    pub fn stud_length(&self) -> f32 {
+        match self.gender {
+            StandoffGender::MaleFemale => {
+                let i = Metric::ALL.iter().position(|m| *m == self.size).unwrap();
+                [3.0, 5.0, 6.0, 6.0, 8.0, 10.0][i]
+            }
+            StandoffGender::FemaleFemale => 0.,
+        }
+    }
+}
+
+/// Which ends of a standoff are threaded studs, rather than tapped holes.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StandoffGender {
+    MaleFemale,
+    FemaleFemale,
+}
+
+impl Default for StandoffGender {
+    fn default() -> Self {
+        StandoffGender::MaleFemale
+    }
+}
+
+impl StandoffGender {
+    pub const ALL: [StandoffGender; 2] = [StandoffGender::MaleFemale, StandoffGender::FemaleFemale];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StandoffGender::MaleFemale => "M-F",
+            StandoffGender::FemaleFemale => "F-F",
+        }
+    }
+}
+
+/// What a standoff is made of.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StandoffMaterial {
+    Brass,
+    Nylon,
+    Aluminium,
+    Steel,
+}
+
+impl Default for StandoffMaterial {
+    fn default() -> Self {
+        StandoffMaterial::Brass
+    }
+}
+
+impl StandoffMaterial {
+    pub const ALL: [StandoffMaterial; 4] = [
+        StandoffMaterial::Brass,
+        StandoffMaterial::Nylon,
+        StandoffMaterial::Aluminium,
+        StandoffMaterial::Steel,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StandoffMaterial::Brass => "brass",
+            StandoffMaterial::Nylon => "nylon",
+            StandoffMaterial::Aluminium => "aluminium",
+            StandoffMaterial::Steel => "steel",
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        match self {
+            StandoffMaterial::Brass => Color::rgb(0.78, 0.62, 0.3),
+            StandoffMaterial::Nylon => Color::rgb(0.92, 0.91, 0.86),
+            StandoffMaterial::Aluminium => Color::rgb(0.77, 0.78, 0.8),
+            StandoffMaterial::Steel => Color::rgb(0.79, 0.8, 0.81),
+        }
+    }
+}
+
+/// Bundle to make it easy to construct standoff entities.
+#[derive(Bundle, Debug, Default)]
+pub struct StandoffBundle {
+    standoff: Standoff,
+    selectable: Selectable,
+    pub transform: Transform,
+    pub global_transform: GlobalTransform,
+}
+
+/// Component that is present on all heat-set insert entities.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Insert(pub Metric);
+
+impl std::fmt::Display for Insert {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Insert {
+    /// Returns the outside diameter and length of the insert in mm.
+    pub fn dims(&self) -> (f32, f32) {
+        match self.0 {
+            Metric::M2 => (3.6, 3.0),
+            Metric::M2_5 => (3.6, 4.0),
+            Metric::M3 => (4.0, 5.7),
+            Metric::M4 => (5.6, 8.1),
+            Metric::M5 => (6.4, 9.5),
+            Metric::M6 => (8.0, 12.7),
+        }
+    }
+}
+
+/// Bundle to make it easy to construct heat-set insert entities.
+#[derive(Bundle, Debug, Default)]
+pub struct InsertBundle {
+    insert: Insert,
+    selectable: Selectable,
+    pub transform: Transform,
+    pub global_transform: GlobalTransform,
+}
+
+/// Component that is present on all nylon rivet and PCB support entities.
+/// They are sized by the holes they fit, which are the clearance holes for
+/// screws of the given size. The length is the grip of a rivet, or the
+/// spacing between the boards a support holds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Support {
+    pub size: Metric,
+    pub length: usize,
+    pub kind: SupportKind,
+}
+
+impl Default for Support {
+    fn default() -> Self {
+        Support {
+            size: Metric::default(),
+            length: 10,
+            kind: SupportKind::default(),
+        }
+    }
+}
+
+impl std::fmt::Display for Support {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} for {} holes, {} mm",
+            self.kind.name(),
+            self.size,
+            self.length
+        )
+    }
+}
+
+/// The kinds of push-in nylon hardware.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SupportKind {
+    /// A rivet, which clamps panels together between its head and barb.
+    Rivet,
+    /// A spacer with a barbed pin at each end, which holds two panels
+    /// apart.
+    PcbSupport,
+}
+
+impl Default for SupportKind {
+    fn default() -> Self {
+        SupportKind::Rivet
+    }
+}
+
+impl SupportKind {
+    pub const ALL: [SupportKind; 2] = [SupportKind::Rivet, SupportKind::PcbSupport];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SupportKind::Rivet => "nylon rivet",
+            SupportKind::PcbSupport => "PCB support",
+        }
+    }
+}
+
+/// Bundle to make it easy to construct rivet and PCB support entities.
+#[derive(Bundle, Debug, Default)]
+pub struct SupportBundle {
+    support: Support,
+    selectable: Selectable,
+    pub transform: Transform,
+    pub global_transform: GlobalTransform,
+}
+
+/// Component describing the drill holes in a panel as (center, radius), in
+/// panel coordinates, along with the extent of the panel along its Z axis.
+#[derive(Debug, Clone, Default)]
+pub struct PanelHoles {
+    pub holes: Vec<(Vec2, f32)>,
+    pub bottom: f32,
+    pub top: f32,
+}
+
+impl PanelHoles {
+    fn new(atoms: &[maker_panel::features::InnerAtom], vertexes: &Vec<[f64; 3]>) -> Self {
+        use maker_panel::features::InnerAtom;
+        use std::f32::MAX;
+
+        // Holes show up as a circle on each layer they affect, so only the
+        // smallest circle at each center is the drill itself.
+        let mut holes: Vec<(Vec2, f32)> = Vec::new();
+        for atom in atoms {
+            if let InnerAtom::Circle { center, radius, .. } = atom {
+                let (center, radius) =
+                    (Vec2::new(center.x as f32, center.y as f32), *radius as f32);
+                match holes
+                    .iter_mut()
+                    .find(|(c, _)| (*c - center).length() < 0.01)
+                {
+                    Some((_, r)) => *r = r.min(radius),
+                    None => holes.push((center, radius)),
+                }
+            }
+        }
+
+        let (bottom, top) = vertexes.iter().fold((MAX, -MAX), |(lo, hi), v| {
+            (lo.min(v[2] as f32), hi.max(v[2] as f32))
+        });
+        Self { holes, bottom, top }
+    }
+}
+
+/// Thicknesses, in mm, panels are commonly made in.
+pub const PANEL_THICKNESSES: [f32; 4] = [0.8, 1.0, 1.6, 2.0];
+
+/// What a panel is made of, which sets its default color and finish.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PanelMaterial {
+    Fr4Green,
+    Fr4Black,
+    Fr4White,
+    Fr4Purple,
+    Aluminium,
+    Acrylic,
+}
+
+impl Default for PanelMaterial {
+    fn default() -> Self {
+        PanelMaterial::Fr4Green
+    }
+}
+
+impl PanelMaterial {
+    pub const ALL: [PanelMaterial; 6] = [
+        PanelMaterial::Fr4Green,
+        PanelMaterial::Fr4Black,
+        PanelMaterial::Fr4White,
+        PanelMaterial::Fr4Purple,
+        PanelMaterial::Aluminium,
+        PanelMaterial::Acrylic,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PanelMaterial::Fr4Green => "FR4 (green)",
+            PanelMaterial::Fr4Black => "FR4 (black)",
+            PanelMaterial::Fr4White => "FR4 (white)",
+            PanelMaterial::Fr4Purple => "FR4 (purple)",
+            PanelMaterial::Aluminium => "Aluminium",
+            PanelMaterial::Acrylic => "Acrylic",
+        }
+    }
+
+    /// The color panels of this material are given when it is picked.
+    pub fn color(&self) -> [f32; 3] {
+        match self {
+            PanelMaterial::Fr4Green => [0.1, 0.4, 0.1],
+            PanelMaterial::Fr4Black => [0.06, 0.06, 0.06],
+            PanelMaterial::Fr4White => [0.92, 0.92, 0.9],
+            PanelMaterial::Fr4Purple => [0.35, 0.12, 0.45],
+            PanelMaterial::Aluminium => [0.77, 0.78, 0.8],
+            PanelMaterial::Acrylic => [0.85, 0.9, 0.95],
+        }
+    }
+
+    /// How opaque panels of this material are, from 0 to 1.
+    pub fn alpha(&self) -> f32 {
+        match self {
+            PanelMaterial::Acrylic => 0.45,
+            _ => 1.,
+        }
+    }
+
+    /// The (metallic, roughness) of the surface of the material.
+    pub fn finish(&self) -> (f32, f32) {
+        match self {
+            PanelMaterial::Aluminium => (1., 0.4),
+            PanelMaterial::Acrylic => (0., 0.1),
+            _ => (0., 0.7),
+        }
+    }
+}
+
+/// Component describing how a panel looks: what it is made of and how
+/// thick it is.
+#[derive(Debug, Clone, PartialEq)]
 pub struct PanelDecorations {
     pub color: [f32; 3],
+    pub material: PanelMaterial,
+    /// Thickness of the panel, in mm.
+    pub thickness: f32,
+}
+
+impl Default for PanelDecorations {
+    fn default() -> Self {
+        Self {
+            color: PanelMaterial::default().color(),
+            material: PanelMaterial::default(),
+            thickness: 1.6,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -119,10 +671,50 @@ impl PanelInfo {
         self.path.split("/").last().unwrap().to_string()
     }
 
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn spec(&self) -> &str {
+        &self.spec
+    }
+
+    /// Returns a copy of the panel with a new spec, such as when the file
+    /// it was read from has changed.
+    pub fn with_spec(&self, data: String) -> Self {
+        Self {
+            convex_hull: self.convex_hull,
+            ..Self::new(self.path.clone(), data)
+        }
+    }
+
+    /// Returns a copy of the panel, drawn as the convex hull of its outline
+    /// if convex_hull is set.
+    pub fn with_convex_hull(&self, convex_hull: bool) -> Self {
+        Self {
+            convex_hull,
+            ..self.clone()
+        }
+    }
+
     pub fn well_formed(&self) -> bool {
         self.err.is_none()
     }
 
+    /// Returns the error encountered parsing the spec, if any.
+    pub fn err(&self) -> Option<&SpecErr> {
+        self.err.as_ref()
+    }
+
+    /// Describes the error encountered parsing the spec, if any, along
+    /// with where in the spec it was found.
+    pub fn error(&self) -> Option<SpecError> {
+        let message = format!("{:?}", self.err.as_ref()?);
+        let location =
+            locate_error(&message, &self.spec).map(|offset| line_column(&self.spec, offset));
+        Some(SpecError { message, location })
+    }
+
     pub fn panel(&self) -> Panel {
         let mut panel = Panel::new();
         panel.push_spec(&self.spec);
@@ -134,6 +726,62 @@ impl PanelInfo {
     }
 }
 
+/// Describes why a spec could not be parsed.
+#[derive(Debug, Clone)]
+pub struct SpecError {
+    pub message: String,
+    /// Line and column, counting from 1, at which the problem was found.
+    pub location: Option<(usize, usize)>,
+}
+
+// Finds the byte offset in spec at which the error was encountered. Parse
+// errors quote the input remaining where parsing stopped, so the longest
+// quoted fragment of the message which ends the spec marks the spot. Failing
+// that, the longest fragment found anywhere in the spec is used.
+fn locate_error(message: &str, spec: &str) -> Option<usize> {
+    let mut fragments: Vec<String> = vec![];
+    let mut chars = message.chars();
+    while let Some(c) = chars.next() {
+        if c != '"' {
+            continue;
+        }
+        let mut fragment = String::new();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => break,
+                '\\' => match chars.next() {
+                    Some('n') => fragment.push('\n'),
+                    Some('t') => fragment.push('\t'),
+                    Some('r') => fragment.push('\r'),
+                    Some(c) => fragment.push(c),
+                    None => {}
+                },
+                c => fragment.push(c),
+            }
+        }
+        if !fragment.trim().is_empty() {
+            fragments.push(fragment);
+        }
+    }
+    fragments.sort_by_key(|f| std::cmp::Reverse(f.len()));
+
+    fragments
+        .iter()
+        .find(|f| spec.ends_with(f.as_str()))
+        .map(|f| spec.len() - f.len())
+        .or_else(|| fragments.iter().find_map(|f| spec.find(f.as_str())))
+}
+
+// Converts a byte offset in spec to a line and column, counting from 1.
+fn line_column(spec: &str, offset: usize) -> (usize, usize) {
+    let before = &spec[..offset];
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
 /// Component that is present on all PCB entities
 #[derive(Default, Debug)]
 pub struct Pcb;
@@ -146,6 +794,7 @@ pub struct PcbBundle {
     decorations: PanelDecorations,
     pcb: Pcb,
     selectable: Selectable,
+    holes: PanelHoles,
     pub transform: Transform,
     pub global_transform: GlobalTransform,
 
@@ -165,6 +814,7 @@ impl PcbBundle {
             decorations,
             pcb: Pcb::default(),
             selectable: Selectable::default(),
+            holes: PanelHoles::default(),
             global_transform: GlobalTransform::default(),
             geometry: Geometry::Spec(path),
         }
@@ -181,156 +831,482 @@ fn spawn_pcb(
     commands: &mut Commands,
     mut materials: &mut ResMut<Assets<StandardMaterial>>,
     mut meshes: &mut ResMut<Assets<Mesh>>,
-    pcb: PcbBundle,
-    geo: PbrBundle,
-) {
-    let mesh2 = geo.mesh.clone();
-
-    commands.spawn(pcb).with_children(|parent| {
+    textures: &mut ResMut<Assets<Texture>>,
+    mut pcb: PcbBundle,
+    geometry: PanelGeometry,
+) -> Entity {
+    let decorations = pcb.decorations.clone();
+    pcb.holes = geometry.holes.clone();
+
+    let entity = commands.spawn(pcb).current_entity().unwrap();
+    commands.with_children(|parent| {
         crate::gizmo::spawn_translate(parent, &mut meshes, &mut materials);
-
-        parent
-            .spawn(geo)
-            .with(bevy_mod_picking::PickableMesh::default().with_bounding_sphere(mesh2));
+        crate::gizmo::spawn_rotate(parent, &mut meshes, &mut materials);
+
+        spawn_panel_faces(
+            parent,
+            geometry,
+            &decorations,
+            true,
+            &mut meshes,
+            &mut materials,
+            textures,
+        );
     });
+    entity
 }
 
 fn spawn_screw(
     screw: Screw,
     commands: &mut Commands,
-    asset_server: &Res<AssetServer>,
+    threads: &ModelThreads,
     mut materials: &mut ResMut<Assets<StandardMaterial>>,
     mut meshes: &mut ResMut<Assets<Mesh>>,
-    mut transform: Transform,
+    transform: Transform,
     length: usize,
-) {
-    // let texture_handle = asset_server.load("worn-shiny-metal-albedo.png");
-    let thread_2mm = asset_server.load("m3-2mm.stl");
+) -> Entity {
     let stainless = materials.add(StandardMaterial {
         albedo: Color::rgb(0.79, 0.8, 0.81).into(),
-        // albedo_texture: Some(texture_handle.clone()),
         ..Default::default()
     });
+    let mesh = meshes.add(crate::fastener::screw_mesh(&screw, length, threads.0));
 
-    if let Screw::M5 = screw {
-        transform.scale = Vec3::new(1. / 3. * 5., 1. / 3. * 5., 1.);
-    }
-
-    commands
+    let entity = commands
         .spawn(ScrewBundle {
             transform,
             screw,
             length: ScrewLength(length),
             ..ScrewBundle::default()
         })
-        .with_children(|parent| {
-            crate::gizmo::spawn_translate(parent, &mut meshes, &mut materials);
-
-            let transform = Transform::from_translation(Vec3::new(0., 0., length as f32));
-            let pan_head = asset_server.load("m3-pan_head.stl");
-            parent
-                .spawn(PbrBundle {
-                    mesh: pan_head.clone(),
-                    material: stainless.clone(),
-                    transform,
-                    ..Default::default()
-                })
-                .with(bevy_mod_picking::PickableMesh::default().with_bounding_sphere(pan_head));
-
-            for i in 0..(length / 2) {
-                parent
-                    .spawn(PbrBundle {
-                        mesh: thread_2mm.clone(),
-                        material: stainless.clone(),
-                        transform: Transform::from_translation(Vec3::new(0., 0., i as f32 * 2.0)),
-                        ..Default::default()
-                    })
-                    .with(
-                        bevy_mod_picking::PickableMesh::default()
-                            .with_bounding_sphere(thread_2mm.clone()),
-                    );
-            }
-        });
+        .current_entity()
+        .unwrap();
+    commands.with_children(|parent| {
+        crate::gizmo::spawn_translate(parent, &mut meshes, &mut materials);
+        crate::gizmo::spawn_rotate(parent, &mut meshes, &mut materials);
+
+        parent
+            .spawn(PbrBundle {
+                mesh: mesh.clone(),
+                material: stainless,
+                ..Default::default()
+            })
+            .with(bevy_mod_picking::PickableMesh::default().with_bounding_sphere(mesh));
+    });
+    entity
 }
 
 fn spawn_washer(
     washer: Washer,
     commands: &mut Commands,
-    asset_server: &Res<AssetServer>,
     mut materials: &mut ResMut<Assets<StandardMaterial>>,
     mut meshes: &mut ResMut<Assets<Mesh>>,
-    mut transform: Transform,
-) {
+    transform: Transform,
+) -> Entity {
     let stainless = materials.add(StandardMaterial {
         albedo: Color::rgb(0.79, 0.8, 0.81).into(),
         ..Default::default()
     });
+    let mesh = meshes.add(crate::fastener::washer_mesh(&washer));
 
-    if let Washer::M5 = washer {
-        transform.scale = Vec3::new(1. / 3. * 5., 1. / 3. * 5., 1.);
-    }
-
-    commands
+    let entity = commands
         .spawn(WasherBundle {
             transform,
             washer,
             ..WasherBundle::default()
         })
-        .with_children(|parent| {
-            crate::gizmo::spawn_translate(parent, &mut meshes, &mut materials);
-
-            let w_mesh = asset_server.load("m3-washer.stl");
-            parent
-                .spawn(PbrBundle {
-                    mesh: w_mesh.clone(),
-                    material: stainless.clone(),
-                    ..Default::default()
-                })
-                .with(bevy_mod_picking::PickableMesh::default().with_bounding_sphere(w_mesh));
-        });
+        .current_entity()
+        .unwrap();
+    commands.with_children(|parent| {
+        crate::gizmo::spawn_translate(parent, &mut meshes, &mut materials);
+        crate::gizmo::spawn_rotate(parent, &mut meshes, &mut materials);
+
+        parent
+            .spawn(PbrBundle {
+                mesh: mesh.clone(),
+                material: stainless,
+                ..Default::default()
+            })
+            .with(bevy_mod_picking::PickableMesh::default().with_bounding_sphere(mesh));
+    });
+    entity
 }
 
 fn spawn_nut(
     nut: Nut,
     commands: &mut Commands,
-    asset_server: &Res<AssetServer>,
     mut materials: &mut ResMut<Assets<StandardMaterial>>,
     mut meshes: &mut ResMut<Assets<Mesh>>,
-    mut transform: Transform,
-) {
+    transform: Transform,
+) -> Entity {
     let stainless = materials.add(StandardMaterial {
         albedo: Color::rgb(0.79, 0.8, 0.81).into(),
         ..Default::default()
     });
+    let mesh = meshes.add(crate::fastener::nut_mesh(&nut));
 
-    if let Nut::M5 = nut {
-        transform.scale = Vec3::new(1. / 3. * 5., 1. / 3. * 5., 1.);
-    }
-
-    commands
+    let entity = commands
         .spawn(NutBundle {
             transform,
             nut,
             ..NutBundle::default()
         })
-        .with_children(|parent| {
-            crate::gizmo::spawn_translate(parent, &mut meshes, &mut materials);
-
-            let w_mesh = asset_server.load("m3-nut.stl");
-            parent
-                .spawn(PbrBundle {
-                    mesh: w_mesh.clone(),
-                    material: stainless.clone(),
-                    ..Default::default()
-                })
-                .with(bevy_mod_picking::PickableMesh::default().with_bounding_sphere(w_mesh));
+        .current_entity()
+        .unwrap();
+    commands.with_children(|parent| {
+        crate::gizmo::spawn_translate(parent, &mut meshes, &mut materials);
+        crate::gizmo::spawn_rotate(parent, &mut meshes, &mut materials);
+
+        parent
+            .spawn(PbrBundle {
+                mesh: mesh.clone(),
+                material: stainless,
+                ..Default::default()
+            })
+            .with(bevy_mod_picking::PickableMesh::default().with_bounding_sphere(mesh));
+    });
+    entity
+}
+
+fn spawn_standoff(
+    standoff: Standoff,
+    commands: &mut Commands,
+    mut materials: &mut ResMut<Assets<StandardMaterial>>,
+    mut meshes: &mut ResMut<Assets<Mesh>>,
+    transform: Transform,
+) -> Entity {
+    let material = materials.add(StandardMaterial {
+        albedo: standoff.material.color(),
+        ..Default::default()
+    });
+    let mesh = meshes.add(crate::fastener::standoff_mesh(&standoff));
+
+    let entity = commands
+        .spawn(StandoffBundle {
+            transform,
+            standoff,
+            ..StandoffBundle::default()
+        })
+        .current_entity()
+        .unwrap();
+    commands.with_children(|parent| {
+        crate::gizmo::spawn_translate(parent, &mut meshes, &mut materials);
+        crate::gizmo::spawn_rotate(parent, &mut meshes, &mut materials);
+
+        parent
+            .spawn(PbrBundle {
+                mesh: mesh.clone(),
+                material,
+                ..Default::default()
+            })
+            .with(bevy_mod_picking::PickableMesh::default().with_bounding_sphere(mesh));
+    });
+    entity
+}
+
+fn spawn_insert(
+    insert: Insert,
+    commands: &mut Commands,
+    mut materials: &mut ResMut<Assets<StandardMaterial>>,
+    mut meshes: &mut ResMut<Assets<Mesh>>,
+    transform: Transform,
+) -> Entity {
+    let brass = materials.add(StandardMaterial {
+        albedo: StandoffMaterial::Brass.color(),
+        ..Default::default()
+    });
+    let mesh = meshes.add(crate::fastener::insert_mesh(&insert));
+
+    let entity = commands
+        .spawn(InsertBundle {
+            transform,
+            insert,
+            ..InsertBundle::default()
+        })
+        .current_entity()
+        .unwrap();
+    commands.with_children(|parent| {
+        crate::gizmo::spawn_translate(parent, &mut meshes, &mut materials);
+        crate::gizmo::spawn_rotate(parent, &mut meshes, &mut materials);
+
+        parent
+            .spawn(PbrBundle {
+                mesh: mesh.clone(),
+                material: brass,
+                ..Default::default()
+            })
+            .with(bevy_mod_picking::PickableMesh::default().with_bounding_sphere(mesh));
+    });
+    entity
+}
+
+fn spawn_support(
+    support: Support,
+    commands: &mut Commands,
+    mut materials: &mut ResMut<Assets<StandardMaterial>>,
+    mut meshes: &mut ResMut<Assets<Mesh>>,
+    transform: Transform,
+) -> Entity {
+    let nylon = materials.add(StandardMaterial {
+        albedo: StandoffMaterial::Nylon.color(),
+        ..Default::default()
+    });
+    let mesh = meshes.add(crate::fastener::support_mesh(&support));
+
+    let entity = commands
+        .spawn(SupportBundle {
+            transform,
+            support,
+            ..SupportBundle::default()
+        })
+        .current_entity()
+        .unwrap();
+    commands.with_children(|parent| {
+        crate::gizmo::spawn_translate(parent, &mut meshes, &mut materials);
+        crate::gizmo::spawn_rotate(parent, &mut meshes, &mut materials);
+
+        parent
+            .spawn(PbrBundle {
+                mesh: mesh.clone(),
+                material: nylon,
+                ..Default::default()
+            })
+            .with(bevy_mod_picking::PickableMesh::default().with_bounding_sphere(mesh));
+    });
+    entity
+}
+
+/// Which side of a panel a layer is on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Side {
+    Front,
+    Back,
+}
+
+/// The kinds of layer drawn on the faces of a panel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LayerKind {
+    Copper,
+    Mask,
+    Silkscreen,
+}
+
+impl LayerKind {
+    pub const ALL: [LayerKind; 3] = [LayerKind::Copper, LayerKind::Mask, LayerKind::Silkscreen];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LayerKind::Copper => "Copper",
+            LayerKind::Mask => "Mask",
+            LayerKind::Silkscreen => "Silkscreen",
+        }
+    }
+}
+
+// Describes the side and kind of a maker-panel layer, or None if it is not
+// drawn.
+fn layer_of(layer: &maker_panel::Layer) -> Option<(Side, LayerKind)> {
+    use maker_panel::Layer;
+    match layer {
+        Layer::FrontCopper => Some((Side::Front, LayerKind::Copper)),
+        Layer::FrontMask => Some((Side::Front, LayerKind::Mask)),
+        Layer::FrontLegend => Some((Side::Front, LayerKind::Silkscreen)),
+        Layer::BackCopper => Some((Side::Back, LayerKind::Copper)),
+        Layer::BackMask => Some((Side::Back, LayerKind::Mask)),
+        Layer::BackLegend => Some((Side::Back, LayerKind::Silkscreen)),
+        _ => None,
+    }
+}
+
+/// Resource describing which layers are drawn on the faces of panels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LayerVisibility {
+    /// Indexed by side, then by the position of the kind in
+    /// `LayerKind::ALL`.
+    shown: [[bool; 3]; 2],
+}
+
+impl Default for LayerVisibility {
+    fn default() -> Self {
+        Self {
+            shown: [[true; 3]; 2],
+        }
+    }
+}
+
+impl LayerVisibility {
+    pub fn shown_mut(&mut self, side: Side, kind: LayerKind) -> &mut bool {
+        &mut self.shown[side as usize][kind as usize]
+    }
+
+    pub fn shown(&self, side: Side, kind: LayerKind) -> bool {
+        self.shown[side as usize][kind as usize]
+    }
+}
+
+/// Component present on the meshes making up a panel, describing which
+/// part of the panel they draw.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PanelFace {
+    Front,
+    Back,
+    Edge,
+}
+
+/// The meshes and textures making up a panel, along with the holes in it.
+pub(crate) struct PanelGeometry {
+    pub front: (Mesh, Texture),
+    pub back: (Mesh, Texture),
+    pub edge: Mesh,
+    pub holes: PanelHoles,
+}
+
+/// Builds the geometry of a panel with the given decorations, with the
+/// visible layers drawn on its faces.
+pub(crate) fn build_panel(
+    panel: &PanelInfo,
+    decorations: &PanelDecorations,
+    layers: &LayerVisibility,
+) -> Result<PanelGeometry, String> {
+    if let Some(err) = panel.error() {
+        return Err(match err.location {
+            Some((line, column)) => format!(
+                "invalid spec at line {}, column {}: {}",
+                line, column, err.message
+            ),
+            None => format!("invalid spec: {}", err.message),
         });
+    }
+    let mut p = panel.panel();
+    p.convex_hull(panel.convex_hull);
+
+    let (mut vertexes, indices) = p
+        .tessellate_3d()
+        .map_err(|e| format!("failed tessellating panel: {:?}", e))?;
+    set_thickness(&mut vertexes, decorations.thickness as f64);
+    let atoms = p.interior_geometry();
+    let holes = PanelHoles::new(&atoms, &vertexes);
+    let indices = indices.into_iter().map(u32::from).collect();
+    let tessellation = crate::drill::drill((vertexes, indices), &holes);
+
+    let color = &decorations.color;
+    let front = build_panel_texture(&atoms, Side::Front, color, &tessellation.0, layers);
+    let back = build_panel_texture(&atoms, Side::Back, color, &tessellation.0, layers);
+    let (front_mesh, back_mesh, edge) = build_panel_meshes(tessellation);
+    Ok(PanelGeometry {
+        front: (front_mesh, front),
+        back: (back_mesh, back),
+        edge,
+        holes,
+    })
+}
+
+// Stretches a tessellated panel along its Z axis to the given thickness,
+// keeping its bottom face where it is.
+fn set_thickness(vertexes: &mut Vec<[f64; 3]>, thickness: f64) {
+    use std::f64::MAX;
+    let (bottom, top) = vertexes
+        .iter()
+        .fold((MAX, -MAX), |(lo, hi), v| (lo.min(v[2]), hi.max(v[2])));
+    if top - bottom < 1e-6 {
+        return;
+    }
+    let scale = thickness / (top - bottom);
+    for v in vertexes.iter_mut() {
+        v[2] = bottom + (v[2] - bottom) * scale;
+    }
+}
+
+/// Spawns the faces of a panel as children of parent. Pickable faces can be
+/// clicked on to select the panel.
+pub(crate) fn spawn_panel_faces(
+    parent: &mut ChildBuilder,
+    geometry: PanelGeometry,
+    decorations: &PanelDecorations,
+    pickable: bool,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<StandardMaterial>>,
+    textures: &mut ResMut<Assets<Texture>>,
+) {
+    // The texture is opaque, so see-through materials are faded by the
+    // alpha of the material instead.
+    let (color, alpha) = (decorations.color, decorations.material.alpha());
+    let edge_material = materials.add(Color::rgba(color[0], color[1], color[2], alpha).into());
+    let visible = Visible {
+        is_transparent: alpha < 1.,
+        ..Default::default()
+    };
+    let faces = vec![
+        (PanelFace::Front, geometry.front.0, Some(geometry.front.1)),
+        (PanelFace::Back, geometry.back.0, Some(geometry.back.1)),
+        (PanelFace::Edge, geometry.edge, None),
+    ];
+
+    for (face, mesh, texture) in faces {
+        let mesh = meshes.add(mesh);
+        let material = match texture {
+            Some(texture) => materials.add(StandardMaterial {
+                albedo: Color::rgba(1., 1., 1., alpha),
+                albedo_texture: Some(textures.add(texture)),
+                ..StandardMaterial::default()
+            }),
+            None => edge_material.clone(),
+        };
+        parent
+            .spawn(PbrBundle {
+                mesh: mesh.clone(),
+                material,
+                visible: visible.clone(),
+                ..Default::default()
+            })
+            .with(face);
+        if pickable {
+            parent.with(bevy_mod_picking::PickableMesh::default().with_bounding_sphere(mesh));
+        }
+    }
+}
+
+/// Replaces the meshes and textures of the faces of a panel in place,
+/// returning the holes in the new geometry.
+pub(crate) fn update_panel_faces<'a>(
+    geometry: PanelGeometry,
+    faces: impl Iterator<
+        Item = (
+            &'a PanelFace,
+            &'a Handle<Mesh>,
+            &'a Handle<StandardMaterial>,
+        ),
+    >,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &Assets<StandardMaterial>,
+    textures: &mut ResMut<Assets<Texture>>,
+) -> PanelHoles {
+    for (face, mesh_handle, material) in faces {
+        let (mesh, texture) = match face {
+            PanelFace::Front => (&geometry.front.0, Some(&geometry.front.1)),
+            PanelFace::Back => (&geometry.back.0, Some(&geometry.back.1)),
+            PanelFace::Edge => (&geometry.edge, None),
+        };
+        if let Some(m) = meshes.get_mut(mesh_handle) {
+            *m = mesh.clone();
+        }
+        let texture_handle = materials
+            .get(material)
+            .and_then(|m| m.albedo_texture.as_ref());
+        if let (Some(texture), Some(t)) =
+            (texture, texture_handle.and_then(|t| textures.get_mut(t)))
+        {
+            *t = texture.clone();
+        }
+    }
+    geometry.holes
 }
 
 fn build_panel_texture(
-    atoms: Vec<maker_panel::features::InnerAtom>,
+    atoms: &[maker_panel::features::InnerAtom],
+    side: Side,
     color: &[f32; 3],
     vertexes: &Vec<[f64; 3]>,
+    layers: &LayerVisibility,
 ) -> Texture {
     use maker_panel::features::InnerAtom;
     use raqote::*;
@@ -370,41 +1346,68 @@ fn build_panel_texture(
         )
     };
 
-    for a in atoms {
-        match a {
-            InnerAtom::Rect { rect, layer } => {
-                if layer == maker_panel::Layer::FrontMask {
+    // Copper shows through the mask as a lighter shade of it, while mask
+    // openings expose the plated copper beneath.
+    let tint = |c: f32, copper: f32| ((c * 0.65 + copper * 0.35) * 255.).ceil() as u8;
+    let sources = [
+        (
+            LayerKind::Copper,
+            SolidSource::from_unpremultiplied_argb(
+                255,
+                tint(color[0], 0.72),
+                tint(color[1], 0.45),
+                tint(color[2], 0.2),
+            ),
+        ),
+        (
+            LayerKind::Mask,
+            SolidSource {
+                r: 151,
+                g: 149,
+                b: 152,
+                a: 0xff,
+            },
+        ),
+        (
+            LayerKind::Silkscreen,
+            SolidSource {
+                r: 240,
+                g: 240,
+                b: 240,
+                a: 0xff,
+            },
+        ),
+    ];
+
+    // Layers are drawn bottom up, so silkscreen ends up on top.
+    for (kind, source) in sources.iter() {
+        if !layers.shown(side, *kind) {
+            continue;
+        }
+
+        for atom in atoms {
+            let layer = match atom {
+                InnerAtom::Rect { layer, .. } | InnerAtom::Circle { layer, .. } => layer,
+                _ => continue,
+            };
+            if layer_of(layer) != Some((side, *kind)) {
+                continue;
+            }
+
+            let mut pb = PathBuilder::new();
+            match atom {
+                InnerAtom::Rect { rect, .. } => {
                     let (x1, y1) = rect.min().x_y();
                     let (x1, y1) = map(x1 as f32, y1 as f32);
                     let (x2, y2) = rect.max().x_y();
                     let (x2, y2) = map(x2 as f32, y2 as f32);
-
-                    let mut pb = PathBuilder::new();
                     pb.rect(x1, y1, x2 - x1, y2 - y1);
-                    dt.fill(
-                        &pb.finish(),
-                        &Source::Solid(SolidSource {
-                            r: 151,
-                            g: 149,
-                            b: 152,
-                            a: 0xff,
-                        }),
-                        &DrawOptions::new(),
-                    );
                 }
-            }
-            InnerAtom::Circle {
-                center,
-                radius,
-                layer,
-            } => {
-                if layer == maker_panel::Layer::FrontMask {
-                    let mut pb = PathBuilder::new();
-
+                InnerAtom::Circle { center, radius, .. } => {
                     for i in 0..=48 {
                         let angle = i as f32 * PI / 24.;
                         let (sin_theta, cos_theta) = angle.sin_cos();
-                        let dx = radius as f32;
+                        let dx = *radius as f32;
                         let dy = 0.;
                         let (x2, y2) = map(
                             dx * cos_theta - dy * sin_theta + center.x as f32,
@@ -417,22 +1420,10 @@ fn build_panel_texture(
                         }
                     }
                     pb.close();
-
-                    // pb.move_to(center.0, center.1);
-                    // pb.arc(center.0, center.1, size.0 / 2., 0., 2. * std::f32::consts::PI);
-                    dt.fill(
-                        &pb.finish(),
-                        &Source::Solid(SolidSource {
-                            r: 151,
-                            g: 149,
-                            b: 152,
-                            a: 0xff,
-                        }),
-                        &DrawOptions::new(),
-                    );
                 }
+                _ => continue,
             }
-            _ => {}
+            dt.fill(&pb.finish(), &Source::Solid(*source), &DrawOptions::new());
         }
     }
 
@@ -458,24 +1449,32 @@ fn build_panel_texture(
     )
 }
 
-fn build_panel_mesh(tessellation: (Vec<[f64; 3]>, Vec<u16>)) -> Mesh {
+/// Builds the meshes of the front face, back face and edges of a panel.
+/// Both faces map the panel's extent onto the whole of their texture.
+fn build_panel_meshes(tessellation: (Vec<[f64; 3]>, Vec<u32>)) -> (Mesh, Mesh, Mesh) {
     use bevy::render::{
         mesh::{Indices, VertexAttributeValues},
         pipeline::PrimitiveTopology,
     };
-    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
 
     let (verts, inds) = tessellation;
-    let v_conv = |idx: u16| {
+    let v_conv = |idx: u32| {
         let v = verts[idx as usize];
         [v[0] as f32, v[1] as f32, v[2] as f32]
     };
 
-    let mut vertexes = Vec::with_capacity(inds.len());
-    let mut normals = Vec::with_capacity(inds.len());
-    let mut indices = Vec::with_capacity(inds.len());
+    use std::f32::MAX;
+    let ((x_min, x_max), (y_min, y_max)) = verts.iter().fold(
+        ((MAX, -MAX), (MAX, -MAX)),
+        |((x_min, x_max), (y_min, y_max)), v| {
+            let (x, y) = (v[0] as f32, v[1] as f32);
+            ((x_min.min(x), x_max.max(x)), (y_min.min(y), y_max.max(y)))
+        },
+    );
 
-    for (i, tri) in inds.chunks_exact(3).enumerate() {
+    // Vertexes and normals of the front, back and edge triangles.
+    let mut faces: [(Vec<[f32; 3]>, Vec<[f32; 3]>); 3] = Default::default();
+    for tri in inds.chunks_exact(3) {
         let verts = [v_conv(tri[0]), v_conv(tri[1]), v_conv(tri[2])];
         let u = [
             verts[1][0] - verts[0][0],
@@ -493,145 +1492,407 @@ fn build_panel_mesh(tessellation: (Vec<[f64; 3]>, Vec<u16>)) -> Mesh {
             (u[0] * v[1]) - (u[1] * v[0]),
         ];
 
-        normals.push(normal);
-        normals.push(normal);
-        normals.push(normal);
-
-        for j in 0..3 {
-            vertexes.push(verts[j]);
-            indices.push((i * 3 + j) as u16);
+        let length = (normal[0].powi(2) + normal[1].powi(2) + normal[2].powi(2)).sqrt();
+        let face = if normal[2] > length * 0.5 {
+            PanelFace::Front
+        } else if normal[2] < -length * 0.5 {
+            PanelFace::Back
+        } else {
+            PanelFace::Edge
+        };
+        let (vertexes, normals) = &mut faces[face as usize];
+        for vert in verts.iter() {
+            vertexes.push(*vert);
+            normals.push(normal);
         }
     }
 
-    use std::f32::MAX;
-    let ((x_min, x_max), (y_min, y_max)) = vertexes.iter().fold(
-        ((MAX, -MAX), (MAX, -MAX)),
-        |((x_min, x_max), (y_min, y_max)), v| {
-            let (x, y) = (v[0], v[1]);
-            ((x_min.min(x), x_max.max(x)), (y_min.min(y), y_max.max(y)))
-        },
-    );
-    let uvs = vertexes
-        .iter()
-        .map(|v| {
-            [
-                (v[0] - x_min) / (x_max - x_min),
-                (v[1] - y_min) / (y_max - y_min),
-            ]
-        })
-        .collect();
+    let [front, back, edge] = faces;
+    let to_mesh = |(vertexes, normals): (Vec<[f32; 3]>, Vec<[f32; 3]>)| {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        let uvs = vertexes
+            .iter()
+            .map(|v| {
+                [
+                    (v[0] - x_min) / (x_max - x_min),
+                    (v[1] - y_min) / (y_max - y_min),
+                ]
+            })
+            .collect();
+        let indices = (0..vertexes.len() as u32).collect();
+
+        mesh.set_attribute(
+            Mesh::ATTRIBUTE_POSITION,
+            VertexAttributeValues::Float3(vertexes),
+        );
+        mesh.set_attribute(
+            Mesh::ATTRIBUTE_NORMAL,
+            VertexAttributeValues::Float3(normals),
+        );
+        mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, VertexAttributeValues::Float2(uvs));
+        mesh.set_indices(Some(Indices::U32(indices)));
+        mesh
+    };
+    (to_mesh(front), to_mesh(back), to_mesh(edge))
+}
 
-    mesh.set_attribute(
-        Mesh::ATTRIBUTE_POSITION,
-        VertexAttributeValues::Float3(vertexes),
-    );
-    mesh.set_attribute(
-        Mesh::ATTRIBUTE_NORMAL,
-        VertexAttributeValues::Float3(normals),
-    );
-    mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, VertexAttributeValues::Float2(uvs));
-    mesh.set_indices(Some(Indices::U16(indices)));
+// redraw_layers rebuilds every panel when the layers which are shown
+// change.
+fn redraw_layers(
+    layers: Res<LayerVisibility>,
+    mut last: Local<Option<LayerVisibility>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    materials: Res<Assets<StandardMaterial>>,
+    mut textures: ResMut<Assets<Texture>>,
+    panels: Query<(&PanelInfo, &PanelDecorations, &Children)>,
+    faces: Query<(&PanelFace, &Handle<Mesh>, &Handle<StandardMaterial>)>,
+) {
+    let changed = last.map(|l| l != *layers).unwrap_or(false);
+    *last = Some(*layers);
+    if !changed {
+        return;
+    }
 
-    mesh
+    for (info, decorations, children) in panels.iter() {
+        match build_panel(info, decorations, &layers) {
+            Ok(geometry) => {
+                update_panel_faces(
+                    geometry,
+                    children.iter().filter_map(|c| faces.get(*c).ok()),
+                    &mut meshes,
+                    &materials,
+                    &mut textures,
+                );
+            }
+            Err(e) => eprintln!("Failed redrawing {}: {}", info.name(), e),
+        }
+    }
+}
+
+// redecorate rebuilds the faces of panels whose decorations have been
+// edited.
+fn redecorate(
+    commands: &mut Commands,
+    layers: Res<LayerVisibility>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut textures: ResMut<Assets<Texture>>,
+    mut panels: Query<
+        (
+            Entity,
+            &PanelInfo,
+            &PanelDecorations,
+            &mut PanelHoles,
+            &Children,
+        ),
+        Mutated<PanelDecorations>,
+    >,
+    faces: Query<&PanelFace>,
+) {
+    for (entity, info, decorations, mut holes, children) in panels.iter_mut() {
+        let geometry = match build_panel(info, decorations, &layers) {
+            Ok(geometry) => geometry,
+            Err(e) => {
+                eprintln!("Failed rebuilding {}: {}", info.name(), e);
+                continue;
+            }
+        };
+
+        for child in children.iter().filter(|c| faces.get(**c).is_ok()) {
+            commands.despawn_recursive(*child);
+        }
+        *holes = geometry.holes.clone();
+        commands.set_current_entity(entity);
+        commands.with_children(|parent| {
+            spawn_panel_faces(
+                parent,
+                geometry,
+                decorations,
+                true,
+                &mut meshes,
+                &mut materials,
+                &mut textures,
+            );
+        });
+    }
 }
 
 fn spawner(
     ev_spawn: Res<Events<SpawnPartEvent>>,
     mut spawn_reader: Local<EventReader<SpawnPartEvent>>,
+    ev_scene: Res<Events<SpawnSceneEvent>>,
+    mut scene_reader: Local<EventReader<SpawnSceneEvent>>,
 
-    mut commands: &mut Commands,
-    asset_server: Res<AssetServer>,
+    commands: &mut Commands,
+    threads: Res<ModelThreads>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     mut textures: ResMut<Assets<Texture>>,
+    layers: Res<LayerVisibility>,
+    mut history: ResMut<crate::history::History>,
+    mut ev_dialog: ResMut<Events<crate::dialog_gui::DialogHotkeyEvent>>,
 ) {
+    let mut errors = vec![];
     for ev in spawn_reader.iter(&ev_spawn) {
-        match ev {
-            SpawnPartEvent::Panel(panel, convex_hull, color, transform) => {
-                let mut p = panel.panel();
-                p.convex_hull(*convex_hull);
-
-                let tessellation = p.tessellate_3d().unwrap();
-                let t = build_panel_texture(p.interior_geometry(), color, &tessellation.0);
-                let mesh = meshes.add(build_panel_mesh(tessellation));
-                let material = materials.add(StandardMaterial {
-                    albedo_texture: Some(textures.add(t)),
-                    ..StandardMaterial::default()
-                });
-
-                let transform = if let Some(t) = transform {
-                    t.clone()
-                } else {
-                    Transform::identity()
-                };
+        let rep = crate::storage::ObjectRep::from_spawn(ev);
+        match spawn_part(
+            ev,
+            commands,
+            &threads,
+            &mut meshes,
+            &mut materials,
+            &mut textures,
+            &layers,
+        ) {
+            Ok(order) => history.record(crate::history::Change::Spawn {
+                entity: order[0],
+                members: order[1..].to_vec(),
+                parent: None,
+                rep,
+                constraints: vec![],
+            }),
+            Err(e) => errors.push(format!("{}: {}", rep.name(), e)),
+        }
+    }
 
-                spawn_pcb(
-                    &mut commands,
-                    &mut materials,
-                    &mut meshes,
-                    PcbBundle::new_with_panel(
-                        panel.clone(),
-                        transform,
-                        PanelDecorations {
-                            color: color.clone(),
-                        },
-                    ),
-                    PbrBundle {
-                        mesh,
-                        material,
-                        ..Default::default()
-                    },
-                )
+    // The objects of a scene are spawned as one change, so they can be
+    // undone together.
+    for SpawnSceneEvent(objects, constraints) in scene_reader.iter(&ev_scene) {
+        let mut order = vec![];
+        let mut pending = vec![];
+        let mut changes = vec![];
+        let failed = errors.len();
+        for ev in objects {
+            let rep = crate::storage::ObjectRep::from_spawn(ev);
+            let first = order.len();
+            match spawn_tree(
+                ev,
+                commands,
+                &threads,
+                &mut meshes,
+                &mut materials,
+                &mut textures,
+                &layers,
+                &mut order,
+                &mut pending,
+            ) {
+                Ok(entity) => changes.push(crate::history::Change::Spawn {
+                    entity,
+                    members: order[first + 1..].to_vec(),
+                    parent: None,
+                    rep,
+                    constraints: vec![],
+                }),
+                Err(e) => errors.push(format!("{}: {}", rep.name(), e)),
             }
-            SpawnPartEvent::Screw(screw, length, transform) => {
-                let transform = if let Some(t) = transform {
-                    t.clone()
-                } else {
-                    Transform::from_translation(Vec3::new(0., 10., 0.))
-                };
-
-                spawn_screw(
-                    screw.clone(),
-                    &mut commands,
-                    &asset_server,
-                    &mut materials,
-                    &mut meshes,
-                    transform,
-                    *length,
-                );
+        }
+        if let Some(change) = crate::history::Change::from_changes(changes) {
+            history.record(change);
+        }
+        // Constraints refer to objects by position, so they cannot be
+        // matched up if any objects were skipped.
+        if errors.len() == failed {
+            match crate::constraints::resolve(constraints, &order) {
+                Ok(resolved) => pending.extend(resolved),
+                Err(e) => errors.push(e),
             }
-            SpawnPartEvent::Washer(washer, transform) => {
-                let transform = if let Some(t) = transform {
-                    t.clone()
-                } else {
-                    Transform::from_translation(Vec3::new(0., 10., 0.))
-                };
+        }
+        crate::constraints::insert(commands, pending);
+    }
 
-                spawn_washer(
-                    washer.clone(),
-                    &mut commands,
-                    &asset_server,
-                    &mut materials,
-                    &mut meshes,
-                    transform,
-                );
-            }
-            SpawnPartEvent::Nut(nut, transform) => {
-                let transform = if let Some(t) = transform {
-                    t.clone()
-                } else {
-                    Transform::from_translation(Vec3::new(0., 10., 0.))
-                };
+    if !errors.is_empty() {
+        ev_dialog.send(crate::dialog_gui::DialogHotkeyEvent::SpawnErrors(errors));
+    }
+}
 
-                spawn_nut(
-                    nut.clone(),
-                    &mut commands,
-                    &asset_server,
-                    &mut materials,
-                    &mut meshes,
-                    transform,
-                );
+/// Spawns the part described by the event, returning the entities spawned
+/// in depth-first order, starting with the part itself and followed by the
+/// members of sub-assemblies.
+pub(crate) fn spawn_part(
+    ev: &SpawnPartEvent,
+    commands: &mut Commands,
+    threads: &ModelThreads,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<StandardMaterial>>,
+    textures: &mut ResMut<Assets<Texture>>,
+    layers: &LayerVisibility,
+) -> Result<Vec<Entity>, String> {
+    let mut order = vec![];
+    let mut constraints = vec![];
+    spawn_tree(
+        ev,
+        commands,
+        threads,
+        meshes,
+        materials,
+        textures,
+        layers,
+        &mut order,
+        &mut constraints,
+    )?;
+    crate::constraints::insert(commands, constraints);
+    Ok(order)
+}
+
+// Spawns the part described by the event, appending the entities spawned to
+// order in depth-first order, and the constraints between members of
+// sub-assemblies to constraints. If the part cannot be built, nothing is
+// spawned and order and constraints are left as they were.
+fn spawn_tree(
+    ev: &SpawnPartEvent,
+    mut commands: &mut Commands,
+    threads: &ModelThreads,
+    mut meshes: &mut ResMut<Assets<Mesh>>,
+    mut materials: &mut ResMut<Assets<StandardMaterial>>,
+    textures: &mut ResMut<Assets<Texture>>,
+    layers: &LayerVisibility,
+    order: &mut Vec<Entity>,
+    constraints: &mut Vec<(Entity, crate::constraints::Constraint)>,
+) -> Result<Entity, String> {
+    let transform = ev.transform();
+
+    let entity = match ev {
+        SpawnPartEvent::Panel(panel, convex_hull, decorations, _) => {
+            let mut panel = panel.clone();
+            panel.convex_hull = *convex_hull;
+
+            let geometry = build_panel(&panel, decorations, layers)?;
+            spawn_pcb(
+                &mut commands,
+                &mut materials,
+                &mut meshes,
+                textures,
+                PcbBundle::new_with_panel(panel, transform, decorations.clone()),
+                geometry,
+            )
+        }
+        SpawnPartEvent::Screw(screw, length, _) => spawn_screw(
+            *screw,
+            &mut commands,
+            threads,
+            &mut materials,
+            &mut meshes,
+            transform,
+            *length,
+        ),
+        SpawnPartEvent::Washer(washer, _) => spawn_washer(
+            *washer,
+            &mut commands,
+            &mut materials,
+            &mut meshes,
+            transform,
+        ),
+        SpawnPartEvent::Nut(nut, _) => {
+            spawn_nut(*nut, &mut commands, &mut materials, &mut meshes, transform)
+        }
+        SpawnPartEvent::Standoff(standoff, _) => spawn_standoff(
+            *standoff,
+            &mut commands,
+            &mut materials,
+            &mut meshes,
+            transform,
+        ),
+        SpawnPartEvent::Insert(insert, _) => spawn_insert(
+            *insert,
+            &mut commands,
+            &mut materials,
+            &mut meshes,
+            transform,
+        ),
+        SpawnPartEvent::Support(support, _) => spawn_support(
+            *support,
+            &mut commands,
+            &mut materials,
+            &mut meshes,
+            transform,
+        ),
+        SpawnPartEvent::Group(name, source, members, group_constraints, _) => {
+            let group = crate::assembly::spawn_sub_assembly(
+                &mut commands,
+                &mut meshes,
+                &mut materials,
+                name.clone(),
+                source.clone(),
+                transform,
+            );
+
+            // If a member cannot be built, the members spawned so far are
+            // removed along with the group.
+            let first_constraint = constraints.len();
+            let mut member_order = vec![];
+            for member in members {
+                match spawn_tree(
+                    member,
+                    commands,
+                    threads,
+                    meshes,
+                    materials,
+                    textures,
+                    layers,
+                    &mut member_order,
+                    constraints,
+                ) {
+                    Ok(entity) => commands.push_children(group, &[entity]),
+                    Err(e) => {
+                        constraints.truncate(first_constraint);
+                        commands.despawn_recursive(group);
+                        return Err(format!("{}: {}", name, e));
+                    }
+                };
+            }
+            match crate::constraints::resolve(group_constraints, &member_order) {
+                Ok(resolved) => constraints.extend(resolved),
+                Err(e) => {
+                    constraints.truncate(first_constraint);
+                    commands.despawn_recursive(group);
+                    return Err(format!("{}: {}", name, e));
+                }
             }
+            order.push(group);
+            order.extend(member_order);
+            return Ok(group);
         }
+    };
+    order.push(entity);
+    Ok(entity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locates_remaining_input() {
+        let spec = "R<10>\nC<5>(h5\n";
+        // The quoted input where parsing stopped ends the spec.
+        let message = r#"Parse(Error("(h5\n", Char))"#;
+        assert_eq!(locate_error(message, spec), Some(10));
+    }
+
+    #[test]
+    fn prefers_fragment_ending_spec() {
+        let spec = "R<5>\nR<5>";
+        let message = r#"Parse("x", "R<5>")"#;
+        assert_eq!(locate_error(message, spec), Some(5));
+    }
+
+    #[test]
+    fn falls_back_to_any_fragment() {
+        let spec = "R<5>\nX<1>\nR<5>";
+        let message = r#"UnknownGeometry("X<1>")"#;
+        assert_eq!(locate_error(message, spec), Some(5));
+        assert_eq!(locate_error(r#"Unknown("Y")"#, spec), None);
+        assert_eq!(locate_error("Unquoted", spec), None);
+    }
+
+    #[test]
+    fn maps_offsets_to_lines_and_columns() {
+        let spec = "R<10>\nC<5>(h5\n";
+        assert_eq!(line_column(spec, 0), (1, 1));
+        assert_eq!(line_column(spec, 3), (1, 4));
+        assert_eq!(line_column(spec, 6), (2, 1));
+        assert_eq!(line_column(spec, 10), (2, 5));
+        // Columns count characters, not bytes.
+        assert_eq!(line_column("\u{b5}m", 2), (1, 2));
     }
 }
@@ -0,0 +1,313 @@
+use bevy::prelude::*;
+
+use crate::assembly::{to_local, to_world};
+use crate::parts::{
+    Insert, Nut, PanelHoles, Screw, ScrewLength, Standoff, Support, SupportKind, Washer,
+};
+
+pub struct Plugin;
+
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(SnapTarget::default())
+            .add_startup_system(spawn_marker.system())
+            .add_system_to_stage(stage::POST_UPDATE, update_marker.system());
+    }
+}
+
+/// Maximum distance, in mm, from a hole at which fasteners snap into it.
+const SNAP_DISTANCE: f32 = 4.;
+
+/// A panel hole which a fastener is seated in.
+#[derive(Debug, Clone, Copy)]
+pub struct Hole {
+    pub panel: Entity,
+    /// World-space center of the hole, on the surface the fastener is
+    /// seated against.
+    pub center: Vec3,
+    /// Orientation of a fastener seated in the hole.
+    pub rotation: Quat,
+    pub radius: f32,
+}
+
+/// Resource describing the hole the part being dragged has snapped to, if
+/// any.
+#[derive(Debug, Default)]
+pub struct SnapTarget(pub Option<Hole>);
+
+/// Component present on the entity which highlights the snap target, or
+/// the selected hole.
+#[derive(Debug, Default)]
+struct SnapMarker;
+
+/// Describes how a fastener fits into a hole: the radius of its shaft, and
+/// how far along its axis its seating face is from its origin.
+pub fn fastener_fit(
+    screw: Option<&Screw>,
+    length: Option<&ScrewLength>,
+    washer: Option<&Washer>,
+    nut: Option<&Nut>,
+    standoff: Option<&Standoff>,
+    insert: Option<&Insert>,
+    support: Option<&Support>,
+) -> Option<(f32, f32)> {
+    match (screw, washer, nut, standoff, insert, support) {
+        (Some(screw), ..) => {
+            let length = length.map(|l| l.0).unwrap_or_default() as f32;
+            Some((screw.size.radius(), length))
+        }
+        (_, Some(washer), ..) => Some((washer.0.radius(), 0.)),
+        (_, _, Some(nut), ..) => Some((nut.0.radius(), 0.)),
+        (_, _, _, Some(standoff), ..) => Some((standoff.size.radius(), 0.)),
+        (_, _, _, _, Some(insert), _) => {
+            let (diameter, length) = insert.dims();
+            Some((diameter / 2., length))
+        }
+        (_, _, _, _, _, Some(support)) => {
+            let radius = support.size.dims().clearance / 2.;
+            match support.kind {
+                SupportKind::Rivet => Some((radius, support.length as f32)),
+                SupportKind::PcbSupport => Some((radius, 0.)),
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Finds the hole nearest to a fastener with the given world transform,
+/// returning the transform which seats the fastener coaxially in it. A
+/// fastener pointing the same way as the panel's normal is seated on its
+/// top surface, and on its bottom surface otherwise.
+pub fn snap_to_hole<'a>(
+    transform: &Transform,
+    (radius, seat_offset): (f32, f32),
+    panels: impl Iterator<Item = (Entity, &'a GlobalTransform, &'a PanelHoles)>,
+) -> Option<(Transform, Hole)> {
+    let axis = transform.rotation * Vec3::unit_z();
+    let seat_point = transform.translation + axis * seat_offset * transform.scale.z;
+
+    let mut best: Option<(f32, Transform, Hole)> = None;
+    for (entity, global, holes) in panels {
+        let frame = Transform {
+            translation: global.translation,
+            rotation: global.rotation,
+            scale: global.scale,
+        };
+        let normal = (global.rotation * Vec3::unit_z()).normalize();
+        let top = axis.dot(normal) >= 0.;
+        let surface = if top { holes.top } else { holes.bottom };
+
+        let local = to_local(&frame, &Transform::from_translation(seat_point)).translation;
+        let depth = (local.z - surface).abs();
+        if depth > SNAP_DISTANCE {
+            continue;
+        }
+
+        for (center, hole_radius) in holes.holes.iter() {
+            if *hole_radius < radius * 0.9 {
+                continue;
+            }
+            let lateral = (Vec2::new(local.x, local.y) - *center).length();
+            let distance = lateral + depth;
+            if lateral > SNAP_DISTANCE || best.as_ref().map(|b| b.0 <= distance).unwrap_or(false) {
+                continue;
+            }
+
+            let seat = to_world(
+                &frame,
+                &Transform::from_translation(Vec3::new(center.x, center.y, surface)),
+            )
+            .translation;
+            let (rotation, seat_axis) = if top {
+                (global.rotation, normal)
+            } else {
+                (
+                    global.rotation * Quat::from_rotation_x(std::f32::consts::PI),
+                    -normal,
+                )
+            };
+
+            let mut snapped = *transform;
+            snapped.rotation = rotation;
+            snapped.translation = seat - seat_axis * seat_offset * transform.scale.z;
+            best = Some((
+                distance,
+                snapped,
+                Hole {
+                    panel: entity,
+                    center: seat,
+                    rotation,
+                    radius: *hole_radius * frame.scale.x,
+                },
+            ));
+        }
+    }
+
+    best.map(|(_, transform, hole)| (transform, hole))
+}
+
+fn spawn_marker(
+    commands: &mut Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands
+        .spawn(PbrBundle {
+            mesh: meshes.add(crate::gizmo::torus(1., 0.12)),
+            material: materials.add(Color::rgb(1.0, 0.6, 0.0).into()),
+            visible: Visible {
+                is_visible: false,
+                is_transparent: false,
+            },
+            ..Default::default()
+        })
+        .with(SnapMarker);
+}
+
+// update_marker highlights the hole being snapped to, or otherwise the
+// selected hole.
+fn update_marker(
+    target: Res<SnapTarget>,
+    selected: Res<crate::stackup::HoleSelection>,
+    mut markers: Query<(&mut Transform, &mut Visible), With<SnapMarker>>,
+) {
+    let hole = target.0.or(selected.hole);
+    for (mut transform, mut visible) in markers.iter_mut() {
+        visible.is_visible = hole.is_some();
+        if let Some(hole) = hole {
+            // Lift the marker off the surface slightly so it isnt hidden
+            // by the panel.
+            transform.translation = hole.center + hole.rotation * Vec3::new(0., 0., 0.05);
+            transform.rotation = hole.rotation;
+            transform.scale = Vec3::splat(hole.radius + 0.5);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parts::{Metric, ScrewDrive, ScrewHead};
+
+    const M3: (f32, f32) = (1.5, 0.);
+
+    // A panel with an M3 clearance hole at (5, 5) in its local frame.
+    fn panel(global: GlobalTransform, hole_radius: f32) -> (Entity, GlobalTransform, PanelHoles) {
+        (
+            Entity::new(0),
+            global,
+            PanelHoles {
+                holes: vec![(Vec2::new(5., 5.), hole_radius)],
+                bottom: 0.,
+                top: 1.6,
+            },
+        )
+    }
+
+    fn snap(
+        transform: Transform,
+        fit: (f32, f32),
+        panels: &[(Entity, GlobalTransform, PanelHoles)],
+    ) -> Option<(Transform, Hole)> {
+        snap_to_hole(
+            &transform,
+            fit,
+            panels.iter().map(|(e, global, holes)| (*e, global, holes)),
+        )
+    }
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-4
+    }
+
+    #[test]
+    fn fits_screws_by_size_and_length() {
+        let screw = Screw {
+            size: Metric::M3,
+            head: ScrewHead::Pan,
+            drive: ScrewDrive::Phillips,
+        };
+        let fit = fastener_fit(
+            Some(&screw),
+            Some(&ScrewLength(12)),
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        assert_eq!(fit, Some((Metric::M3.radius(), 12.)));
+
+        let washer = Washer(Metric::M4);
+        let fit = fastener_fit(None, None, Some(&washer), None, None, None, None);
+        assert_eq!(fit, Some((Metric::M4.radius(), 0.)));
+
+        assert_eq!(fastener_fit(None, None, None, None, None, None, None), None);
+    }
+
+    #[test]
+    fn snaps_to_holes_within_the_radius_tolerance() {
+        let near = Transform::from_translation(Vec3::new(5.5, 5., 2.));
+        // Holes down to 90% of the shaft radius are accepted, so that holes
+        // drawn slightly undersize still take the fastener.
+        let loose = [panel(GlobalTransform::identity(), 1.4)];
+        assert!(snap(near, M3, &loose).is_some());
+        let tight = [panel(GlobalTransform::identity(), 1.3)];
+        assert!(snap(near, M3, &tight).is_none());
+    }
+
+    #[test]
+    fn ignores_holes_out_of_reach() {
+        let panels = [panel(GlobalTransform::identity(), 1.6)];
+        let beside = Transform::from_translation(Vec3::new(5. + SNAP_DISTANCE + 1., 5., 1.6));
+        assert!(snap(beside, M3, &panels).is_none());
+        let above = Transform::from_translation(Vec3::new(5., 5., 1.6 + SNAP_DISTANCE + 1.));
+        assert!(snap(above, M3, &panels).is_none());
+    }
+
+    #[test]
+    fn seats_on_the_top_surface_when_pointing_with_the_normal() {
+        let panels = [panel(GlobalTransform::identity(), 1.6)];
+        let (snapped, hole) = snap(
+            Transform::from_translation(Vec3::new(5.5, 4.5, -8.)),
+            (1.5, 10.),
+            &panels,
+        )
+        .unwrap();
+        assert!(close(hole.center, Vec3::new(5., 5., 1.6)));
+        assert!(close(snapped.rotation * Vec3::unit_z(), Vec3::unit_z()));
+        // The seating face lies on the surface, the length behind the
+        // origin.
+        assert!(close(snapped.translation, Vec3::new(5., 5., -8.4)));
+    }
+
+    #[test]
+    fn seats_on_the_bottom_surface_when_pointing_against_the_normal() {
+        let panels = [panel(GlobalTransform::identity(), 1.6)];
+        let mut transform = Transform::from_translation(Vec3::new(5., 5.5, -1.));
+        transform.rotation = Quat::from_rotation_y(std::f32::consts::PI);
+        let (snapped, hole) = snap(transform, M3, &panels).unwrap();
+        assert!(close(hole.center, Vec3::new(5., 5., 0.)));
+        assert!(close(snapped.translation, hole.center));
+        assert!(close(snapped.rotation * Vec3::unit_z(), -Vec3::unit_z()));
+        assert!(close(
+            (hole.rotation * Vec3::unit_z()).normalize(),
+            -Vec3::unit_z()
+        ));
+    }
+
+    #[test]
+    fn follows_the_orientation_of_rotated_panels() {
+        let rotation = Quat::from_rotation_x(std::f32::consts::FRAC_PI_2);
+        let mut global = GlobalTransform::from_rotation(rotation);
+        global.translation = Vec3::new(0., 10., 0.);
+        let panels = [panel(global, 1.6)];
+        // The panel's normal is -y, and the hole's top is at (5, 8.4, 5).
+        let mut transform = Transform::from_translation(Vec3::new(5.5, 8., 5.));
+        transform.rotation = rotation;
+        let (snapped, hole) = snap(transform, M3, &panels).unwrap();
+        assert!(close(hole.center, Vec3::new(5., 8.4, 5.)));
+        assert!(close(snapped.translation, hole.center));
+        assert!(close(snapped.rotation * Vec3::unit_z(), -Vec3::unit_y()));
+    }
+}
@@ -0,0 +1,236 @@
+use bevy::prelude::*;
+use bevy_egui::*;
+
+use crate::inspector_gui::Library;
+use crate::parts::{
+    build_panel, spawn_panel_faces, update_panel_faces, LayerVisibility, PanelDecorations,
+    PanelFace, PanelInfo,
+};
+
+pub struct Plugin;
+
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(SpecEditor::default())
+            .add_event::<EditSpecEvent>()
+            .add_system(ui.system());
+    }
+}
+
+/// Opens the spec at the given path in the editor.
+#[derive(Debug)]
+pub struct EditSpecEvent(pub String);
+
+/// Resource holding the spec being edited, if any.
+#[derive(Default)]
+pub struct SpecEditor(Option<EditorState>);
+
+struct EditorState {
+    /// The spec as last parsed, which is written back to its path when
+    /// saved.
+    panel: PanelInfo,
+    text: String,
+    /// The contents of the file, as last read or saved.
+    saved: String,
+    /// Preview entity, the faces of which are rebuilt in place.
+    preview: Option<Entity>,
+    /// Set if the spec parsed, but could not be built.
+    build_err: Option<String>,
+}
+
+impl EditorState {
+    // Rebuilds the preview from the current spec, spawning it if needed.
+    // The last good preview is kept while the spec is invalid.
+    fn update_preview(
+        &mut self,
+        commands: &mut Commands,
+        meshes: &mut ResMut<Assets<Mesh>>,
+        materials: &mut ResMut<Assets<StandardMaterial>>,
+        textures: &mut ResMut<Assets<Texture>>,
+        layers: &LayerVisibility,
+        previews: &Query<&Children>,
+        faces: &Query<(&PanelFace, &Handle<Mesh>, &Handle<StandardMaterial>)>,
+    ) {
+        self.build_err = None;
+        if !self.panel.well_formed() {
+            return;
+        }
+        // The preview is drawn as a panel would be spawned by default.
+        let decorations = PanelDecorations::default();
+        let geometry = match build_panel(&self.panel, &decorations, layers) {
+            Ok(geometry) => geometry,
+            Err(e) => {
+                self.build_err = Some(e);
+                return;
+            }
+        };
+
+        match self.preview {
+            Some(entity) => {
+                if let Ok(children) = previews.get(entity) {
+                    update_panel_faces(
+                        geometry,
+                        children.iter().filter_map(|c| faces.get(*c).ok()),
+                        meshes,
+                        materials,
+                        textures,
+                    );
+                }
+            }
+            None => {
+                let entity = commands
+                    .spawn((Transform::default(), GlobalTransform::default()))
+                    .with_children(|parent| {
+                        spawn_panel_faces(
+                            parent,
+                            geometry,
+                            &decorations,
+                            false,
+                            meshes,
+                            materials,
+                            textures,
+                        );
+                    })
+                    .current_entity()
+                    .unwrap();
+                self.preview = Some(entity);
+            }
+        }
+    }
+}
+
+fn open(path: &str, library: &Library) -> Result<EditorState, String> {
+    let text = match std::fs::read(path) {
+        Ok(contents) => String::from_utf8_lossy(&contents).to_string(),
+        // Fall back to the spec in the library, so it can be saved again
+        // if the file has gone away.
+        Err(e) => match library.0.iter().find(|p| p.path() == path) {
+            Some(panel) => panel.spec().to_string(),
+            None => return Err(format!("Failed reading {}: {:?}", path, e)),
+        },
+    };
+
+    Ok(EditorState {
+        panel: PanelInfo::new(path.to_string(), text.clone()),
+        saved: text.clone(),
+        text,
+        preview: None,
+        build_err: None,
+    })
+}
+
+fn ui(
+    ev_edit: Res<Events<EditSpecEvent>>,
+    mut edit_reader: Local<EventReader<EditSpecEvent>>,
+    mut egui_context: ResMut<EguiContext>,
+    mut editor: ResMut<SpecEditor>,
+    mut library: ResMut<Library>,
+    commands: &mut Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut textures: ResMut<Assets<Texture>>,
+    layers: Res<LayerVisibility>,
+    previews: Query<&Children>,
+    faces: Query<(&PanelFace, &Handle<Mesh>, &Handle<StandardMaterial>)>,
+) {
+    for EditSpecEvent(path) in edit_reader.iter(&ev_edit) {
+        if let Some(entity) = editor.0.take().and_then(|s| s.preview) {
+            commands.despawn_recursive(entity);
+        }
+        match open(path, &library) {
+            Ok(mut state) => {
+                state.update_preview(
+                    commands,
+                    &mut meshes,
+                    &mut materials,
+                    &mut textures,
+                    &layers,
+                    &previews,
+                    &faces,
+                );
+                editor.0 = Some(state);
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    let state = match &mut editor.0 {
+        Some(state) => state,
+        None => return,
+    };
+
+    let ctx = &mut egui_context.ctx;
+    let mut open = true;
+    egui::Window::new(format!("Edit {}", state.panel.name()))
+        .id(egui::Id::new("spec_editor"))
+        .open(&mut open)
+        .default_width(420.)
+        .show(ctx, |ui| {
+            let response = egui::ScrollArea::from_max_height(400.).show(ui, |ui| {
+                ui.add(
+                    egui::TextEdit::multiline(&mut state.text)
+                        .text_style(egui::TextStyle::Monospace),
+                )
+            });
+            if response.changed() {
+                state.panel = state.panel.with_spec(state.text.clone());
+                state.update_preview(
+                    commands,
+                    &mut meshes,
+                    &mut materials,
+                    &mut textures,
+                    &layers,
+                    &previews,
+                    &faces,
+                );
+            }
+
+            ui.separator();
+            if !state.panel.well_formed() {
+                crate::inspector_gui::spec_error_ui(ui, &state.panel);
+            } else if let Some(e) = &state.build_err {
+                ui.colored_label(egui::Color32::RED, e.as_str());
+            } else {
+                ui.label("Spec is valid.");
+            }
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                let dirty = state.text != state.saved;
+                if ui.button(if dirty { "Save *" } else { "Save" }).clicked() {
+                    match std::fs::write(state.panel.path(), &state.text) {
+                        Ok(()) => {
+                            state.saved = state.text.clone();
+                            // Panels spawned from the spec are rebuilt once
+                            // the file watcher sees the change.
+                            let path = state.panel.path();
+                            match library.0.iter_mut().find(|p| p.path() == path) {
+                                Some(panel) => *panel = state.panel.clone(),
+                                None => library.0.push(state.panel.clone()),
+                            }
+                        }
+                        Err(e) => eprintln!("Failed writing {}: {:?}", state.panel.path(), e),
+                    }
+                }
+                if ui.button("Revert").clicked() {
+                    state.text = state.saved.clone();
+                    state.panel = state.panel.with_spec(state.text.clone());
+                    state.update_preview(
+                        commands,
+                        &mut meshes,
+                        &mut materials,
+                        &mut textures,
+                        &layers,
+                        &previews,
+                        &faces,
+                    );
+                }
+            });
+        });
+
+    if !open {
+        if let Some(entity) = editor.0.take().and_then(|s| s.preview) {
+            commands.despawn_recursive(entity);
+        }
+    }
+}
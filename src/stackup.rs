@@ -0,0 +1,391 @@
+use bevy::prelude::*;
+
+use crate::assembly::{to_local, to_world};
+use crate::inspector_gui::{SpawnPartEvent, SpawnSceneEvent};
+use crate::parts::{Metric, Nut, PanelHoles, Screw, Washer, SCREW_LENGTHS};
+use crate::snap::Hole;
+
+pub struct Plugin;
+
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(HoleSelection::default())
+            .add_event::<HoleClickedEvent>()
+            .add_event::<FastenEvent>()
+            .add_system(select_hole.system())
+            .add_system(fasten.system());
+    }
+}
+
+/// Distance, in mm, a hole in another panel may be off the axis of the
+/// selected hole and still be considered part of the same stack.
+const AXIS_TOLERANCE: f32 = 0.5;
+
+/// Largest gap, in mm, between neighbouring panels in the same stack.
+/// Panels further apart are taken to be held apart by standoffs instead.
+const MAX_GAP: f32 = 5.;
+
+/// Length, in mm, a screw should protrude past its nut.
+const PROTRUSION: f32 = 0.5;
+
+/// Resource describing the panel hole which is selected, and the outcome
+/// of the last attempt to fasten it.
+#[derive(Debug, Default)]
+pub struct HoleSelection {
+    pub hole: Option<Hole>,
+    pub report: Option<String>,
+    /// Center and radius of the hole in panel coordinates, so the hole
+    /// stays selected as the panel moves.
+    local: Option<(Vec2, f32)>,
+}
+
+/// Emitted when a panel is clicked with alt held, at the given point in
+/// world space.
+#[derive(Debug)]
+pub struct HoleClickedEvent {
+    pub panel: Entity,
+    pub point: Vec3,
+}
+
+/// Requests a fastener stack be generated for the selected hole.
+#[derive(Debug)]
+pub struct FastenEvent;
+
+fn frame(global: &GlobalTransform) -> Transform {
+    Transform {
+        translation: global.translation,
+        rotation: global.rotation,
+        scale: global.scale,
+    }
+}
+
+fn select_hole(
+    ev_clicked: Res<Events<HoleClickedEvent>>,
+    mut clicked_reader: Local<EventReader<HoleClickedEvent>>,
+    mut selection: ResMut<HoleSelection>,
+    panels: Query<(&GlobalTransform, &PanelHoles)>,
+) {
+    for ev in clicked_reader.iter(&ev_clicked) {
+        let (global, holes) = match panels.get(ev.panel) {
+            Ok(panel) => panel,
+            Err(_) => continue,
+        };
+        let local = to_local(&frame(global), &Transform::from_translation(ev.point)).translation;
+        let point = Vec2::new(local.x, local.y);
+
+        // The click must land within the hole, or on the rim around it.
+        let nearest = holes
+            .holes
+            .iter()
+            .filter(|(center, radius)| (*center - point).length() <= radius + 1.)
+            .min_by(|a, b| {
+                (a.0 - point)
+                    .length()
+                    .partial_cmp(&(b.0 - point).length())
+                    .unwrap()
+            });
+
+        selection.report = None;
+        selection.local = nearest.copied();
+        selection.hole = nearest.map(|_| Hole {
+            panel: ev.panel,
+            center: Vec3::zero(),
+            rotation: Quat::identity(),
+            radius: 0.,
+        });
+    }
+
+    // Keep the hole in step with its panel, which may have moved or been
+    // deleted.
+    let panel = selection.hole.map(|h| h.panel);
+    match (panel.and_then(|p| panels.get(p).ok()), selection.local) {
+        (Some((global, holes)), Some((center, radius))) => {
+            let seat = to_world(
+                &frame(global),
+                &Transform::from_translation(Vec3::new(center.x, center.y, holes.top)),
+            )
+            .translation;
+            if let Some(hole) = &mut selection.hole {
+                hole.center = seat;
+                hole.rotation = global.rotation;
+                hole.radius = radius * global.scale.x;
+            }
+        }
+        _ => {
+            selection.hole = None;
+            selection.local = None;
+        }
+    }
+}
+
+/// Describes a fastener stack which fits a hole.
+#[derive(Debug)]
+struct Stack {
+    screw: Screw,
+    washer: Washer,
+    nut: Nut,
+    length: usize,
+    /// Distance along the axis of the hole to the top and bottom of the
+    /// panels being fastened.
+    top: f32,
+    bottom: f32,
+}
+
+// Works out the fastener stack for a hole, given the panels which might be
+// stacked with it. The stack is made up of the panel with the hole, and
+// the panels stacked against it with holes in line.
+fn plan_stack<'a>(
+    hole: &Hole,
+    panels: impl Iterator<Item = (Entity, &'a GlobalTransform, &'a PanelHoles)>,
+) -> Result<Stack, String> {
+    // The largest size which fits the hole is used.
+    let size = match Metric::ALL
+        .iter()
+        .rev()
+        .find(|m| hole.radius >= m.radius() * 0.9)
+    {
+        Some(size) => *size,
+        None => {
+            return Err(format!(
+                "No fastener fits a hole of diameter {:.1} mm",
+                hole.radius * 2.
+            ))
+        }
+    };
+    let screw = Screw {
+        size,
+        ..Screw::default()
+    };
+    let (washer, nut, shaft) = (Washer(size), Nut(size), size.radius());
+
+    // The extent of each panel along the axis of the hole.
+    let axis = hole.rotation * Vec3::unit_z();
+    let mut layers = vec![];
+    let mut seed = None;
+    for (entity, global, holes) in panels {
+        let normal = global.rotation * Vec3::unit_z();
+        if normal.dot(axis).abs() < 0.999 {
+            continue;
+        }
+
+        // Panels are part of the stack if they have a hole the screw
+        // passes through, in line with the selected hole.
+        let local = to_local(&frame(global), &Transform::from_translation(hole.center)).translation;
+        let point = Vec2::new(local.x, local.y);
+        if !holes.holes.iter().any(|(center, radius)| {
+            (*center - point).length() <= AXIS_TOLERANCE && *radius >= shaft * 0.9
+        }) {
+            continue;
+        }
+
+        let (mut lo, mut hi) = (f32::MAX, f32::MIN);
+        for z in [holes.top, holes.bottom].iter() {
+            let surface = to_world(
+                &frame(global),
+                &Transform::from_translation(Vec3::new(local.x, local.y, *z)),
+            )
+            .translation;
+            let t = (surface - hole.center).dot(axis);
+            lo = lo.min(t);
+            hi = hi.max(t);
+        }
+        if entity == hole.panel {
+            seed = Some((lo, hi));
+        }
+        layers.push((lo, hi));
+    }
+    let (mut bottom, mut top) = match seed {
+        Some(seed) => seed,
+        None => return Err("The hole is not part of a panel".to_string()),
+    };
+
+    // The stack grows outwards from the panel with the hole, one
+    // neighbouring panel at a time.
+    loop {
+        let mut grown = false;
+        for (lo, hi) in layers.iter() {
+            let near = *lo <= top + MAX_GAP && *hi >= bottom - MAX_GAP;
+            if near && (*lo < bottom || *hi > top) {
+                bottom = bottom.min(*lo);
+                top = top.max(*hi);
+                grown = true;
+            }
+        }
+        if !grown {
+            break;
+        }
+    }
+
+    let needed = top - bottom + 2. * washer.thickness() + nut.thickness() + PROTRUSION;
+    match SCREW_LENGTHS.iter().find(|l| **l as f32 >= needed) {
+        Some(length) => Ok(Stack {
+            screw,
+            washer,
+            nut,
+            length: *length,
+            top,
+            bottom,
+        }),
+        None => Err(format!(
+            "No standard {} screw is long enough: {:.1} mm is needed, but the longest is {} mm",
+            screw,
+            needed,
+            SCREW_LENGTHS.last().unwrap()
+        )),
+    }
+}
+
+fn fasten(
+    ev_fasten: Res<Events<FastenEvent>>,
+    mut fasten_reader: Local<EventReader<FastenEvent>>,
+    mut selection: ResMut<HoleSelection>,
+    mut spawner: ResMut<Events<SpawnSceneEvent>>,
+    panels: Query<(Entity, &GlobalTransform, &PanelHoles)>,
+) {
+    for _ in fasten_reader.iter(&ev_fasten) {
+        let hole = match selection.hole {
+            Some(hole) => hole,
+            None => continue,
+        };
+        let stack = match plan_stack(&hole, panels.iter()) {
+            Ok(stack) => stack,
+            Err(e) => {
+                selection.report = Some(e);
+                continue;
+            }
+        };
+
+        // The screw goes in from the top, with a washer either side and the
+        // nut at the bottom. Parts below the stack face downwards.
+        let axis = hole.rotation * Vec3::unit_z();
+        let up = hole.rotation;
+        let down = hole.rotation * Quat::from_rotation_x(std::f32::consts::PI);
+        let at = |t: f32, rotation: Quat| Transform {
+            translation: hole.center + axis * t,
+            rotation,
+            ..Transform::default()
+        };
+        let (w, head) = (
+            stack.washer.thickness(),
+            stack.top + stack.washer.thickness(),
+        );
+
+        selection.report = Some(format!(
+            "Added {}x{} screw, 2 washers and a nut",
+            stack.screw, stack.length
+        ));
+        spawner.send(SpawnSceneEvent(
+            vec![
+                SpawnPartEvent::Washer(stack.washer, Some(at(stack.top, up))),
+                SpawnPartEvent::Screw(
+                    stack.screw,
+                    stack.length,
+                    Some(at(head - stack.length as f32, up)),
+                ),
+                SpawnPartEvent::Washer(stack.washer, Some(at(stack.bottom, down))),
+                SpawnPartEvent::Nut(stack.nut, Some(at(stack.bottom - w, down))),
+            ],
+            vec![],
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THICKNESS: f32 = 1.6;
+
+    // A panel with its bottom face at the given height, and an M3
+    // clearance hole at (5, 5).
+    fn panel(z: f32) -> (GlobalTransform, PanelHoles) {
+        (
+            GlobalTransform::from_translation(Vec3::new(0., 0., z)),
+            PanelHoles {
+                holes: vec![(Vec2::new(5., 5.), 1.6)],
+                bottom: 0.,
+                top: THICKNESS,
+            },
+        )
+    }
+
+    fn hole() -> Hole {
+        Hole {
+            panel: Entity::new(0),
+            center: Vec3::new(5., 5., THICKNESS),
+            rotation: Quat::identity(),
+            radius: 1.6,
+        }
+    }
+
+    fn plan(panels: &[(GlobalTransform, PanelHoles)]) -> Result<Stack, String> {
+        plan_stack(
+            &hole(),
+            panels
+                .iter()
+                .enumerate()
+                .map(|(i, (global, holes))| (Entity::new(i as u32), global, holes)),
+        )
+    }
+
+    // Returns the shortest standard screw which fastens panels of the
+    // given total thickness.
+    fn expected_length(thickness: f32) -> usize {
+        let needed = thickness
+            + 2. * Washer(Metric::M3).thickness()
+            + Nut(Metric::M3).thickness()
+            + PROTRUSION;
+        *SCREW_LENGTHS.iter().find(|l| **l as f32 >= needed).unwrap()
+    }
+
+    #[test]
+    fn fastens_one_panel() {
+        let stack = plan(&[panel(0.)]).unwrap();
+        assert_eq!(stack.screw.size, Metric::M3);
+        assert!(stack.top.abs() < 1e-5);
+        assert!((stack.bottom + THICKNESS).abs() < 1e-5);
+        assert_eq!(stack.length, expected_length(THICKNESS));
+    }
+
+    #[test]
+    fn fastens_stacked_panels() {
+        let stack = plan(&[panel(0.), panel(-THICKNESS - 2.)]).unwrap();
+        let thickness = 2. * THICKNESS + 2.;
+        assert!((stack.bottom + thickness).abs() < 1e-5);
+        assert_eq!(stack.length, expected_length(thickness));
+    }
+
+    #[test]
+    fn ignores_panels_which_are_not_parallel() {
+        let (_, holes) = panel(0.);
+        let tilted = GlobalTransform {
+            translation: Vec3::new(0., 0., -2.),
+            rotation: Quat::from_rotation_x(std::f32::consts::FRAC_PI_2),
+            scale: Vec3::one(),
+        };
+        let stack = plan(&[panel(0.), (tilted, holes)]).unwrap();
+        assert!((stack.bottom + THICKNESS).abs() < 1e-5);
+    }
+
+    #[test]
+    fn ignores_panels_which_are_far_away() {
+        let stack = plan(&[panel(0.), panel(-50.)]).unwrap();
+        assert!((stack.bottom + THICKNESS).abs() < 1e-5);
+    }
+
+    #[test]
+    fn fails_when_no_screw_is_long_enough() {
+        // Panels 1mm apart, which together are thicker than the longest
+        // screw.
+        let panels: Vec<_> = (0..30)
+            .map(|i| panel(-(THICKNESS + 1.) * i as f32))
+            .collect();
+        let err = plan(&panels).unwrap_err();
+        assert!(err.contains("long enough"), "{}", err);
+    }
+
+    #[test]
+    fn fails_without_the_panel_of_the_hole() {
+        assert!(plan(&[]).is_err());
+    }
+}
@@ -2,8 +2,15 @@ use bevy::{input::keyboard::KeyboardInput, prelude::*, render::camera::Camera};
 use bevy_mod_picking::*;
 use serde::{Deserialize, Serialize};
 
+use crate::assembly::SubAssembly;
+use crate::constraints::{Constraint, ConstraintRep, Constraints};
+use crate::inspector_gui::SpawnPartEvent;
 use crate::interaction::Selectable;
-use crate::parts::{Nut, PanelDecorations, PanelInfo, Pcb, Screw, ScrewLength, Washer};
+use crate::parts::{
+    Insert, Metric, Nut, PanelDecorations, PanelInfo, PanelMaterial, Pcb, Screw, ScrewDrive,
+    ScrewHead, ScrewLength, Standoff, StandoffGender, StandoffMaterial, Support, SupportKind,
+    Washer,
+};
 
 pub struct Plugin;
 
@@ -16,55 +23,545 @@ impl bevy::prelude::Plugin for Plugin {
 
 pub enum StorageEvent {
     Save,
+    /// Exports a bill of materials for the assembly.
+    ExportBom,
+}
+
+/// Components which together describe the state of an object, in the form
+/// needed to construct an ObjectRep.
+pub(crate) type ObjectComponents<'a> = (
+    &'a Transform,
+    Option<&'a Screw>,
+    Option<&'a ScrewLength>,
+    Option<&'a Washer>,
+    Option<&'a Nut>,
+    Option<&'a Standoff>,
+    Option<&'a Insert>,
+    Option<&'a Support>,
+    Option<&'a PanelInfo>,
+    Option<&'a PanelDecorations>,
+    Option<&'a SubAssembly>,
+    Option<&'a Constraints>,
+);
+
+/// Describes the object an entity represents, including the members of
+/// sub-assemblies.
+pub(crate) fn capture(
+    entity: Entity,
+    q: &Query<ObjectComponents, With<Selectable>>,
+    children: &Query<&Children>,
+) -> ObjectRep {
+    capture_tree(entity, q, children, &mut vec![])
+}
+
+/// Describes an object which is about to be despawned, so it can be
+/// respawned later. Along with the object, this returns the entities it is
+/// made up of in depth-first order, and the constraints they hold which are
+/// not part of the description because they involve the object itself or
+/// parts outside it.
+pub(crate) fn capture_detached(
+    entity: Entity,
+    q: &Query<ObjectComponents, With<Selectable>>,
+    children: &Query<&Children>,
+) -> (ObjectRep, Vec<Entity>, Vec<(Entity, Constraint)>) {
+    let mut order = vec![];
+    let rep = capture_tree(entity, q, children, &mut order);
+
+    let members = order.get(1..).unwrap_or(&[]);
+    let mut external = vec![];
+    for part in order.iter() {
+        if let Ok((.., Some(c))) = q.get(*part) {
+            for constraint in c.0.iter() {
+                if *part == entity || !members.contains(&constraint.target) {
+                    external.push((*part, constraint.clone()));
+                }
+            }
+        }
+    }
+    (rep, order, external)
+}
+
+/// Returns the sub-assembly component of an object, if it is one.
+pub(crate) fn sub_assembly<'a>(obj: &ObjectComponents<'a>) -> Option<&'a SubAssembly> {
+    let (.., sub_assembly, _) = obj;
+    *sub_assembly
+}
+
+// Captures an object, appending the entities it is made up of to order in
+// depth-first order.
+fn capture_tree(
+    entity: Entity,
+    q: &Query<ObjectComponents, With<Selectable>>,
+    children: &Query<&Children>,
+    order: &mut Vec<Entity>,
+) -> ObjectRep {
+    let mut rep: ObjectRep = match q.get(entity) {
+        Ok(obj) => obj.into(),
+        Err(_) => return ObjectRep::None,
+    };
+    if matches!(rep, ObjectRep::None) {
+        return rep;
+    }
+    order.push(entity);
+
+    if let ObjectRep::Group {
+        objects,
+        constraints,
+        ..
+    } = &mut rep
+    {
+        if let Ok(members) = children.get(entity) {
+            let (o, c) = capture_scope(members.iter().copied(), q, children, order);
+            *objects = o;
+            *constraints = c;
+        }
+    }
+    rep
+}
+
+/// Captures a list of objects, along with the constraints between them.
+/// Constraints between members of a sub-assembly are saved with the
+/// sub-assembly instead, so they stay with it when it is moved to another
+/// file.
+fn capture_scope(
+    entities: impl Iterator<Item = Entity>,
+    q: &Query<ObjectComponents, With<Selectable>>,
+    children: &Query<&Children>,
+    order: &mut Vec<Entity>,
+) -> (Vec<ObjectRep>, Vec<ConstraintRep>) {
+    let start = order.len();
+    let mut objects = vec![];
+    // Ranges of order covering the members of each sub-assembly.
+    let mut nested = vec![];
+    for entity in entities {
+        let first = order.len();
+        let rep = capture_tree(entity, q, children, order);
+        match rep {
+            ObjectRep::None => continue,
+            ObjectRep::Group { .. } => nested.push(first + 1 - start..order.len() - start),
+            _ => {}
+        }
+        objects.push(rep);
+    }
+
+    let scope = &order[start..];
+    let mut constraints = vec![];
+    for (part, entity) in scope.iter().enumerate() {
+        if let Ok((.., Some(c))) = q.get(*entity) {
+            for constraint in c.0.iter() {
+                let target = match scope.iter().position(|e| *e == constraint.target) {
+                    Some(target) => target,
+                    None => continue,
+                };
+                if nested
+                    .iter()
+                    .any(|r| r.contains(&part) && r.contains(&target))
+                {
+                    continue;
+                }
+                constraints.push(ConstraintRep {
+                    part,
+                    target,
+                    kind: constraint.kind.clone(),
+                });
+            }
+        }
+    }
+    (objects, constraints)
 }
 
 fn saver(
     ev_action: Res<Events<StorageEvent>>,
     mut action_reader: Local<EventReader<StorageEvent>>,
 
-    q: Query<
-        (
-            &Transform,
-            Option<&Screw>,
-            Option<&ScrewLength>,
-            Option<&Washer>,
-            Option<&Nut>,
-            Option<&PanelInfo>,
-            Option<&PanelDecorations>,
-        ),
-        With<Selectable>,
-    >,
+    q: Query<ObjectComponents, With<Selectable>>,
+    children: Query<&Children>,
+    top_level: Query<Entity, (With<Selectable>, Without<Parent>)>,
     mut ev_storage: ResMut<Events<crate::dialog_gui::DialogHotkeyEvent>>,
 ) {
     for ev in action_reader.iter(&ev_action) {
         match ev {
             StorageEvent::Save => {
-                let objs: Vec<serde_json::Value> = q
-                    .iter()
-                    .map(|obj| {
-                        let rep: ObjectRep = obj.into();
-                        rep
-                    })
-                    .filter(|rep| !matches!(rep, ObjectRep::None))
-                    .map(|rep| serde_json::to_value(&rep).unwrap())
-                    .collect();
+                // Members of sub-assemblies are nested within them.
+                let (objects, constraints) =
+                    capture_scope(top_level.iter(), &q, &children, &mut vec![]);
 
-                let obj = serde_json::Value::Array(objs);
-                ev_storage.send(crate::dialog_gui::DialogHotkeyEvent::SaveScene(obj));
+                let doc = Document {
+                    header: Header::default(),
+                    objects,
+                    constraints,
+                };
+                ev_storage.send(crate::dialog_gui::DialogHotkeyEvent::SaveScene(
+                    serde_json::to_value(&doc).unwrap(),
+                ));
+            }
+            StorageEvent::ExportBom => {
+                let (objects, _) = capture_scope(top_level.iter(), &q, &children, &mut vec![]);
+                ev_storage.send(crate::dialog_gui::DialogHotkeyEvent::ExportBom(
+                    crate::bom::Bom::from_objects(&objects),
+                ));
             }
         }
     }
 }
 
-pub(crate) fn decode_scene(json: &Vec<u8>) -> Vec<ObjectRep> {
-    let decode: Result<Vec<ObjectRep>, _> = serde_json::from_slice(json);
-    if let Ok(objs) = decode {
-        objs
-    } else {
-        vec![]
+/// Version of the .mpa document format written by this build. Files
+/// written before the format was versioned are a bare array of untagged
+/// objects, and are treated as version 0. Version 2 added sub-assemblies,
+/// constraints, panel materials, screw heads and drives, and standoffs,
+/// inserts and supports.
+pub(crate) const FORMAT_VERSION: u32 = 2;
+
+/// Describes the contents of an .mpa document.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct Header {
+    pub format_version: u32,
+    pub units: String,
+    pub app_version: String,
+}
+
+impl Default for Header {
+    fn default() -> Self {
+        Self {
+            format_version: FORMAT_VERSION,
+            units: "mm".to_string(),
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+        }
     }
 }
 
+/// Top-level structure of an .mpa file.
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct Document {
+    pub header: Header,
+    pub objects: Vec<ObjectRep>,
+    /// Constraints between the objects.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub constraints: Vec<ConstraintRep>,
+}
+
+/// Describes an object in an .mpa file which could not be decoded.
+#[derive(Debug, Clone)]
+pub(crate) struct ObjectError {
+    /// Position of the object, or of the constraint if field is
+    /// "constraint". Problems within a group are counted against the
+    /// top-level object.
+    pub index: usize,
+    /// Where the object is in the file, such as objects[3].objects[1].
+    pub path: String,
+    pub field: Option<String>,
+    pub reason: String,
+}
+
+/// The outcome of decoding an .mpa file. Objects which could not be decoded
+/// are omitted from the document, and described in errors.
+#[derive(Debug)]
+pub(crate) struct LoadResult {
+    pub document: Document,
+    /// True if the file was written in an older format version, and
+    /// should be written back once it has loaded cleanly.
+    pub upgraded: bool,
+    pub errors: Vec<ObjectError>,
+}
+
+/// Decodes an .mpa file, upgrading it to the current format if it was
+/// written by an older version. An error is only returned if the file
+/// as a whole is unreadable: malformed objects are reported in the result.
+pub(crate) fn decode_scene(json: &[u8]) -> Result<LoadResult, serde_json::Error> {
+    use serde::de::Error;
+
+    match serde_json::from_slice(json)? {
+        serde_json::Value::Array(objs) => {
+            let (objects, errors) = decode_objects::<LegacyObjectRep>(objs, "objects");
+            Ok(LoadResult {
+                document: Document {
+                    header: Header::default(),
+                    objects,
+                    constraints: vec![],
+                },
+                upgraded: true,
+                errors,
+            })
+        }
+        serde_json::Value::Object(mut doc) => {
+            let header: Header = serde_json::from_value(
+                doc.remove("header")
+                    .ok_or_else(|| serde_json::Error::missing_field("header"))?,
+            )?;
+            if header.format_version > FORMAT_VERSION {
+                return Err(serde_json::Error::custom(format!(
+                    "file is from a newer version of the app (format version {}, this build supports up to {})",
+                    header.format_version, FORMAT_VERSION
+                )));
+            }
+            let objs = match doc.remove("objects") {
+                Some(serde_json::Value::Array(objs)) => objs,
+                Some(_) => return Err(serde_json::Error::custom("objects must be an array")),
+                None => return Err(serde_json::Error::missing_field("objects")),
+            };
+
+            let (objects, errors) = decode_objects::<ObjectRep>(objs, "objects");
+
+            let (constraints, errors) = match doc.remove("constraints") {
+                Some(serde_json::Value::Array(constraints)) => {
+                    decode_constraints(constraints, errors, "constraints")
+                }
+                Some(_) => return Err(serde_json::Error::custom("constraints must be an array")),
+                None => (vec![], errors),
+            };
+            Ok(LoadResult {
+                upgraded: header.format_version < FORMAT_VERSION,
+                document: Document {
+                    header: Header {
+                        format_version: FORMAT_VERSION,
+                        ..header
+                    },
+                    objects,
+                    constraints,
+                },
+                errors,
+            })
+        }
+        _ => Err(serde_json::Error::custom(
+            "expected an assembly document or an array of objects",
+        )),
+    }
+}
+
+/// Maximum depth of nested links between files, which guards against
+/// files which link to themselves.
+const MAX_LINK_DEPTH: usize = 8;
+
+/// Reads the objects of a linked sub-assembly from its source file, along
+/// with the constraints between them. Problems which didnt prevent it
+/// loading, such as objects which were skipped, are added to problems.
+pub(crate) fn load_linked(
+    path: &str,
+    problems: &mut Vec<String>,
+) -> Result<(Vec<ObjectRep>, Vec<ConstraintRep>), String> {
+    read_linked(path, 0, problems)
+}
+
+fn read_linked(
+    path: &str,
+    depth: usize,
+    problems: &mut Vec<String>,
+) -> Result<(Vec<ObjectRep>, Vec<ConstraintRep>), String> {
+    if depth >= MAX_LINK_DEPTH {
+        return Err("sub-assemblies are linked too deeply".to_string());
+    }
+    let contents = std::fs::read(path).map_err(|e| format!("failed reading file: {}", e))?;
+    let mut result =
+        decode_scene(&contents).map_err(|e| format!("failed decoding assembly: {}", e))?;
+    if !result.errors.is_empty() {
+        problems.push(format!(
+            "{} objects in {} could not be loaded",
+            result.errors.len(),
+            path
+        ));
+    }
+
+    resolve_links_within(&mut result.document.objects, depth + 1, problems);
+    Ok((result.document.objects, result.document.constraints))
+}
+
+/// Replaces the objects of any linked sub-assemblies with the current
+/// contents of their source files. If a source cannot be read, the
+/// objects saved with the link are kept. Returns descriptions of any
+/// links which could not be fully loaded.
+pub(crate) fn resolve_links(objects: &mut [ObjectRep]) -> Vec<String> {
+    let mut problems = vec![];
+    resolve_links_within(objects, 0, &mut problems);
+    problems
+}
+
+/// Removes the links from sub-assemblies, so they keep the objects they
+/// currently contain.
+pub(crate) fn unlink(objects: &mut [ObjectRep]) {
+    for obj in objects.iter_mut() {
+        if let ObjectRep::Group {
+            source, objects, ..
+        } = obj
+        {
+            *source = None;
+            unlink(objects);
+        }
+    }
+}
+
+fn resolve_links_within(objects: &mut [ObjectRep], depth: usize, problems: &mut Vec<String>) {
+    for obj in objects.iter_mut() {
+        if let ObjectRep::Group {
+            source,
+            objects,
+            constraints,
+            ..
+        } = obj
+        {
+            match source {
+                Some(path) => match read_linked(path, depth, problems) {
+                    Ok(linked) => {
+                        *objects = linked.0;
+                        *constraints = linked.1;
+                    }
+                    Err(e) => problems.push(format!(
+                        "failed loading linked sub-assembly {}: {}",
+                        path, e
+                    )),
+                },
+                None => resolve_links_within(objects, depth, problems),
+            }
+        }
+    }
+}
+
+// decode_objects decodes each object on its own, so one bad object doesnt
+// prevent the rest being loaded. The members of groups are decoded the same
+// way, so one bad member doesnt discard the whole group. Objects are
+// described in errors by their path from prefix, such as
+// objects[3].objects[1].
+fn decode_objects<T>(
+    objs: Vec<serde_json::Value>,
+    prefix: &str,
+) -> (Vec<ObjectRep>, Vec<ObjectError>)
+where
+    T: serde::de::DeserializeOwned + Into<ObjectRep>,
+{
+    let mut objects = Vec::with_capacity(objs.len());
+    let mut errors = Vec::new();
+
+    for (index, mut obj) in objs.into_iter().enumerate() {
+        let path = format!("{}[{}]", prefix, index);
+        let is_group = obj.get("kind").and_then(|k| k.as_str()) == Some("group");
+        let mut take = |key: &str| match obj.get_mut(key) {
+            Some(value) if is_group && value.is_array() => {
+                Some(std::mem::replace(value, serde_json::Value::Array(vec![])))
+            }
+            _ => None,
+        };
+        let (members, constraints) = (take("objects"), take("constraints"));
+
+        let mut rep: ObjectRep = match serde_json::from_value::<T>(obj.clone()) {
+            Ok(o) => o.into(),
+            Err(e) => {
+                errors.push(ObjectError {
+                    index,
+                    path,
+                    field: error_field::<T>(&obj, &e),
+                    reason: e.to_string(),
+                });
+                continue;
+            }
+        };
+
+        if let ObjectRep::Group {
+            objects: group_objects,
+            constraints: group_constraints,
+            ..
+        } = &mut rep
+        {
+            let (decoded, member_errors) = match members {
+                Some(serde_json::Value::Array(members)) => {
+                    decode_objects::<ObjectRep>(members, &format!("{}.objects", path))
+                }
+                _ => (vec![], vec![]),
+            };
+            let (decoded_constraints, member_errors) = match constraints {
+                Some(serde_json::Value::Array(constraints)) => {
+                    decode_constraints(constraints, member_errors, &format!("{}.constraints", path))
+                }
+                _ => (vec![], member_errors),
+            };
+            *group_objects = decoded;
+            *group_constraints = decoded_constraints;
+            // Errors within a group are counted against the top-level
+            // object they belong to.
+            errors.extend(
+                member_errors
+                    .into_iter()
+                    .map(|e| ObjectError { index, ..e }),
+            );
+        }
+        objects.push(rep);
+    }
+
+    (objects, errors)
+}
+
+// decode_constraints decodes each constraint on its own, so one bad
+// constraint doesnt prevent the rest being loaded. Constraints which
+// cannot be decoded are reported in errors, as are all the constraints if
+// any objects were skipped: they refer to objects by position, so cannot
+// be matched up.
+fn decode_constraints(
+    constraints: Vec<serde_json::Value>,
+    mut errors: Vec<ObjectError>,
+    prefix: &str,
+) -> (Vec<ConstraintRep>, Vec<ObjectError>) {
+    let objects_skipped = !errors.is_empty();
+    let mut out = Vec::with_capacity(constraints.len());
+
+    for (index, c) in constraints.into_iter().enumerate() {
+        let path = format!("{}[{}]", prefix, index);
+        if objects_skipped {
+            errors.push(ObjectError {
+                index,
+                path,
+                field: Some("constraint".to_string()),
+                reason: "not all objects could be loaded".to_string(),
+            });
+            continue;
+        }
+        match serde_json::from_value::<ConstraintRep>(c) {
+            Ok(c) => out.push(c),
+            Err(e) => errors.push(ObjectError {
+                index,
+                path,
+                field: Some("constraint".to_string()),
+                reason: e.to_string(),
+            }),
+        }
+    }
+
+    (out, errors)
+}
+
+/// Works out which field of an object caused a decoding error. Serde only
+/// names the field for missing fields, so for other errors each field is
+/// removed in turn: the culprit is the one whose removal results in it
+/// being reported as missing instead.
+fn error_field<T: serde::de::DeserializeOwned>(
+    obj: &serde_json::Value,
+    err: &serde_json::Error,
+) -> Option<String> {
+    let missing_field = |e: &serde_json::Error| {
+        let msg = e.to_string();
+        if msg.starts_with("missing field") {
+            msg.split('`').nth(1).map(|f| f.to_string())
+        } else {
+            None
+        }
+    };
+
+    if let Some(field) = missing_field(err) {
+        return Some(field);
+    }
+    if let serde_json::Value::Object(fields) = obj {
+        // The tag of a tagged object is reported missing whatever the
+        // problem is, so it is never the culprit found this way.
+        for key in fields.keys().filter(|k| *k != "kind") {
+            let mut without = fields.clone();
+            without.remove(key);
+            if let Err(e) = serde_json::from_value::<T>(serde_json::Value::Object(without)) {
+                if missing_field(&e).as_ref() == Some(key) {
+                    return Some(key.clone());
+                }
+            }
+        }
+    }
+    None
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub(crate) struct Pos {
     x: f32,
@@ -94,12 +591,19 @@ impl Into<Transform> for Pos {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(untagged)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
 pub(crate) enum ObjectRep {
     Screw {
         pos: Pos,
-        screw: Screw,
+        /// Thread size of the screw. Files saved before screws had a head
+        /// and drive get the defaults.
+        #[serde(rename = "screw")]
+        size: Metric,
+        #[serde(default)]
+        head: ScrewHead,
+        #[serde(default)]
+        drive: ScrewDrive,
         length: usize,
     },
     Nut {
@@ -110,76 +614,590 @@ pub(crate) enum ObjectRep {
         pos: Pos,
         washer: Washer,
     },
+    Standoff {
+        pos: Pos,
+        size: Metric,
+        length: usize,
+        gender: StandoffGender,
+        material: StandoffMaterial,
+    },
+    Insert {
+        pos: Pos,
+        insert: Insert,
+    },
+    Support {
+        pos: Pos,
+        size: Metric,
+        length: usize,
+        /// Saved as "support", as "kind" is taken by the tag.
+        #[serde(rename = "support")]
+        kind: SupportKind,
+    },
     Panel {
         pos: Pos,
         path: String,
         spec: String,
         convex_hull: bool,
         color: [f32; 3],
+        #[serde(default)]
+        material: PanelMaterial,
+        /// Thickness of the panel in mm. Files saved before panels had a
+        /// thickness get the default.
+        #[serde(default = "default_thickness")]
+        thickness: f32,
     },
+    Group {
+        pos: Pos,
+        name: String,
+        /// Path of the .mpa file the group is linked to. The objects are
+        /// still saved, so the group can be loaded if the source is missing.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        source: Option<String>,
+        objects: Vec<ObjectRep>,
+        /// Constraints between the objects.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        constraints: Vec<ConstraintRep>,
+    },
+    #[serde(skip)]
     None,
 }
 
-impl
-    From<(
-        &Transform,
-        Option<&Screw>,
-        Option<&ScrewLength>,
-        Option<&Washer>,
-        Option<&Nut>,
-        Option<&PanelInfo>,
-        Option<&PanelDecorations>,
-    )> for ObjectRep
-{
-    fn from(
-        info: (
-            &Transform,
-            Option<&Screw>,
-            Option<&ScrewLength>,
-            Option<&Washer>,
-            Option<&Nut>,
-            Option<&PanelInfo>,
-            Option<&PanelDecorations>,
-        ),
-    ) -> Self {
-        let (transform, screw, length, washer, nut, panel, panel_dec) = info;
+/// Object representation used by unversioned (version 0) files, where
+/// the kind of object was inferred from the fields present.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum LegacyObjectRep {
+    Screw {
+        pos: Pos,
+        screw: Metric,
+        length: usize,
+    },
+    Nut {
+        pos: Pos,
+        nut: Nut,
+    },
+    Washer {
+        pos: Pos,
+        washer: Washer,
+    },
+    Panel {
+        pos: Pos,
+        path: String,
+        spec: String,
+        convex_hull: bool,
+        color: [f32; 3],
+    },
+}
+
+fn default_thickness() -> f32 {
+    PanelDecorations::default().thickness
+}
+
+impl ObjectRep {
+    /// Describes the event which would spawn this object.
+    pub(crate) fn into_spawn(self) -> Option<SpawnPartEvent> {
+        match self {
+            ObjectRep::Panel {
+                path,
+                spec,
+                convex_hull,
+                pos,
+                color,
+                material,
+                thickness,
+            } => Some(SpawnPartEvent::Panel(
+                PanelInfo::new(path, spec),
+                convex_hull,
+                PanelDecorations {
+                    color,
+                    material,
+                    thickness,
+                },
+                Some(pos.into()),
+            )),
+            ObjectRep::Screw {
+                pos,
+                size,
+                head,
+                drive,
+                length,
+            } => Some(SpawnPartEvent::Screw(
+                Screw { size, head, drive },
+                length,
+                Some(pos.into()),
+            )),
+            ObjectRep::Washer { pos, washer } => {
+                Some(SpawnPartEvent::Washer(washer, Some(pos.into())))
+            }
+            ObjectRep::Nut { pos, nut } => Some(SpawnPartEvent::Nut(nut, Some(pos.into()))),
+            ObjectRep::Standoff {
+                pos,
+                size,
+                length,
+                gender,
+                material,
+            } => Some(SpawnPartEvent::Standoff(
+                Standoff {
+                    size,
+                    length,
+                    gender,
+                    material,
+                },
+                Some(pos.into()),
+            )),
+            ObjectRep::Insert { pos, insert } => {
+                Some(SpawnPartEvent::Insert(insert, Some(pos.into())))
+            }
+            ObjectRep::Support {
+                pos,
+                size,
+                length,
+                kind,
+            } => Some(SpawnPartEvent::Support(
+                Support { size, length, kind },
+                Some(pos.into()),
+            )),
+            ObjectRep::Group {
+                pos,
+                name,
+                source,
+                objects,
+                constraints,
+            } => Some(SpawnPartEvent::Group(
+                name,
+                source,
+                objects.into_iter().filter_map(|o| o.into_spawn()).collect(),
+                constraints,
+                Some(pos.into()),
+            )),
+            ObjectRep::None => None,
+        }
+    }
+
+    /// Describes the object which would be spawned by the given event.
+    pub(crate) fn from_spawn(ev: &SpawnPartEvent) -> Self {
+        let pos = (&ev.transform()).into();
+        match ev {
+            SpawnPartEvent::Panel(panel, convex_hull, decorations, _) => {
+                let (path, spec, _) = panel.clone().split();
+                ObjectRep::Panel {
+                    pos,
+                    path,
+                    spec,
+                    convex_hull: *convex_hull,
+                    color: decorations.color,
+                    material: decorations.material,
+                    thickness: decorations.thickness,
+                }
+            }
+            SpawnPartEvent::Screw(screw, length, _) => ObjectRep::Screw {
+                pos,
+                size: screw.size,
+                head: screw.head,
+                drive: screw.drive,
+                length: *length,
+            },
+            SpawnPartEvent::Washer(washer, _) => ObjectRep::Washer {
+                pos,
+                washer: *washer,
+            },
+            SpawnPartEvent::Nut(nut, _) => ObjectRep::Nut { pos, nut: *nut },
+            SpawnPartEvent::Standoff(standoff, _) => ObjectRep::Standoff {
+                pos,
+                size: standoff.size,
+                length: standoff.length,
+                gender: standoff.gender,
+                material: standoff.material,
+            },
+            SpawnPartEvent::Insert(insert, _) => ObjectRep::Insert {
+                pos,
+                insert: *insert,
+            },
+            SpawnPartEvent::Support(support, _) => ObjectRep::Support {
+                pos,
+                size: support.size,
+                length: support.length,
+                kind: support.kind,
+            },
+            SpawnPartEvent::Group(name, source, members, constraints, _) => ObjectRep::Group {
+                pos,
+                name: name.clone(),
+                source: source.clone(),
+                objects: members.iter().map(ObjectRep::from_spawn).collect(),
+                constraints: constraints.clone(),
+            },
+        }
+    }
+
+    /// Returns a short human-readable description of the object.
+    pub(crate) fn name(&self) -> String {
+        match self {
+            ObjectRep::Panel { path, .. } => path.split("/").last().unwrap().to_string(),
+            ObjectRep::Screw {
+                size, head, length, ..
+            } => format!("{}x{} {} screw", size, length, head.name()),
+            ObjectRep::Washer { washer, .. } => format!("{} washer", washer),
+            ObjectRep::Nut { nut, .. } => format!("{} nut", nut),
+            ObjectRep::Standoff {
+                size,
+                length,
+                gender,
+                ..
+            } => format!("{}x{} {} standoff", size, length, gender.name()),
+            ObjectRep::Insert { insert, .. } => format!("{} heat-set insert", insert),
+            ObjectRep::Support {
+                size, length, kind, ..
+            } => Support {
+                size: *size,
+                length: *length,
+                kind: *kind,
+            }
+            .to_string(),
+            ObjectRep::Group { name, objects, .. } => format!("{} ({} parts)", name, objects.len()),
+            ObjectRep::None => "<none>".to_string(),
+        }
+    }
+}
+
+impl From<LegacyObjectRep> for ObjectRep {
+    fn from(legacy: LegacyObjectRep) -> Self {
+        match legacy {
+            LegacyObjectRep::Screw { pos, screw, length } => ObjectRep::Screw {
+                pos,
+                size: screw,
+                head: ScrewHead::default(),
+                drive: ScrewDrive::default(),
+                length,
+            },
+            LegacyObjectRep::Nut { pos, nut } => ObjectRep::Nut { pos, nut },
+            LegacyObjectRep::Washer { pos, washer } => ObjectRep::Washer { pos, washer },
+            LegacyObjectRep::Panel {
+                pos,
+                path,
+                spec,
+                convex_hull,
+                color,
+            } => ObjectRep::Panel {
+                pos,
+                path,
+                spec,
+                convex_hull,
+                color,
+                material: PanelMaterial::default(),
+                thickness: default_thickness(),
+            },
+        }
+    }
+}
+
+impl From<ObjectComponents<'_>> for ObjectRep {
+    fn from(info: ObjectComponents) -> Self {
+        let (
+            transform,
+            screw,
+            length,
+            washer,
+            nut,
+            standoff,
+            insert,
+            support,
+            panel,
+            panel_dec,
+            sub_assembly,
+            _,
+        ) = info;
         if let Some(screw) = screw {
             return ObjectRep::Screw {
                 pos: transform.into(),
-                screw: screw.clone(),
+                size: screw.size,
+                head: screw.head,
+                drive: screw.drive,
                 length: length.unwrap().0,
             };
         }
         if let Some(nut) = nut {
             return ObjectRep::Nut {
                 pos: transform.into(),
-                nut: nut.clone(),
+                nut: *nut,
             };
         }
         if let Some(washer) = washer {
             return ObjectRep::Washer {
                 pos: transform.into(),
-                washer: washer.clone(),
+                washer: *washer,
+            };
+        }
+        if let Some(standoff) = standoff {
+            return ObjectRep::Standoff {
+                pos: transform.into(),
+                size: standoff.size,
+                length: standoff.length,
+                gender: standoff.gender,
+                material: standoff.material,
+            };
+        }
+        if let Some(insert) = insert {
+            return ObjectRep::Insert {
+                pos: transform.into(),
+                insert: *insert,
+            };
+        }
+        if let Some(support) = support {
+            return ObjectRep::Support {
+                pos: transform.into(),
+                size: support.size,
+                length: support.length,
+                kind: support.kind,
             };
         }
         if let Some(panel) = panel {
             let (path, spec, convex_hull) = panel.clone().split();
 
-            let color = if let Some(panel_dec) = panel_dec {
-                panel_dec.color
-            } else {
-                [0.2, 0.5, 0.2]
-            };
+            let decorations = panel_dec.cloned().unwrap_or_default();
 
             return ObjectRep::Panel {
                 path,
                 spec,
                 convex_hull,
-                color,
+                color: decorations.color,
+                material: decorations.material,
+                thickness: decorations.thickness,
+                pos: transform.into(),
+            };
+        }
+        if let Some(sub_assembly) = sub_assembly {
+            // Members and constraints are filled in by capture().
+            return ObjectRep::Group {
                 pos: transform.into(),
+                name: sub_assembly.name.clone(),
+                source: sub_assembly.source.clone(),
+                objects: vec![],
+                constraints: vec![],
             };
         }
 
         ObjectRep::None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const POS: &str = r#"{"x": 1.0, "y": 2.0, "z": 3.0, "quat": [0.0, 0.0, 0.0, 1.0]}"#;
+
+    fn decode(json: String) -> LoadResult {
+        decode_scene(json.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn migrates_legacy_array() {
+        let result = decode(format!(
+            r#"[
+                {{"pos": {pos}, "screw": "M3", "length": 10}},
+                {{"pos": {pos}, "nut": "M5"}},
+                {{"pos": {pos}, "washer": "M2.5"}},
+                {{"pos": {pos}, "path": "a/b.spec", "spec": "R<5>", "convex_hull": false, "color": [1.0, 1.0, 1.0]}}
+            ]"#,
+            pos = POS
+        ));
+
+        assert!(result.upgraded);
+        assert!(result.errors.is_empty());
+        assert_eq!(result.document.header.format_version, FORMAT_VERSION);
+        let objects = &result.document.objects;
+        assert_eq!(objects.len(), 4);
+        assert!(matches!(
+            objects[0],
+            ObjectRep::Screw {
+                size: Metric::M3,
+                head: ScrewHead::Pan,
+                length: 10,
+                ..
+            }
+        ));
+        assert!(matches!(
+            objects[1],
+            ObjectRep::Nut {
+                nut: Nut(Metric::M5),
+                ..
+            }
+        ));
+        assert!(matches!(
+            objects[2],
+            ObjectRep::Washer {
+                washer: Washer(Metric::M2_5),
+                ..
+            }
+        ));
+        match &objects[3] {
+            ObjectRep::Panel {
+                path, thickness, ..
+            } => {
+                assert_eq!(path, "a/b.spec");
+                assert_eq!(*thickness, default_thickness());
+            }
+            obj => panic!("expected a panel, got {:?}", obj),
+        }
+    }
+
+    #[test]
+    fn legacy_array_reports_bad_objects() {
+        let result = decode(format!(
+            r#"[{{"pos": {pos}, "nut": "M5"}}, {{"pos": {pos}, "bolt": "M3"}}]"#,
+            pos = POS
+        ));
+
+        assert_eq!(result.document.objects.len(), 1);
+        assert_eq!(result.errors.len(), 1);
+        assert_eq!(result.errors[0].index, 1);
+    }
+
+    #[test]
+    fn reports_each_bad_object() {
+        let result = decode(format!(
+            r#"{{
+                "header": {{"format_version": 2, "units": "mm", "app_version": "0.2.0"}},
+                "objects": [
+                    {{"kind": "nut", "pos": {pos}, "nut": "M5"}},
+                    {{"kind": "screw", "pos": {pos}, "screw": "M7", "length": 10}},
+                    {{"kind": "washer", "pos": {pos}, "washer": "M3"}},
+                    {{"kind": "nut", "pos": {pos}}}
+                ],
+                "constraints": [{{"part": 0, "target": 1, "type": "offset", "distance": 1.0}}]
+            }}"#,
+            pos = POS
+        ));
+
+        assert!(!result.upgraded);
+        assert_eq!(result.document.objects.len(), 2);
+        let errors: Vec<_> = result
+            .errors
+            .iter()
+            .map(|e| (e.index, e.field.as_deref()))
+            .collect();
+        // Constraints are dropped, as the objects they refer to moved.
+        assert_eq!(
+            errors,
+            vec![
+                (1, Some("screw")),
+                (3, Some("nut")),
+                (0, Some("constraint"))
+            ]
+        );
+        assert!(result.document.constraints.is_empty());
+    }
+
+    #[test]
+    fn reports_bad_group_members_by_path() {
+        let result = decode(format!(
+            r#"{{
+                "header": {{"format_version": 2, "units": "mm", "app_version": "0.2.0"}},
+                "objects": [
+                    {{"kind": "nut", "pos": {pos}, "nut": "M5"}},
+                    {{
+                        "kind": "group", "pos": {pos}, "name": "Feet",
+                        "objects": [
+                            {{"kind": "nut", "pos": {pos}, "nut": "M3"}},
+                            {{"kind": "washer", "pos": {pos}, "washer": "M9"}},
+                            {{
+                                "kind": "group", "pos": {pos}, "name": "Inner",
+                                "objects": [{{"kind": "nut", "pos": {pos}}}]
+                            }}
+                        ],
+                        "constraints": [{{"part": 0, "target": 1, "type": "offset", "distance": 1.0}}]
+                    }}
+                ]
+            }}"#,
+            pos = POS
+        ));
+
+        // The group is kept, with the members which could be decoded.
+        assert_eq!(result.document.objects.len(), 2);
+        match &result.document.objects[1] {
+            ObjectRep::Group {
+                objects,
+                constraints,
+                ..
+            } => {
+                assert_eq!(objects.len(), 2);
+                assert!(constraints.is_empty());
+                assert!(
+                    matches!(&objects[1], ObjectRep::Group { objects, .. } if objects.is_empty())
+                );
+            }
+            obj => panic!("expected a group, got {:?}", obj),
+        }
+
+        let errors: Vec<_> = result
+            .errors
+            .iter()
+            .map(|e| (e.index, e.path.as_str(), e.field.as_deref()))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (1, "objects[1].objects[1]", Some("washer")),
+                (1, "objects[1].objects[2].objects[0]", Some("nut")),
+                (1, "objects[1].constraints[0]", Some("constraint")),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_each_bad_constraint() {
+        let result = decode(format!(
+            r#"{{
+                "header": {{"format_version": 2, "units": "mm", "app_version": "0.2.0"}},
+                "objects": [
+                    {{"kind": "nut", "pos": {pos}, "nut": "M5"}},
+                    {{"kind": "washer", "pos": {pos}, "washer": "M5"}}
+                ],
+                "constraints": [
+                    {{"part": 1, "target": 0, "type": "glued"}},
+                    {{"part": 1, "target": 0, "type": "offset", "distance": 1.0}}
+                ]
+            }}"#,
+            pos = POS
+        ));
+
+        assert_eq!(result.document.objects.len(), 2);
+        assert_eq!(result.document.constraints.len(), 1);
+        assert_eq!(result.errors.len(), 1);
+        assert_eq!(result.errors[0].index, 0);
+    }
+
+    #[test]
+    fn rejects_newer_format() {
+        let json = r#"{"header": {"format_version": 99, "units": "mm", "app_version": "9.0.0"}, "objects": []}"#;
+        let err = decode_scene(json.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("newer version"), "{}", err);
+    }
+
+    #[test]
+    fn error_field_names_culprit() {
+        let pos: serde_json::Value = serde_json::from_str(POS).unwrap();
+        let check = |obj: serde_json::Value| {
+            let err = serde_json::from_value::<ObjectRep>(obj.clone()).unwrap_err();
+            error_field::<ObjectRep>(&obj, &err)
+        };
+
+        // Missing fields are named by serde.
+        assert_eq!(
+            check(json!({"kind": "screw", "pos": pos, "screw": "M3"})).as_deref(),
+            Some("length")
+        );
+        // Invalid values are found by removing each field in turn.
+        assert_eq!(
+            check(json!({"kind": "screw", "pos": pos, "screw": "M3", "length": "long"})).as_deref(),
+            Some("length")
+        );
+        assert_eq!(
+            check(json!({"kind": "washer", "pos": pos, "washer": "M9"})).as_deref(),
+            Some("washer")
+        );
+        // An unknown kind isnt blamed on any field.
+        assert_eq!(check(json!({"kind": "bolt", "pos": pos})), None);
+    }
+}
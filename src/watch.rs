@@ -0,0 +1,139 @@
+use bevy::prelude::*;
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use crate::inspector_gui::Library;
+use crate::parts::{
+    build_panel, update_panel_faces, LayerVisibility, PanelDecorations, PanelFace, PanelHoles,
+    PanelInfo,
+};
+
+pub struct Plugin;
+
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(SpecWatcher::default())
+            .add_system(watch_specs.system());
+    }
+}
+
+/// How often, in seconds, spec files are checked for changes.
+const POLL_INTERVAL: f32 = 0.5;
+
+/// Resource tracking when each spec file in use was last modified, so
+/// panels can be rebuilt when the file is edited.
+pub struct SpecWatcher {
+    timer: Timer,
+    modified: HashMap<String, SystemTime>,
+}
+
+impl Default for SpecWatcher {
+    fn default() -> Self {
+        Self {
+            timer: Timer::from_seconds(POLL_INTERVAL, true),
+            modified: HashMap::new(),
+        }
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// watch_specs re-reads spec files which have changed, updating the library
+// and rebuilding the panels spawned from them in place.
+fn watch_specs(
+    time: Res<Time>,
+    mut watcher: ResMut<SpecWatcher>,
+    mut library: ResMut<Library>,
+    layers: Res<LayerVisibility>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    materials: Res<Assets<StandardMaterial>>,
+    mut textures: ResMut<Assets<Texture>>,
+    mut panels: Query<(
+        &mut PanelInfo,
+        &PanelDecorations,
+        &mut PanelHoles,
+        &Children,
+    )>,
+    faces: Query<(&PanelFace, &Handle<Mesh>, &Handle<StandardMaterial>)>,
+    mut ev_dialog: ResMut<Events<crate::dialog_gui::DialogHotkeyEvent>>,
+) {
+    if !watcher.timer.tick(time.delta_seconds()).just_finished() {
+        return;
+    }
+
+    // Specs in the library and those used by panels in the assembly are
+    // watched, as panels may have been loaded from an assembly.
+    let mut paths: Vec<String> = library.0.iter().map(|p| p.path().to_string()).collect();
+    paths.extend(panels.iter_mut().map(|(p, ..)| p.path().to_string()));
+    paths.sort();
+    paths.dedup();
+
+    let mut changed = vec![];
+    for path in paths {
+        // Files which have gone away keep the last spec read from them.
+        let time = match modified(&path) {
+            Some(time) => time,
+            None => continue,
+        };
+        match watcher.modified.insert(path.clone(), time) {
+            Some(previous) if previous != time => changed.push(path),
+            _ => {}
+        }
+    }
+
+    let mut errors = vec![];
+    for path in changed {
+        let data = match std::fs::read(&path) {
+            Ok(data) => String::from_utf8_lossy(&data).to_string(),
+            Err(e) => {
+                errors.push(format!("Failed reading {}: {}", path, e));
+                continue;
+            }
+        };
+        for panel in library.0.iter_mut().filter(|p| p.path() == path) {
+            *panel = panel.with_spec(data.clone());
+        }
+
+        // Panels are left as they were if the new spec is invalid. The
+        // problem is shown on the library entry for the spec, which is
+        // added if the panels were loaded from an assembly.
+        let spec = PanelInfo::new(path.clone(), data.clone());
+        if !spec.well_formed() {
+            if !library.0.iter().any(|p| p.path() == path) {
+                library.0.push(spec);
+            }
+            continue;
+        }
+        for (mut info, decorations, mut holes, children) in panels.iter_mut() {
+            if info.path() != path {
+                continue;
+            }
+            let reloaded = info.with_spec(data.clone());
+            let geometry = match build_panel(&reloaded, decorations, &layers) {
+                Ok(geometry) => geometry,
+                Err(e) => {
+                    let error = format!("Not reloading {}: {}", path, e);
+                    if !errors.contains(&error) {
+                        errors.push(error);
+                    }
+                    continue;
+                }
+            };
+
+            *holes = update_panel_faces(
+                geometry,
+                children.iter().filter_map(|c| faces.get(*c).ok()),
+                &mut meshes,
+                &materials,
+                &mut textures,
+            );
+            *info = reloaded;
+        }
+    }
+
+    if !errors.is_empty() {
+        ev_dialog.send(crate::dialog_gui::DialogHotkeyEvent::SpawnErrors(errors));
+    }
+}